@@ -1,28 +1,31 @@
 #![recursion_limit = "256"]
 
 pub mod filter;
-mod pw;
 pub mod tui;
 
 use std::num::NonZero;
 
 use anyhow::Context;
-use pw_util::module::{BiquadCoefficients, FILTER_PREFIX, MANAGED_PROP};
+use pw_util::backend::PwBackend;
+use pw_util::graph::Graph;
+use pw_util::module::{self, AudioPosition, BiquadCoefficients, Direction, MANAGED_PROP};
+use pw_util::routing::{self, Stream};
 use tabled::Tabled;
-use tokio::process::Command;
 
 #[derive(Tabled)]
 pub struct EqMeta {
     id: u32,
     name: String,
+    class: String,
+    target: String,
 }
 
-pub async fn list_eqs() -> anyhow::Result<Vec<EqMeta>> {
-    let objects = pw_util::dump().await?;
+pub async fn list_eqs(backend: &dyn PwBackend) -> anyhow::Result<Vec<EqMeta>> {
+    let objects = backend.dump().await?;
 
     let eqs = objects
-        .into_iter()
-        .filter(is_managed_eq)
+        .iter()
+        .filter(|obj| is_managed_eq(obj))
         .filter(|obj| matches!(obj.object_type, pw_util::PwObjectType::Node))
         .map(|obj| {
             let props = &obj.info.props;
@@ -30,9 +33,15 @@ pub async fn list_eqs() -> anyhow::Result<Vec<EqMeta>> {
                 .get("media.name")
                 .and_then(|v| v.as_str())
                 .unwrap_or("Unknown");
+            let class = props
+                .get("media.class")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
             EqMeta {
                 id: obj.id,
                 name: name.to_string(),
+                class: class.to_string(),
+                target: eq_target(&objects, obj).unwrap_or("default").to_string(),
             }
         })
         .collect();
@@ -40,6 +49,60 @@ pub async fn list_eqs() -> anyhow::Result<Vec<EqMeta>> {
     Ok(eqs)
 }
 
+/// The device an EQ is bound to. The `target.object` is set on the other stream of the
+/// filter-chain, which shares the EQ node's `node.link-group`.
+fn eq_target<'a>(
+    objects: &'a [pw_util::PwDumpObject],
+    eq: &pw_util::PwDumpObject,
+) -> Option<&'a str> {
+    let link_group = eq.info.props.get("node.link-group")?;
+    objects
+        .iter()
+        .filter(|obj| obj.id != eq.id && obj.info.props.get("node.link-group") == Some(link_group))
+        .find_map(|obj| obj.info.props.get("target.object")?.as_str())
+}
+
+/// A device an EQ can be bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub id: u32,
+    pub name: String,
+    pub description: String,
+}
+
+/// List the sinks (or sources) that are not pw-eq EQs
+pub async fn list_devices(
+    backend: &dyn PwBackend,
+    direction: Direction,
+) -> anyhow::Result<Vec<Device>> {
+    let objects = backend.dump().await?;
+
+    let devices = objects
+        .into_iter()
+        .filter(|obj| matches!(obj.object_type, pw_util::PwObjectType::Node))
+        .filter(|obj| !is_managed_eq(obj))
+        .filter(|obj| {
+            obj.info.props.get("media.class").and_then(|v| v.as_str())
+                == Some(direction.media_class())
+        })
+        .filter_map(|obj| {
+            let props = &obj.info.props;
+            let name = props.get("node.name")?.as_str()?;
+            let description = props
+                .get("node.description")
+                .and_then(|v| v.as_str())
+                .unwrap_or(name);
+            Some(Device {
+                id: obj.id,
+                name: name.to_string(),
+                description: description.to_string(),
+            })
+        })
+        .collect();
+
+    Ok(devices)
+}
+
 pub fn is_managed_eq(props: &pw_util::PwDumpObject) -> bool {
     props
         .info
@@ -49,8 +112,19 @@ pub fn is_managed_eq(props: &pw_util::PwDumpObject) -> bool {
 }
 
 /// Find an EQ node by profile name or ID
-pub async fn find_eq_node(profile: &str) -> anyhow::Result<pw_util::PwDumpObject> {
-    let objects = pw_util::dump().await?;
+pub async fn find_eq_node(
+    backend: &dyn PwBackend,
+    profile: &str,
+) -> anyhow::Result<pw_util::PwDumpObject> {
+    find_eq_node_where(backend, profile, |_| true).await
+}
+
+async fn find_eq_node_where(
+    backend: &dyn PwBackend,
+    profile: &str,
+    predicate: impl Fn(&pw_util::PwDumpObject) -> bool,
+) -> anyhow::Result<pw_util::PwDumpObject> {
+    let objects = backend.dump().await?;
 
     // Try to parse as ID first
     let target_id: Option<u32> = profile.parse().ok();
@@ -59,6 +133,7 @@ pub async fn find_eq_node(profile: &str) -> anyhow::Result<pw_util::PwDumpObject
         .into_iter()
         .filter(|obj| matches!(obj.object_type, pw_util::PwObjectType::Node))
         .filter(is_managed_eq)
+        .filter(predicate)
         .find(|obj| {
             if let Some(target_id) = target_id {
                 obj.id == target_id
@@ -74,12 +149,225 @@ pub async fn find_eq_node(profile: &str) -> anyhow::Result<pw_util::PwDumpObject
         .ok_or_else(|| anyhow::anyhow!("EQ '{profile}' not found"))
 }
 
-pub async fn use_eq(profile: &str) -> anyhow::Result<u32> {
-    let node = find_eq_node(profile).await?;
-    pw_util::set_default(node.id).await?;
+/// Set a sink EQ as the default sink, or a source EQ as the default source
+pub async fn use_eq(
+    backend: &dyn PwBackend,
+    profile: &str,
+    direction: Direction,
+) -> anyhow::Result<u32> {
+    let node = find_eq_node_where(backend, profile, |obj| {
+        obj.info.props.get("media.class").and_then(|v| v.as_str()) == Some(direction.media_class())
+    })
+    .await?;
+    backend.set_default(node.id).await?;
     Ok(node.id)
 }
 
+/// Move every stream of an application onto the EQ for its direction, including streams pinned
+/// to a device. Returns the streams as they were before.
+pub async fn route_app(
+    backend: &dyn PwBackend,
+    app: &str,
+    profile: &str,
+) -> anyhow::Result<Vec<Stream>> {
+    let streams = app_streams(backend, app).await?;
+    for stream in &streams {
+        let direction = stream.direction;
+        let eq = find_eq_node_where(backend, profile, |obj| {
+            obj.info.props.get("media.class").and_then(|v| v.as_str())
+                == Some(direction.media_class())
+        })
+        .await?;
+        routing::route_stream(backend, stream, eq.id).await?;
+    }
+    Ok(streams)
+}
+
+/// Put an application's routed streams back where they were before they were routed: on the
+/// device they were pinned to, or following the default. Returns the streams that were put back.
+pub async fn unroute_app(backend: &dyn PwBackend, app: &str) -> anyhow::Result<Vec<Stream>> {
+    let streams = app_streams(backend, app)
+        .await?
+        .into_iter()
+        .filter(|stream| stream.origin.is_some())
+        .collect::<Vec<_>>();
+    for stream in &streams {
+        routing::restore_stream(backend, stream).await?;
+    }
+    Ok(streams)
+}
+
+async fn app_streams(backend: &dyn PwBackend, app: &str) -> anyhow::Result<Vec<Stream>> {
+    let streams = routing::list_streams(backend)
+        .await?
+        .into_iter()
+        .filter(|stream| stream.matches_app(app))
+        .collect::<Vec<_>>();
+    anyhow::ensure!(!streams.is_empty(), "no streams from '{app}'");
+    Ok(streams)
+}
+
+/// What becomes the default instead of an EQ that is the default now
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Replacement {
+    /// The device the EQ is bound to with `target.object`
+    Target(Device),
+    /// The device the EQ plays into, or records from
+    Linked(Device),
+    /// No device, so the configured default is cleared and the session manager picks one
+    Cleared,
+}
+
+/// What deleting a profile's live EQs involves
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeletePlan {
+    /// What replaces each EQ that is the default now
+    pub defaults: Vec<(Direction, Replacement)>,
+    /// The filter-chain modules that created the EQs, destroyed to unload them with their nodes
+    pub modules: Vec<u32>,
+    /// The nodes of filter-chains whose module isn't known, destroyed one by one
+    pub nodes: Vec<u32>,
+}
+
+/// Work out what to change to take a profile's EQs (sink and source) out of the graph. An EQ
+/// that is the default is replaced by the device it is bound to, or else the device it is
+/// linked to. With neither, the default is left to the session manager.
+pub async fn plan_delete(backend: &dyn PwBackend, profile: &str) -> anyhow::Result<DeletePlan> {
+    let objects = backend.dump().await?;
+    let graph = Graph::from_objects(&objects);
+
+    let mut plan = DeletePlan::default();
+    let eqs = graph.nodes.values().filter_map(|node| {
+        let obj = objects.iter().find(|obj| obj.id == node.id)?;
+        let is_profile = obj.prop("media.name").and_then(|v| v.as_str()) == Some(profile);
+        (node.managed && is_profile).then_some((node, obj))
+    });
+    for (eq, obj) in eqs {
+        match owning_module(&objects, obj) {
+            Some(module) if !plan.modules.contains(&module) => plan.modules.push(module),
+            Some(_) => {}
+            None => plan.nodes.extend(graph.group(eq.id)),
+        }
+
+        let direction = match eq.media_class.as_deref() {
+            Some(class) if class == Direction::Source.media_class() => Direction::Source,
+            _ => Direction::Sink,
+        };
+        let is_default = backend
+            .get_default(direction)
+            .await?
+            .is_some_and(|default| default.id == Some(eq.id));
+        if !is_default {
+            continue;
+        }
+
+        let devices = list_devices(backend, direction).await?;
+        // `target.object` is a node name, or an object.serial when set by hand
+        let serial = |id| {
+            let obj = objects.iter().find(|obj| obj.id == id)?;
+            Some(obj.prop("object.serial")?.as_u64()?.to_string())
+        };
+        let target = eq_target(&objects, obj).and_then(|target| {
+            devices.iter().find(|device| {
+                device.name == target || serial(device.id).as_deref() == Some(target)
+            })
+        });
+        let behind = match direction {
+            Direction::Sink => graph.external_outputs(eq.id),
+            Direction::Source => graph.external_inputs(eq.id),
+        };
+        let linked = devices.iter().find(|device| behind.contains(&device.id));
+        let replacement = match (target, linked) {
+            (Some(device), _) => Replacement::Target(device.clone()),
+            (None, Some(device)) => Replacement::Linked(device.clone()),
+            (None, None) => Replacement::Cleared,
+        };
+        plan.defaults.push((direction, replacement));
+    }
+    Ok(plan)
+}
+
+/// The filter-chain module global a node was created by. A module loaded inside a client has no
+/// global, so its `module.id` must name a filter-chain module in the graph.
+fn owning_module(objects: &[pw_util::PwDumpObject], node: &pw_util::PwDumpObject) -> Option<u32> {
+    let id = u32::try_from(node.prop("module.id")?.as_u64()?).ok()?;
+    objects
+        .iter()
+        .any(|obj| {
+            obj.id == id
+                && obj.object_type == pw_util::PwObjectType::Module
+                && obj.prop("module.name").and_then(|name| name.as_str())
+                    == Some(module::FILTER_CHAIN_MODULE)
+        })
+        .then_some(id)
+}
+
+/// Switch the defaults away from a profile's EQs, then unload their modules
+pub async fn delete_eq(backend: &dyn PwBackend, plan: &DeletePlan) -> anyhow::Result<()> {
+    for (direction, replacement) in &plan.defaults {
+        match replacement {
+            Replacement::Target(device) | Replacement::Linked(device) => {
+                backend.set_default(device.id).await?;
+            }
+            Replacement::Cleared => backend.clear_default(*direction).await?,
+        }
+    }
+    for &id in plan.modules.iter().chain(&plan.nodes) {
+        backend.destroy(id).await?;
+    }
+    Ok(())
+}
+
+/// The signal chain through the default sink and out of each EQ
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChainStatus {
+    /// Name of the default sink, if there is one
+    pub default_sink: Option<String>,
+    /// Every path into the default sink, e.g. `Firefox -> pweq.focal`
+    pub into_default: Vec<String>,
+    /// Every path out of an EQ, e.g. `pweq.focal -> alsa_output.usb-...`
+    pub from_eqs: Vec<String>,
+    /// EQs that are not in the path of any audio
+    pub warnings: Vec<String>,
+}
+
+/// Trace what feeds the default sink and where each EQ's output goes
+pub async fn chain_status(backend: &dyn PwBackend) -> anyhow::Result<ChainStatus> {
+    let objects = backend.dump().await?;
+    let graph = Graph::from_objects(&objects);
+    let default = backend.get_default(Direction::Sink).await?;
+
+    let mut status = ChainStatus::default();
+    if let Some(default) = default {
+        if let Some(id) = default.id {
+            status.into_default = graph
+                .chains_into(id)
+                .iter()
+                .map(|chain| graph.format_chain(chain))
+                .collect();
+        }
+        status.default_sink = Some(default.name);
+    }
+
+    for eq in graph.nodes.values().filter(|node| node.managed) {
+        status.from_eqs.extend(
+            graph
+                .chains_from(eq.id)
+                .iter()
+                .map(|chain| graph.format_chain(chain)),
+        );
+        if graph.external_inputs(eq.id).is_empty() {
+            status.warnings.push(format!("{} has no inputs", eq.label));
+        }
+        if graph.external_outputs(eq.id).is_empty() {
+            status
+                .warnings
+                .push(format!("{} has no output link", eq.label));
+        }
+    }
+    Ok(status)
+}
+
 #[derive(Debug, Clone)]
 pub struct UpdateFilter {
     pub frequency: Option<f64>,
@@ -88,104 +376,470 @@ pub struct UpdateFilter {
     pub coeffs: Option<BiquadCoefficients>,
 }
 
+/// Address of a filter node, optionally scoped to a channel of a per-channel EQ (e.g. `L:3`)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FilterId {
+    pub channel: Option<AudioPosition>,
+    pub band: Band,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum FilterId {
+pub enum Band {
     Preamp,
     Index(NonZero<usize>),
 }
 
-impl std::fmt::Display for FilterId {
+impl FilterId {
+    pub fn preamp() -> Self {
+        Band::Preamp.into()
+    }
+
+    pub fn index(idx: NonZero<usize>) -> Self {
+        Band::Index(idx).into()
+    }
+
+    pub fn on_channel(self, channel: AudioPosition) -> Self {
+        Self {
+            channel: Some(channel),
+            ..self
+        }
+    }
+
+    /// Name of the filter-chain node this filter is implemented by
+    pub fn node_name(&self) -> String {
+        module::filter_node_name(self.channel, self.band)
+    }
+
+    pub fn from_node_name(name: &str) -> anyhow::Result<Self> {
+        let (channel, band) = module::parse_filter_node_name(name)
+            .with_context(|| format!("not a pw-eq filter node: {name}"))?;
+        Ok(Self {
+            channel,
+            band: band.parse()?,
+        })
+    }
+}
+
+impl From<Band> for FilterId {
+    fn from(band: Band) -> Self {
+        Self {
+            channel: None,
+            band,
+        }
+    }
+}
+
+impl std::fmt::Display for Band {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            FilterId::Preamp => write!(f, "preamp"),
-            FilterId::Index(idx) => write!(f, "{idx}"),
+            Band::Preamp => write!(f, "preamp"),
+            Band::Index(idx) => write!(f, "{idx}"),
         }
     }
 }
 
-impl std::str::FromStr for FilterId {
+impl std::str::FromStr for Band {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.eq_ignore_ascii_case("preamp") {
-            Ok(FilterId::Preamp)
+            Ok(Band::Preamp)
         } else {
             let idx: usize = s.parse().context("Invalid filter index")?;
             let nz_idx = NonZero::new(idx).context("Filter index must be non-zero")?;
-            Ok(FilterId::Index(nz_idx))
+            Ok(Band::Index(nz_idx))
         }
     }
 }
 
-/// Update multiple filter bands in a single pw-cli call
-#[tracing::instrument(skip(updates))]
+impl std::fmt::Display for FilterId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.channel {
+            Some(channel) => write!(f, "{channel}:{}", self.band),
+            None => write!(f, "{}", self.band),
+        }
+    }
+}
+
+impl std::str::FromStr for FilterId {
+    type Err = anyhow::Error;
+
+    /// Parses `<band>` or `<channel>:<band>`, where channel is a position name or `L`/`R`/`C`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((channel, band)) = s.split_once(':') else {
+            return Ok(s.parse::<Band>()?.into());
+        };
+
+        Ok(FilterId::from(band.parse::<Band>()?).on_channel(parse_channel(channel)?))
+    }
+}
+
+/// Parse a channel position name, also accepting `L`/`R`/`C` as shorthands for `FL`/`FR`/`FC`
+pub fn parse_channel(s: &str) -> anyhow::Result<AudioPosition> {
+    match s.to_ascii_uppercase().as_str() {
+        "L" => Ok(AudioPosition::FrontLeft),
+        "R" => Ok(AudioPosition::FrontRight),
+        "C" => Ok(AudioPosition::FrontCenter),
+        _ => s.parse(),
+    }
+}
+
+/// Update multiple filter bands in a single `Props` update
+#[tracing::instrument(skip(backend, updates))]
 pub async fn update_filters(
+    backend: &dyn PwBackend,
     node_id: u32,
     updates: impl IntoIterator<Item = (FilterId, UpdateFilter)>,
 ) -> anyhow::Result<()> {
-    let mut updates = updates.into_iter().peekable();
-    if updates.peek().is_none() {
-        tracing::warn!("no filter updates provided");
-        return Ok(());
-    }
-
-    // Build the params array for pw-cli
-    let mut params = Vec::new();
+    let mut controls = Vec::new();
 
     for (filter_id, update) in updates {
+        let node = filter_id.node_name();
+        let mut control = |port: &str, value: f64| {
+            controls.push((format!("{node}:{port}"), value as f32));
+        };
+
         if let Some(freq) = update.frequency {
-            params.push(format!(r#""{FILTER_PREFIX}{filter_id}:Freq""#));
-            params.push(freq.to_string());
+            control("Freq", freq);
         }
 
-        if let Some(gain_val) = update.gain {
-            params.push(format!(r#""{FILTER_PREFIX}{filter_id}:Gain""#));
-            params.push(gain_val.to_string());
+        if let Some(gain) = update.gain {
+            control("Gain", gain);
         }
 
-        if let Some(q_val) = update.q {
-            params.push(format!(r#""{FILTER_PREFIX}{filter_id}:Q""#));
-            params.push(q_val.to_string());
+        if let Some(q) = update.q {
+            control("Q", q);
         }
 
         if let Some(BiquadCoefficients { b0, b1, b2, a1, a2 }) = update.coeffs {
-            params.push(format!(r#""{FILTER_PREFIX}{filter_id}:b0""#));
-            params.push(b0.to_string());
-            params.push(format!(r#""{FILTER_PREFIX}{filter_id}:b1""#));
-            params.push(b1.to_string());
-            params.push(format!(r#""{FILTER_PREFIX}{filter_id}:b2""#));
-            params.push(b2.to_string());
-            params.push(format!(r#""{FILTER_PREFIX}{filter_id}:a1""#));
-            params.push(a1.to_string());
-            params.push(format!(r#""{FILTER_PREFIX}{filter_id}:a2""#));
-            params.push(a2.to_string());
-        }
-    }
-
-    tracing::trace!(?params, "updating filter parameters");
-
-    let output = Command::new("pw-cli")
-        .arg("set-param")
-        .arg(node_id.to_string())
-        .arg("Props")
-        .arg(format!("{{ params = [ {} ] }}", params.join(", ")))
-        .output()
-        .await
-        .context("Failed to execute pw-cli")?;
+            control("b0", b0);
+            control("b1", b1);
+            control("b2", b2);
+            control("a1", a1);
+            control("a2", a2);
+        }
+    }
 
-    if !output.status.success() {
-        anyhow::bail!("pw-cli failed: {}", String::from_utf8_lossy(&output.stderr));
+    if controls.is_empty() {
+        tracing::warn!("no filter updates provided");
+        return Ok(());
     }
 
-    Ok(())
+    tracing::trace!(?controls, "updating filter parameters");
+    backend.set_controls(node_id, controls).await
 }
 
 /// Update a single filter (convenience wrapper)
-#[tracing::instrument(skip(update))]
+#[tracing::instrument(skip(backend, update))]
 pub async fn update_filter(
+    backend: &dyn PwBackend,
     node_id: u32,
     filter_id: FilterId,
     update: UpdateFilter,
 ) -> anyhow::Result<()> {
-    update_filters(node_id, [(filter_id, update)]).await
+    update_filters(backend, node_id, [(filter_id, update)]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZero;
+
+    use pw_util::backend::{Call, Fake, PwBackend as _};
+    use pw_util::module::Direction;
+    use serde_json::json;
+
+    use super::{Device, FilterId, Replacement, UpdateFilter};
+
+    #[tokio::test]
+    async fn test_list_eqs() {
+        let fake = Fake::new();
+        fake.add_node(json!({
+            "node.name": "alsa_output.speakers",
+            "node.description": "Speakers",
+            "media.class": "Audio/Sink",
+        }));
+        fake.load_eq("living-room", Direction::Sink);
+        fake.load_eq("mic", Direction::Source);
+
+        let eqs = super::list_eqs(&fake).await.unwrap();
+        let eqs = eqs
+            .iter()
+            .map(|eq| (eq.name.as_str(), eq.class.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            eqs,
+            [("living-room", "Audio/Sink"), ("mic", "Audio/Source")]
+        );
+
+        // EQs are not devices to bind an EQ to
+        let devices = super::list_devices(&fake, Direction::Sink).await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].description, "Speakers");
+    }
+
+    #[tokio::test]
+    async fn test_use_eq() {
+        let fake = Fake::new();
+        fake.load_eq("studio", Direction::Sink);
+        let source = fake.load_eq("studio", Direction::Source);
+
+        let id = super::use_eq(&fake, "studio", Direction::Source)
+            .await
+            .unwrap();
+        assert_eq!(id, source);
+        assert!(fake.calls().ends_with(&[Call::SetDefault(source)]));
+        let default = fake.get_default(Direction::Source).await.unwrap().unwrap();
+        assert_eq!(default.id, Some(source));
+        assert_eq!(default.name, "effect_output.pweq.source.studio");
+        assert_eq!(fake.get_default(Direction::Sink).await.unwrap(), None);
+
+        let err = super::use_eq(&fake, "missing", Direction::Sink)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "EQ 'missing' not found");
+    }
+
+    #[tokio::test]
+    async fn test_route_app() {
+        let fake = Fake::new();
+        let speakers = fake.add_node(json!({
+            "node.name": "alsa_output.speakers",
+            "media.class": "Audio/Sink",
+        }));
+        let sink = fake.load_eq("music", Direction::Sink);
+        let mpv = fake.add_node(json!({
+            "application.name": "mpv",
+            "media.class": "Stream/Output/Audio",
+        }));
+        fake.add_link(mpv, speakers);
+        fake.set_stream_target(mpv, Some(speakers)).await.unwrap();
+
+        let err = super::route_app(&fake, "vlc", "music").await.unwrap_err();
+        assert_eq!(err.to_string(), "no streams from 'vlc'");
+
+        let routed = super::route_app(&fake, "MPV", "music").await.unwrap();
+        assert_eq!(routed.len(), 1);
+        assert_eq!(routed[0].target, Some(speakers));
+        assert_eq!(fake.links(mpv), vec![sink]);
+
+        // Back on the device it was pinned to before, and only once
+        let unrouted = super::unroute_app(&fake, "mpv").await.unwrap();
+        assert_eq!(unrouted.len(), 1);
+        assert_eq!(fake.links(mpv), vec![speakers]);
+        assert_eq!(fake.stream_targets().await.unwrap()[&mpv], speakers);
+        let unrouted = super::unroute_app(&fake, "mpv").await.unwrap();
+        assert!(unrouted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_chain_status() {
+        let fake = Fake::new();
+        let speakers = fake.add_node(json!({
+            "node.name": "alsa_output.speakers",
+            "media.class": "Audio/Sink",
+        }));
+        let focal = fake.load_eq("focal", Direction::Sink);
+        fake.load_eq("idle", Direction::Sink);
+        fake.set_default(focal).await.unwrap();
+
+        let firefox = fake.add_node(json!({
+            "application.name": "Firefox",
+            "media.class": "Stream/Output/Audio",
+        }));
+        fake.add_link(firefox, focal);
+        // The playback side of the filter-chain, which plays into the device
+        let objects = fake.dump().await.unwrap();
+        let playback = objects
+            .iter()
+            .find(|obj| obj.prop("node.name") == Some(&json!("effect_input.pweq.focal")))
+            .unwrap();
+        fake.add_link(playback.id, speakers);
+
+        let status = super::chain_status(&fake).await.unwrap();
+        assert_eq!(
+            status.default_sink.as_deref(),
+            Some("effect_output.pweq.focal")
+        );
+        assert_eq!(status.into_default, ["Firefox -> pweq.focal"]);
+        assert_eq!(
+            status.from_eqs,
+            ["pweq.focal -> alsa_output.speakers", "pweq.idle"]
+        );
+        assert_eq!(
+            status.warnings,
+            ["pweq.idle has no inputs", "pweq.idle has no output link"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_eq() {
+        let fake = Fake::new();
+        let speakers = fake.add_node(json!({
+            "node.name": "alsa_output.speakers",
+            "node.description": "Speakers",
+            "media.class": "Audio/Sink",
+        }));
+        let headphones = fake.add_node(json!({
+            "node.name": "alsa_output.headphones",
+            "node.description": "Headphones",
+            "media.class": "Audio/Sink",
+        }));
+        let sink = fake.load_eq("studio", Direction::Sink);
+        let source = fake.load_eq("studio", Direction::Source);
+        let other = fake.load_eq("other", Direction::Sink);
+        fake.set_default(sink).await.unwrap();
+
+        // The EQ plays into the headphones, so they become the default again
+        let objects = fake.dump().await.unwrap();
+        let playback = objects
+            .iter()
+            .find(|obj| obj.prop("node.name") == Some(&json!("effect_input.pweq.studio")))
+            .unwrap();
+        fake.add_link(playback.id, headphones);
+
+        let plan = super::plan_delete(&fake, "studio").await.unwrap();
+        let device = Device {
+            id: headphones,
+            name: "alsa_output.headphones".to_string(),
+            description: "Headphones".to_string(),
+        };
+        assert_eq!(
+            plan.defaults,
+            [(Direction::Sink, Replacement::Linked(device))]
+        );
+        // The modules are unloaded, rather than their nodes destroyed
+        let module_of = |id| {
+            let module = fake.object(id).unwrap().prop("module.id").unwrap().clone();
+            serde_json::from_value::<u32>(module).unwrap()
+        };
+        let modules = [module_of(sink), module_of(source)];
+        assert_eq!(plan.modules, modules);
+        assert!(plan.nodes.is_empty());
+
+        super::delete_eq(&fake, &plan).await.unwrap();
+        let default = fake.get_default(Direction::Sink).await.unwrap().unwrap();
+        assert_eq!(default.id, Some(headphones));
+        let calls = fake.calls();
+        for id in modules {
+            assert!(calls.contains(&Call::Destroy(id)));
+            assert!(fake.object(id).is_none());
+        }
+        assert!(fake.object(sink).is_none() && fake.object(source).is_none());
+        assert!(fake.object(other).is_some());
+        assert!(fake.object(speakers).is_some());
+
+        let plan = super::plan_delete(&fake, "studio").await.unwrap();
+        assert_eq!(plan, super::DeletePlan::default());
+    }
+
+    #[tokio::test]
+    async fn test_delete_eq_replacement() {
+        let fake = Fake::new();
+        let speakers = fake.add_node(json!({
+            "node.name": "alsa_output.speakers",
+            "node.description": "Speakers",
+            "media.class": "Audio/Sink",
+            "object.serial": 1042,
+        }));
+        let headphones = fake.add_node(json!({
+            "node.name": "alsa_output.headphones",
+            "node.description": "Headphones",
+            "media.class": "Audio/Sink",
+        }));
+
+        // The device the EQ is bound to wins over the one it is linked to
+        let mut args = Fake::eq_args("bound", Direction::Sink);
+        args.set_target(Some("1042".to_string()));
+        let bound = fake.load_eq_with(args);
+        fake.set_default(bound).await.unwrap();
+        let objects = fake.dump().await.unwrap();
+        let playback = objects
+            .iter()
+            .find(|obj| obj.prop("node.name") == Some(&json!("effect_input.pweq.bound")))
+            .unwrap();
+        fake.add_link(playback.id, headphones);
+
+        let plan = super::plan_delete(&fake, "bound").await.unwrap();
+        let device = Device {
+            id: speakers,
+            name: "alsa_output.speakers".to_string(),
+            description: "Speakers".to_string(),
+        };
+        assert_eq!(
+            plan.defaults,
+            [(Direction::Sink, Replacement::Target(device))]
+        );
+
+        // With neither, the session manager picks the default
+        let free = fake.load_eq("free", Direction::Sink);
+        fake.set_default(free).await.unwrap();
+        let plan = super::plan_delete(&fake, "free").await.unwrap();
+        assert_eq!(plan.defaults, [(Direction::Sink, Replacement::Cleared)]);
+
+        super::delete_eq(&fake, &plan).await.unwrap();
+        assert!(fake.calls().contains(&Call::ClearDefault(Direction::Sink)));
+        assert_eq!(fake.get_default(Direction::Sink).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_update_filters() {
+        let fake = Fake::new();
+        let node_id = fake.load_eq("living-room", Direction::Sink);
+
+        let update = |frequency, gain, q| UpdateFilter {
+            frequency,
+            gain,
+            q,
+            coeffs: None,
+        };
+        super::update_filters(
+            &fake,
+            node_id,
+            [
+                (FilterId::preamp(), update(None, Some(-3.0), None)),
+                (
+                    FilterId::index(NonZero::new(1).unwrap()),
+                    update(Some(100.0), Some(2.0), Some(0.5)),
+                ),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let controls = [
+            ("pweq.filter_preamp:Gain", -3.0),
+            ("pweq.filter_1:Freq", 100.0),
+            ("pweq.filter_1:Gain", 2.0),
+            ("pweq.filter_1:Q", 0.5),
+        ];
+        assert_eq!(
+            fake.calls().last(),
+            Some(&Call::SetControls {
+                node_id,
+                controls: controls
+                    .map(|(name, value)| (name.to_string(), value))
+                    .to_vec(),
+            })
+        );
+
+        // What `describe` reads back
+        let node = super::find_eq_node(&fake, "living-room").await.unwrap();
+        let filters = pw_util::props::filter_controls(&node.info.params);
+        let filter = filters
+            .iter()
+            .find(|filter| filter.node == "pweq.filter_1")
+            .unwrap();
+        assert_eq!(filter.value("Freq"), Some(100.0));
+
+        fake.fail_next("node is gone");
+        let err = super::update_filter(
+            &fake,
+            node_id,
+            FilterId::preamp(),
+            update(None, Some(0.0), None),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "node is gone");
+    }
 }
@@ -4,14 +4,18 @@ use crossterm::event::EventStream;
 use futures_util::StreamExt as _;
 use pw_eq::filter::Filter;
 use pw_eq::tui;
-use pw_eq::{FilterId, find_eq_node, use_eq};
+use pw_eq::{FilterId, Replacement, find_eq_node, use_eq};
 use pw_util::apo::{self, FilterType};
-use pw_util::module::{self, FILTER_PREFIX};
+use pw_util::backend::{PipeWire, PwBackend};
+use pw_util::module::{
+    self, AudioPosition, ChannelChain, ChannelLayout, Direction, ExtraProps, FILTER_PREFIX,
+};
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use tabled::Table;
 use tokio::fs;
 use tracing_subscriber::EnvFilter;
@@ -24,24 +28,71 @@ use tracing_subscriber::util::SubscriberInitExt as _;
 struct Args {
     #[clap(long)]
     pub log_file: Option<PathBuf>,
+    /// Query the graph by running pw-dump instead of connecting to PipeWire directly
+    #[clap(long, global = true)]
+    pub pw_dump: bool,
     #[clap(subcommand)]
     command: Cmd,
 }
 
 #[derive(Parser)]
 /// Create a new Pipewire EQ from an AutoEQ .apo file
+#[command(group(clap::ArgGroup::new("input").required(true)))]
 struct CreateArgs {
     /// Name for the EQ (e.g., focal-celestee)
     name: String,
-    /// Path to the file (APO)
-    #[arg(short, long)]
-    file: PathBuf,
-    /// Set as default sink after creating
+    /// Path to the file (APO), applied to every channel
+    #[arg(short, long, group = "input")]
+    file: Option<PathBuf>,
+    /// Path to an APO file that PipeWire reads on every load instead of inlining its filters,
+    /// so edits to the file apply the next time the EQ is loaded
+    #[arg(long, value_name = "PATH", group = "input")]
+    link_file: Option<PathBuf>,
+    /// Per-channel APO file as <channel>=<path> (e.g. L=left.apo), repeat for each channel
+    #[arg(long = "channel-file", value_name = "CHANNEL=PATH", group = "input", value_parser = parse_channel_file)]
+    channel_files: Vec<(AudioPosition, PathBuf)>,
+    /// Set as default sink (or source) after creating
     #[arg(short, long)]
     r#use: bool,
+    /// Create an input-side EQ: a virtual source that filters a capture device
+    #[arg(short, long)]
+    source: bool,
+    /// Node name of the device to output to (or capture from with --source), instead of the
+    /// default device
+    #[arg(short, long, value_name = "NODE_NAME")]
+    target: Option<String>,
     /// Overwrite existing EQ configuration if it exists
     #[arg(short, long)]
     force: bool,
+    /// Channel layout: mono, stereo, 2.1, 5.1, 7.1, aux<N> or a list of positions (e.g. FL,FR,LFE)
+    #[arg(short, long, default_value_t)]
+    layout: ChannelLayout,
+    /// Extra module arg as <key>=<value> (e.g. audio.rate=48000), repeatable
+    #[arg(long = "prop", value_name = "KEY=VALUE", value_parser = parse_prop)]
+    props: Vec<(String, spa_json::Value)>,
+    /// Extra capture stream prop as <key>=<value> (e.g. node.latency=256/48000), repeatable
+    #[arg(long = "capture-prop", value_name = "KEY=VALUE", value_parser = parse_prop)]
+    capture_props: Vec<(String, spa_json::Value)>,
+    /// Extra playback stream prop as <key>=<value> (e.g. node.autoconnect=false), repeatable
+    #[arg(long = "playback-prop", value_name = "KEY=VALUE", value_parser = parse_prop)]
+    playback_props: Vec<(String, spa_json::Value)>,
+}
+
+// Values are parsed as SPA-JSON, falling back to a plain string
+fn parse_prop(s: &str) -> anyhow::Result<(String, spa_json::Value)> {
+    let (key, value) = s
+        .split_once('=')
+        .context("expected <key>=<value>, e.g. node.latency=256/48000")?;
+    let value =
+        spa_json::from_str(value).unwrap_or_else(|_| spa_json::Value::String(value.to_string()));
+    Ok((key.to_string(), value))
+}
+
+fn parse_channel_file(s: &str) -> anyhow::Result<(AudioPosition, PathBuf)> {
+    let (channel, path) = s
+        .split_once('=')
+        .context("expected <channel>=<path>, e.g. L=left.apo")?;
+    Ok((pw_eq::parse_channel(channel)?, PathBuf::from(path)))
 }
 
 #[derive(Parser)]
@@ -59,7 +110,8 @@ struct DescribeArgs {
 struct SetArgs {
     /// EQ name or ID
     profile: String,
-    /// Filter ID (depends on preset, use 'describe' to see available filters)
+    /// Filter ID, prefixed with the channel for per-channel EQs (e.g. 3, preamp, L:3, FR:preamp).
+    /// Use 'describe' to see available filters
     filter: FilterId,
     /// Set frequency in Hz
     #[arg(short, long = "freq", group = "params")]
@@ -82,10 +134,45 @@ struct SetArgs {
 }
 
 #[derive(Parser)]
-/// Set an EQ as the default sink
+/// Compare two EQ configs, showing whether changes can be applied live
+struct DiffArgs {
+    /// Profile name or path of the old .conf file
+    old: String,
+    /// Profile name or path of the new .conf file
+    new: String,
+}
+
+#[derive(Parser)]
+/// Set an EQ as the default sink, or as the default source with --source
 struct UseArgs {
     /// EQ name or ID
     profile: String,
+    /// Use the source EQ with this name
+    #[arg(short, long)]
+    source: bool,
+}
+
+#[derive(Parser)]
+/// Remove an EQ's config and take it out of the running graph
+struct DeleteArgs {
+    /// EQ name
+    profile: String,
+    /// Show what would be changed without changing anything
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+/// Move an application's streams onto an EQ, even if they are pinned to a device
+struct RouteArgs {
+    /// Application name or binary (e.g. firefox)
+    app: String,
+    /// EQ name or ID
+    #[arg(required_unless_present = "restore")]
+    profile: Option<String>,
+    /// Put the application's streams back where they were before they were routed
+    #[arg(short, long, conflicts_with = "profile")]
+    restore: bool,
 }
 
 #[derive(Parser)]
@@ -97,6 +184,12 @@ struct TuiArgs {
     /// Apply a pre-existing preset filter configuration on startup
     #[arg(short, long)]
     preset: Option<Preset>,
+    /// Channel layout: mono, stereo, 2.1, 5.1, 7.1, aux<N> or a list of positions (e.g. FL,FR,LFE)
+    #[arg(short, long, default_value_t)]
+    layout: ChannelLayout,
+    /// Equalize the default source (e.g. a microphone) instead of playback
+    #[arg(short, long)]
+    source: bool,
 }
 
 #[derive(Clone)]
@@ -184,6 +277,8 @@ enum Cmd {
     #[clap(subcommand)]
     Config(ConfigArgs),
     Create(CreateArgs),
+    #[clap(alias = "rm")]
+    Delete(DeleteArgs),
     /// List available EQ filters
     #[clap(alias = "ls")]
     List,
@@ -191,6 +286,10 @@ enum Cmd {
     Describe(DescribeArgs),
     Set(SetArgs),
     Use(UseArgs),
+    Route(RouteArgs),
+    /// Show what plays into the default sink and where each EQ's output goes
+    Status,
+    Diff(DiffArgs),
     /// Interactive TUI mode
     Tui(TuiArgs),
 }
@@ -198,6 +297,7 @@ enum Cmd {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    let backend = Arc::new(PipeWire::new().with_pw_dump(args.pw_dump));
 
     // Set up tracing subscriber with file logging
     let _guard = if let Some(log_file_path) = args.log_file {
@@ -219,18 +319,27 @@ async fn main() -> anyhow::Result<()> {
 
     match args.command {
         Cmd::Config(config) => configure(config).await?,
-        Cmd::Create(create) => create_eq(create).await?,
+        Cmd::Create(create) => create_eq(&*backend, create).await?,
+        Cmd::Delete(delete) => delete_eq(&*backend, delete).await?,
         Cmd::List => {
-            let eqs = pw_eq::list_eqs().await?;
+            let eqs = pw_eq::list_eqs(&*backend).await?;
             let table = Table::new(eqs);
             println!("{table}");
         }
-        Cmd::Describe(describe) => describe_eq(&describe).await?,
-        Cmd::Set(set) => set_filter(set).await?,
+        Cmd::Describe(describe) => describe_eq(&*backend, &describe).await?,
+        Cmd::Set(set) => set_filter(&*backend, set).await?,
         Cmd::Use(use_cmd) => {
-            use_eq(&use_cmd.profile).await?;
+            use_eq(
+                &*backend,
+                &use_cmd.profile,
+                Direction::from_source_flag(use_cmd.source),
+            )
+            .await?;
         }
-        Cmd::Tui(tui) => run_tui(tui).await?,
+        Cmd::Route(route) => route_app(&*backend, route).await?,
+        Cmd::Status => print_status(&*backend).await?,
+        Cmd::Diff(diff) => diff_configs(diff)?,
+        Cmd::Tui(tui) => run_tui(backend, tui).await?,
     }
 
     Ok(())
@@ -266,52 +375,43 @@ async fn configure(args: ConfigArgs) -> anyhow::Result<()> {
     }
 }
 
-async fn run_tui(args: TuiArgs) -> anyhow::Result<()> {
-    let filters = match (args.file, args.preset) {
+async fn run_tui(backend: Arc<dyn PwBackend>, args: TuiArgs) -> anyhow::Result<()> {
+    // Files carry their own preamp, presets get one that leaves headroom for their gains
+    let (filters, preamp) = match (args.file, args.preset) {
         (Some(_), Some(_)) => unreachable!("clap should prevent this case"),
         (Some(path), None) => {
-            match path.extension() {
+            let apo = match path.extension() {
                 Some(ext) if ext == "conf" => {
                     let conf = module::Config::parse_file(&path)?;
-                    if conf.context_modules.len() != 1 {
-                        anyhow::bail!(
-                            "expected exactly one context module in config, found {}",
-                            conf.context_modules.len()
-                        );
-                    }
-
-                    todo!("{:#?}", conf.context_modules[0])
-                }
-                Some(ext) if ext == "apo" => {
-                    let conf = apo::Config::parse_file(path).await?;
-                    // FIXME preamp ignored
-                    conf.filters.into_iter().map(Filter::from).collect()
+                    let mut modules = conf.filter_chains();
+                    let (Some(module), None) = (modules.next(), modules.next()) else {
+                        anyhow::bail!("expected exactly one filter-chain module in config");
+                    };
+                    module.to_apo()?
                 }
+                Some(ext) if ext == "apo" => apo::Config::parse_file(path).await?,
                 _ => anyhow::bail!("file must have an extension of .apo or .conf"),
-            }
+            };
+            let filters = apo.filters.into_iter().map(Filter::from).collect();
+            (filters, Some(apo.preamp))
         }
-        (None, Some(preset)) => preset.make_filters(),
-        _ => vec![],
+        (None, Some(preset)) => (preset.make_filters(), None),
+        _ => (vec![], None),
     };
 
+    let config = load_config().await?;
     let term = ratatui::init();
 
-    let base_config = tui::Config::default();
-    let user_config_path = dirs::config_dir().unwrap().join("pw-eq/pweq.conf");
-    let config = if user_config_path.exists() {
-        tracing::info!(
-            path = %user_config_path.display(),
-            "loading user configuration",
-        );
-        let file = fs::File::open(user_config_path).await?;
-        let config =
-            spa_json::from_reader::<_, tui::Config>(BufReader::new(file.try_into_std().unwrap()))?;
-        base_config.merge(config)
-    } else {
-        base_config
-    };
-
-    let mut app = tui::App::new(term, config, filters)?;
+    let direction = Direction::from_source_flag(args.source);
+    let mut app = tui::App::new(
+        term,
+        backend,
+        config,
+        args.layout,
+        direction,
+        filters,
+        preamp,
+    )?;
     app.enter()?;
 
     let events = EventStream::new()
@@ -323,31 +423,150 @@ async fn run_tui(args: TuiArgs) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn route_app(backend: &dyn PwBackend, args: RouteArgs) -> anyhow::Result<()> {
+    let streams = match &args.profile {
+        Some(profile) => pw_eq::route_app(backend, &args.app, profile).await?,
+        None => pw_eq::unroute_app(backend, &args.app).await?,
+    };
+    for stream in streams {
+        let media_name = stream.media_name.as_deref().unwrap_or_default();
+        match &args.profile {
+            Some(profile) => println!("Moved {} ({media_name}) onto {profile}", stream.id),
+            None => println!("Restored {} ({media_name})", stream.id),
+        }
+    }
+    Ok(())
+}
+
+async fn print_status(backend: &dyn PwBackend) -> anyhow::Result<()> {
+    let status = pw_eq::chain_status(backend).await?;
+    match &status.default_sink {
+        Some(name) => println!("Default sink: {name}"),
+        None => println!("No default sink"),
+    }
+    for chain in &status.into_default {
+        println!("  {chain}");
+    }
+    if !status.from_eqs.is_empty() {
+        println!("EQs:");
+    }
+    for chain in &status.from_eqs {
+        println!("  {chain}");
+    }
+    for warning in &status.warnings {
+        eprintln!("warning: {warning}");
+    }
+    Ok(())
+}
+
+/// Load the user's pweq.conf on top of the defaults
+fn user_config_path() -> PathBuf {
+    dirs::config_dir().unwrap().join("pw-eq/pweq.conf")
+}
+
+async fn load_config() -> anyhow::Result<tui::Config> {
+    let base_config = tui::Config::default();
+    let user_config_path = user_config_path();
+    if !user_config_path.exists() {
+        return Ok(base_config);
+    }
+
+    tracing::info!(
+        path = %user_config_path.display(),
+        "loading user configuration",
+    );
+    let file = fs::File::open(user_config_path).await?;
+    let config =
+        spa_json::from_reader::<_, tui::Config>(BufReader::new(file.try_into_std().unwrap()))?;
+    Ok(base_config.merge(config))
+}
+
+/// The plugins and defaults from the user configuration, falling back to none with a warning
+/// if they can't be read
+async fn load_profile_config() -> tui::ProfileConfig {
+    let path = user_config_path();
+    if !path.exists() {
+        return tui::ProfileConfig::default();
+    }
+
+    let config = async {
+        let file = fs::File::open(&path).await?;
+        anyhow::Ok(spa_json::from_reader::<_, tui::ProfileConfig>(
+            BufReader::new(file.try_into_std().unwrap()),
+        )?)
+    };
+    config.await.unwrap_or_else(|err| {
+        eprintln!(
+            "warning: ignoring plugins and defaults in {}: {err:#}",
+            path.display()
+        );
+        tui::ProfileConfig::default()
+    })
+}
+
 async fn create_eq(
+    backend: &dyn PwBackend,
     CreateArgs {
         name,
         file,
+        link_file,
+        channel_files,
         r#use: use_after,
+        source,
+        target,
         force,
+        layout,
+        props,
+        capture_props,
+        playback_props,
     }: CreateArgs,
 ) -> anyhow::Result<()> {
-    // Parse the .apo file
-    let apo_config = apo::Config::parse_file(file).await?;
-
     // Generate the filter-chain config
-    let config_content = pw_util::module::Config::from_apo(&name, &apo_config);
-    let content = pw_util::to_spa_json(&config_content);
-
-    // Get the config directory path
-    let config_dir = dirs::config_dir()
-        .ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?
-        .join("pipewire/pipewire.conf.d");
+    let module = match (file, link_file) {
+        (Some(file), _) => {
+            let apo_config = apo::Config::parse_file(file).await?;
+            module::Module::from_apo(&name, &layout, &apo_config)
+        }
+        (_, Some(path)) => {
+            // Catch mistakes now rather than when PipeWire loads the file
+            apo::Config::parse_file(&path)
+                .await
+                .with_context(|| format!("failed to load {}", path.display()))?;
+            // PipeWire doesn't resolve relative paths against our working directory
+            let path = fs::canonicalize(&path).await?;
+            module::Module::from_apo_file(&name, &layout, &path)?
+        }
+        (None, None) => {
+            let mut chains = Vec::with_capacity(channel_files.len());
+            for (position, path) in channel_files {
+                let apo_config = apo::Config::parse_file(&path)
+                    .await
+                    .with_context(|| format!("failed to load {}", path.display()))?;
+                chains.push(ChannelChain::from_apo(position, &apo_config));
+            }
+            module::Module::from_channel_chains(&name, &layout, chains)?
+        }
+    };
+    let direction = Direction::from_source_flag(source);
+    let config = load_profile_config().await;
+    let props = config.defaults.merge(ExtraProps {
+        args: props.into_iter().collect(),
+        capture_props: capture_props.into_iter().collect(),
+        playback_props: playback_props.into_iter().collect(),
+    });
+    let mut module = module
+        .with_direction(direction)
+        .with_target(target)
+        .with_props(&props)?;
+    module.append_plugins(&config.plugins);
+    module.args.validate()?;
+    let content = pw_util::to_spa_json(&module::Config::from(module));
 
     // Create the directory if it doesn't exist
-    fs::create_dir_all(&config_dir).await?;
+    let config_file = profile_config_file(&name)?;
+    fs::create_dir_all(config_file.parent().unwrap()).await?;
 
     // Write the config file
-    let config_file = config_dir.join(format!("pweq-{name}.conf"));
     if !force && config_file.exists() {
         return Err(anyhow::anyhow!(
             "EQ configuration '{}' already exists",
@@ -358,13 +577,119 @@ async fn create_eq(
     fs::write(&config_file, content).await?;
 
     if use_after {
-        use_eq(&name).await?;
+        use_eq(backend, &name, direction).await?;
+    }
+
+    Ok(())
+}
+
+async fn delete_eq(
+    backend: &dyn PwBackend,
+    DeleteArgs { profile, dry_run }: DeleteArgs,
+) -> anyhow::Result<()> {
+    let config_file = profile_config_file(&profile)?;
+    let has_config = config_file.exists();
+    let plan = pw_eq::plan_delete(backend, &profile).await?;
+    if !has_config && plan.modules.is_empty() && plan.nodes.is_empty() {
+        anyhow::bail!("EQ '{profile}' not found");
+    }
+
+    let verb = |would: &str, doing: &str| {
+        if dry_run {
+            format!("Would {would}")
+        } else {
+            doing.to_string()
+        }
+    };
+    for (direction, replacement) in &plan.defaults {
+        let (kind, linked) = match direction {
+            Direction::Sink => ("sink", "the device the EQ plays into"),
+            Direction::Source => ("source", "the device the EQ records from"),
+        };
+        let (device, why) = match replacement {
+            Replacement::Target(device) => (device, "the device the EQ is bound to"),
+            Replacement::Linked(device) => (device, linked),
+            Replacement::Cleared => {
+                let clear = verb("clear", "Clearing");
+                println!("{clear} the default {kind}, as the EQ has no device to go back to");
+                continue;
+            }
+        };
+        println!(
+            "{} the default {kind} back to {} ({}), {why}",
+            verb("switch", "Switching"),
+            device.description,
+            device.name
+        );
+    }
+    if !plan.modules.is_empty() {
+        let modules = plan.modules.iter().map(u32::to_string).collect::<Vec<_>>();
+        println!(
+            "{} modules {}",
+            verb("unload", "Unloading"),
+            modules.join(", ")
+        );
+    }
+    if !plan.nodes.is_empty() {
+        let nodes = plan.nodes.iter().map(u32::to_string).collect::<Vec<_>>();
+        println!(
+            "{} nodes {}",
+            verb("destroy", "Destroying"),
+            nodes.join(", ")
+        );
+    }
+    if has_config {
+        println!("{} {}", verb("remove", "Removing"), config_file.display());
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    pw_eq::delete_eq(backend, &plan).await?;
+    if has_config {
+        fs::remove_file(&config_file)
+            .await
+            .with_context(|| format!("failed to remove {}", config_file.display()))?;
+    }
+    Ok(())
+}
+
+/// The pipewire.conf.d fragment `create` writes for a profile
+fn profile_config_file(name: &str) -> anyhow::Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?
+        .join("pipewire/pipewire.conf.d")
+        .join(format!("pweq-{name}.conf")))
+}
+
+fn diff_configs(DiffArgs { old, new }: DiffArgs) -> anyhow::Result<()> {
+    let load = |profile: &str| {
+        let path = match PathBuf::from(profile) {
+            path if path.exists() => path,
+            _ => profile_config_file(profile)?,
+        };
+        module::Config::parse_file(&path)
+            .with_context(|| format!("failed to load {}", path.display()))
+    };
+
+    let diff = load(&old)?.diff(&load(&new)?);
+    if diff.is_empty() {
+        println!("No differences");
+        return Ok(());
+    }
+
+    print!("{diff}");
+    if diff.is_live() {
+        println!("\nOnly filter parameters changed, which can be applied live");
+    } else {
+        println!("\nStructural changes, the module must be reloaded");
     }
 
     Ok(())
 }
 
 async fn set_filter(
+    backend: &dyn PwBackend,
     SetArgs {
         profile,
         filter,
@@ -378,9 +703,10 @@ async fn set_filter(
         anyhow::bail!("Persisting changes is not yet implemented");
     }
 
-    let node = find_eq_node(&profile).await?;
+    let node = find_eq_node(backend, &profile).await?;
 
     pw_eq::update_filter(
+        backend,
         node.id,
         filter,
         pw_eq::UpdateFilter {
@@ -400,84 +726,42 @@ async fn set_filter(
     Ok(())
 }
 
-async fn describe_eq(DescribeArgs { all, profile }: &DescribeArgs) -> anyhow::Result<()> {
-    let node = find_eq_node(profile).await?;
+async fn describe_eq(
+    backend: &dyn PwBackend,
+    DescribeArgs { all, profile }: &DescribeArgs,
+) -> anyhow::Result<()> {
+    let node = find_eq_node(backend, profile).await?;
     let info = node.info;
 
-    #[derive(Debug, Default)]
-    struct FilterInfo {
-        freq: Option<f64>,
-        gain: Option<f64>,
-        q: Option<f64>,
-        a0: Option<f64>,
-        a1: Option<f64>,
-        a2: Option<f64>,
-        b0: Option<f64>,
-        b1: Option<f64>,
-        b2: Option<f64>,
-    }
-
-    let mut filter_infos = BTreeMap::<FilterId, FilterInfo>::new();
-    // Dodgy parsing, weird structures. See `pw-dump <id>`
-    for prop in info.params.props {
-        for (key, value) in &prop.params.0 {
-            let Some((id, param_name)) = key
-                .strip_prefix(FILTER_PREFIX)
-                .and_then(|s| s.split_once(':'))
-            else {
-                continue;
-            };
-
-            let id = id
-                .parse::<FilterId>()
-                .with_context(|| format!("invalid filter id in parameter name: {key}"))?;
-            let value = value
-                .as_f64()
-                .with_context(|| format!("invalid value for parameter {key}"))?;
-
-            let filter_info = filter_infos.entry(id).or_default();
-            match param_name {
-                "Freq" => filter_info.freq = Some(value),
-                "Gain" => filter_info.gain = Some(value),
-                "Q" => filter_info.q = Some(value),
-                "a0" => filter_info.a0 = Some(value),
-                "a1" => filter_info.a1 = Some(value),
-                "a2" => filter_info.a2 = Some(value),
-                "b0" => filter_info.b0 = Some(value),
-                "b1" => filter_info.b1 = Some(value),
-                "b2" => filter_info.b2 = Some(value),
-                _ => anyhow::bail!("Unknown EQ filter parameter: {param_name}"),
-            }
-        }
-
-        if !filter_infos.is_empty() {
-            break;
-        }
-    }
+    let filters = pw_util::props::filter_controls(&info.params)
+        .into_iter()
+        .filter(|controls| controls.node.starts_with(FILTER_PREFIX))
+        .map(|controls| Ok((FilterId::from_node_name(&controls.node)?, controls)))
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
 
     println!("EQ Profile: {profile}");
     println!("Node ID: {}", node.id);
     println!("Filters:");
-    for (id, filter) in filter_infos {
-        let freq = filter
-            .freq
-            .ok_or_else(|| anyhow::anyhow!("Missing frequency for filter {id}"))?;
-        let gain = filter
-            .gain
-            .ok_or_else(|| anyhow::anyhow!("Missing gain for filter {id}"))?;
-        let q = filter
-            .q
-            .ok_or_else(|| anyhow::anyhow!("Missing Q for filter {id}"))?;
+    for (id, filter) in filters {
+        let control = |name| {
+            filter
+                .value(name)
+                .ok_or_else(|| anyhow::anyhow!("Missing {name} for filter {id}"))
+        };
+        let freq = control("Freq")?;
+        let gain = control("Gain")?;
+        let q = control("Q")?;
 
         if *all {
+            let coeff = |name| filter.value(name).unwrap_or(0.0);
             println!(
                 "  Filter {id:>2}: Freq {freq:>8.2} Hz  Gain {gain:+5.2} dB  Q {q:.2} --> ({:.6}, {:.6}, {:.6}, {:.6}, {:.6}, {:.6})",
-                filter.b0.unwrap_or(0.0),
-                filter.b1.unwrap_or(0.0),
-                filter.b2.unwrap_or(0.0),
-                filter.a0.unwrap_or(0.0),
-                filter.a1.unwrap_or(0.0),
-                filter.a2.unwrap_or(0.0),
+                coeff("b0"),
+                coeff("b1"),
+                coeff("b2"),
+                coeff("a0"),
+                coeff("a1"),
+                coeff("a2"),
             );
         } else {
             println!("  Filter {id:>2}: Freq {freq:>8.2} Hz  Gain {gain:+5.2} dB  Q {q:.2}",);
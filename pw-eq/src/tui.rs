@@ -3,15 +3,17 @@ mod draw;
 mod eq;
 mod theme;
 
-use crate::{FilterId, UpdateFilter, filter::Filter, update_filters, use_eq};
+use crate::{
+    Device, FilterId, UpdateFilter, filter::Filter, find_eq_node, list_devices, update_filters,
+};
 use std::collections::HashMap;
 use std::{
-    error::Error,
     io, mem,
     num::NonZero,
     ops::ControlFlow,
     path::PathBuf,
     pin::{Pin, pin},
+    sync::Arc,
 };
 use zi_input::{Event, KeyCode, KeyEvent, KeyModifiers};
 
@@ -23,13 +25,16 @@ use crossterm::{
 };
 use futures_util::{Stream, StreamExt as _, future::BoxFuture, stream::FusedStream};
 use keymap::KeyMap;
-use pw_util::pipewire;
+use pw_util::backend::{LoadedModule, ModuleManager, PwBackend};
+use pw_util::events::Event as GraphEvent;
+use pw_util::module::{COMMON_SAMPLE_RATES, ChannelLayout, Direction, ExtraProps, PluginStage};
+use pw_util::registry::Param;
+use pw_util::routing;
 use ratatui::{Terminal, prelude::Backend};
-use tokio::sync::mpsc;
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinSet;
 use tokio_stream::wrappers::ReceiverStream;
 
-use crate::pw::{self, pw_thread};
-
 use self::{action::Action, eq::Eq, theme::Theme};
 
 pub enum Format {
@@ -69,16 +74,32 @@ enum InputMode {
     #[default]
     Normal,
     Command,
+    /// Choosing the device the EQ outputs to (or captures from)
+    Picker,
+    /// Moving application streams onto the EQ
+    Streams,
 }
 
 pub enum Notif {
-    ModuleLoaded {
-        id: u32,
-        name: String,
-        media_name: String,
-        reused: bool,
-    },
-    Error(anyhow::Error),
+    ModuleLoaded(Result<LoadedModule, String>),
+    Devices(Vec<Device>),
+    Streams(Vec<routing::Stream>),
+    /// A stream was moved onto the EQ, with the stream as it was before
+    Routed(routing::Stream),
+    /// A routed stream was put back
+    Restored(u32),
+}
+
+/// Devices to bind the EQ to. Index 0 is the default device.
+struct TargetPicker {
+    devices: Vec<Device>,
+    selected: usize,
+}
+
+/// Application streams to route onto the EQ
+struct StreamPane {
+    streams: Vec<routing::Stream>,
+    selected: usize,
 }
 
 pub type TaskResult = Result<Option<String>, String>;
@@ -87,13 +108,18 @@ pub type Task = BoxFuture<'static, TaskResult>;
 pub struct App<B: Backend + io::Write> {
     term: Terminal<B>,
     notifs: mpsc::Receiver<Notif>,
+    notifs_tx: mpsc::Sender<Notif>,
     tasks: Pin<Box<dyn FusedStream<Item = TaskResult> + Send>>,
     task_tx: mpsc::Sender<Task>,
-    pw_tx: pipewire::channel::Sender<pw::Message>,
+    backend: Arc<dyn PwBackend>,
+    modules: Arc<Mutex<ModuleManager>>,
+    /// Module loads in flight. The EQ node is replaced once one finishes.
+    loading: usize,
+    /// The tasks running those loads, awaited on exit so none still holds the module manager
+    loads: JoinSet<()>,
     eq: Eq,
     active_node_id: Option<u32>,
-    original_default_sink: Option<u32>,
-    pw_handle: Option<std::thread::JoinHandle<io::Result<()>>>,
+    original_default: Option<u32>,
     sample_rate: u32,
     input_mode: InputMode,
     command_history: Vec<String>,
@@ -104,6 +130,10 @@ pub struct App<B: Backend + io::Write> {
     show_help: bool,
     status: Option<Result<String, String>>,
     view_mode: ViewMode,
+    picker: Option<TargetPicker>,
+    stream_pane: Option<StreamPane>,
+    /// Streams routed onto the EQ, as they were before, to put back on exit
+    routed: HashMap<u32, routing::Stream>,
     config: Config,
 }
 
@@ -112,6 +142,12 @@ pub struct App<B: Backend + io::Write> {
 pub struct Config {
     keymap: KeyMap<InputMode, zi_input::KeyEvent, Action>,
     pub(super) theme: Theme,
+    /// LADSPA/LV2 plugin stages appended after the EQ
+    pub plugins: Vec<PluginStage>,
+    /// Sample rates raw biquad coefficients are generated for
+    pub sample_rates: Vec<u32>,
+    /// Extra module args and stream props (e.g. `node.latency`) for every EQ profile
+    pub defaults: ExtraProps,
 }
 
 impl Config {
@@ -123,14 +159,29 @@ impl Config {
         Self {
             keymap: self.keymap,
             theme: config.theme,
+            plugins: config.plugins,
+            sample_rates: config.sample_rates,
+            defaults: config.defaults,
         }
     }
 }
 
+/// The parts of `Config` that go into the EQ profiles `create` writes, read on their own so a
+/// mistake in the keymap or theme doesn't stop a profile being created
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub plugins: Vec<PluginStage>,
+    pub defaults: ExtraProps,
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             theme: Theme::default(),
+            plugins: Vec::new(),
+            sample_rates: COMMON_SAMPLE_RATES.to_vec(),
+            defaults: ExtraProps::default(),
             keymap: serde_json::from_value(serde_json::json!({
                 "normal": {
                     "<C-c>":     "quit",
@@ -174,6 +225,8 @@ impl Default for Config {
                     "<S-Tab>": { "cycle-filter-type": { "rotation": "counter-clockwise" } },
                     "v":       { "cycle-view-mode": { "rotation": "clockwise" } },
                     "0":       { "adjust-gain": { "set": 0.0 } },
+                    "t":       { "enter-mode": { "mode": "picker" } },
+                    "o":       { "enter-mode": { "mode": "streams" } },
                 },
                 "command": {
                     "<Esc>":       { "enter-mode": { "mode": "normal" } },
@@ -187,6 +240,27 @@ impl Default for Config {
                     "<Right>":     "move-cursor-right",
                     "<Home>":      "move-cursor-home",
                     "<End>":       "move-cursor-end",
+                },
+                "picker": {
+                    "<Esc>":  { "enter-mode": { "mode": "normal" } },
+                    "<C-c>":  { "enter-mode": { "mode": "normal" } },
+                    "q":      { "enter-mode": { "mode": "normal" } },
+                    "j":      "picker-next",
+                    "<Down>": "picker-next",
+                    "k":      "picker-previous",
+                    "<Up>":   "picker-previous",
+                    "<CR>":   "picker-confirm",
+                },
+                "streams": {
+                    "<Esc>":  { "enter-mode": { "mode": "normal" } },
+                    "<C-c>":  { "enter-mode": { "mode": "normal" } },
+                    "q":      { "enter-mode": { "mode": "normal" } },
+                    "j":      "stream-next",
+                    "<Down>": "stream-next",
+                    "k":      "stream-previous",
+                    "<Up>":   "stream-previous",
+                    "<CR>":   "toggle-stream-route",
+                    "<Space>": "toggle-stream-route",
                 }
             }))
             .unwrap(),
@@ -201,36 +275,48 @@ where
 {
     pub fn new(
         term: Terminal<B>,
+        backend: Arc<dyn PwBackend>,
         config: Config,
+        layout: ChannelLayout,
+        direction: Direction,
         filters: impl IntoIterator<Item = Filter>,
+        preamp: Option<f64>,
     ) -> io::Result<Self> {
-        let (pw_tx, rx) = pipewire::channel::channel();
         let (notifs_tx, notifs) = mpsc::channel(100);
-        let pw_handle = std::thread::spawn(|| pw_thread(notifs_tx, rx));
 
         let (task_tx, task_rx) = mpsc::channel::<BoxFuture<'static, TaskResult>>(100);
         let tasks = Box::pin(ReceiverStream::new(task_rx).buffered(8));
 
         let filters = filters.into_iter().collect::<Vec<_>>();
-        let eq = if !filters.is_empty() {
-            Eq::with_filters("pweq".to_string(), filters)
+        let mut eq = if !filters.is_empty() {
+            Eq::with_filters("pweq".to_string(), layout, filters)
         } else {
-            Eq::new("pweq".to_string())
+            Eq::new("pweq".to_string(), layout)
         };
+        eq.plugins = config.plugins.clone();
+        eq.sample_rates = config.sample_rates.clone();
+        eq.props = config.defaults.clone();
+        eq.direction = direction;
+        if let Some(preamp) = preamp {
+            eq.preamp = preamp;
+        }
 
         Ok(Self {
             term,
-            pw_tx,
+            modules: Arc::new(Mutex::new(ModuleManager::new(backend.clone()))),
+            loading: 0,
+            loads: JoinSet::new(),
+            backend,
             notifs,
+            notifs_tx,
             tasks,
             task_tx,
             eq,
             config,
-            pw_handle: Some(pw_handle),
-            // TODO query sample rate
+            // Updated from the clock settings on start, then follows the EQ node's format
             sample_rate: 48000,
             active_node_id: Default::default(),
-            original_default_sink: Default::default(),
+            original_default: Default::default(),
             input_mode: Default::default(),
             command_history: Default::default(),
             command_history_index: Default::default(),
@@ -239,6 +325,9 @@ where
             command_cursor_pos: Default::default(),
             show_help: Default::default(),
             view_mode: Default::default(),
+            picker: None,
+            stream_pane: None,
+            routed: HashMap::new(),
             status: Default::default(),
         })
     }
@@ -270,19 +359,36 @@ where
             cursor::SetCursorStyle::SteadyBar,
         )?;
 
-        // Save the current default sink so we can restore it on exit
-        self.original_default_sink = pw_util::get_default_audio_sink()
+        // Save the current default sink or source so we can restore it on exit
+        self.original_default = self
+            .backend
+            .get_default(self.eq.direction)
             .await
             .inspect_err(|err| {
-                tracing::warn!(error = %err, "Failed to get default audio sink");
+                tracing::warn!(error = %err, "Failed to get default audio node");
             })
-            .ok();
+            .ok()
+            .flatten()
+            .and_then(|node| node.id);
+
+        match self.backend.clock_settings().await {
+            Ok(clock) => self.sample_rate = clock.effective_rate(),
+            Err(err) => tracing::warn!(error = %err, "Failed to get graph clock rate"),
+        }
 
         if !self.eq.is_noop() {
             self.load_module();
         }
 
         let mut events = pin!(events.fuse());
+        let graph_events = match self.backend.events().await {
+            Ok(graph_events) => graph_events.left_stream(),
+            Err(err) => {
+                tracing::warn!(error = %err, "Failed to watch PipeWire graph");
+                futures_util::stream::pending().right_stream()
+            }
+        };
+        let mut graph_events = pin!(graph_events.fuse());
 
         loop {
             self.draw()?;
@@ -294,6 +400,7 @@ where
                     }
                 }
                 Some(notif) = self.notifs.recv() => self.on_notif(notif).await,
+                event = graph_events.select_next_some() => self.on_graph_event(event),
                 result = self.tasks.select_next_some() => match result {
                     Ok(Some(status)) => self.status = Some(Ok(status)),
                     Ok(None) => {}
@@ -302,56 +409,136 @@ where
             }
         }
 
-        let _ = self.pw_tx.send(pw::Message::Terminate);
-
-        // Restore the original default sink before exiting
-        if let Some(sink_id) = self.original_default_sink {
-            tracing::info!(sink_id, "Restoring original default sink");
-            pw_util::set_default(sink_id).await.inspect_err(|err| {
-                tracing::error!(error = %err, "Failed to restore original default sink");
-            })?;
+        // Put routed streams back where they were, restore the original default sink or source,
+        // then unload the EQ so no sink is left behind
+        for (_, stream) in self.routed.drain() {
+            if let Err(err) = routing::restore_stream(&*self.backend, &stream).await {
+                tracing::warn!(error = %err, stream = stream.id, "Failed to restore stream");
+            }
         }
-
-        if let Some(handle) = self.pw_handle.take() {
-            match handle.join() {
-                Ok(Ok(())) => tracing::info!("PipeWire thread exited cleanly"),
-                Ok(Err(err)) => tracing::error!(
-                    error = &err as &dyn Error,
-                    "PipeWire thread exited with error"
-                ),
-                Err(err) => tracing::error!(error = ?err, "PipeWire thread panicked"),
+        let restored = match self.original_default {
+            Some(node_id) => {
+                tracing::info!(node_id, "Restoring original default node");
+                self.backend.set_default(node_id).await.map(drop)
             }
+            None => Ok(()),
+        };
+        // A load that is still waiting for its node finishes first, so its module is unloaded
+        // too. Nothing reads its notification any more.
+        self.notifs.close();
+        while self.loads.join_next().await.is_some() {}
+        if let Err(err) = self.modules.lock().await.shutdown().await {
+            tracing::error!(error = %err, "Failed to unload modules");
         }
-
-        Ok(())
+        restored.inspect_err(|err| {
+            tracing::error!(error = %err, "Failed to restore original default node");
+        })
     }
 
     async fn on_notif(&mut self, notif: Notif) {
         match notif {
-            Notif::ModuleLoaded {
-                id,
-                name,
-                media_name,
-                reused,
-            } => {
-                tracing::info!(id, name, media_name, "module loaded");
+            Notif::ModuleLoaded(loaded) => {
+                self.loading -= 1;
+                let LoadedModule {
+                    module_id, node_id, ..
+                } = match loaded {
+                    Ok(loaded) => loaded,
+                    Err(err) => {
+                        tracing::error!(error = %err, "failed to load module");
+                        self.status = Some(Err(err));
+                        return;
+                    }
+                };
+                tracing::info!(module_id, node_id, "module loaded");
 
-                let Ok(node_id) = use_eq(&media_name).await.inspect_err(|err| {
+                if let Err(err) = self.backend.set_default(node_id).await {
                     tracing::error!(error = %err, "failed to use EQ");
-                }) else {
                     return;
-                };
+                }
 
-                if reused {
-                    // If the module was reused, it may have stale filter settings
-                    self.sync(node_id, self.sample_rate);
+                // Live coefficient updates must match the rate the node is running at
+                let rate = find_eq_node(&*self.backend, &node_id.to_string())
+                    .await
+                    .ok()
+                    .and_then(|node| node.sample_rate());
+                if let Some(rate) = rate {
+                    self.sample_rate = rate;
                 }
 
+                // The module was built from the bands as they were when loading started
+                self.sync(node_id, self.sample_rate);
+
                 self.active_node_id = Some(node_id);
             }
-            Notif::Error(err) => {
-                tracing::error!(error = &*err, "PipeWire error");
+            Notif::Devices(devices) => {
+                let selected = self
+                    .eq
+                    .target
+                    .as_ref()
+                    .and_then(|target| devices.iter().position(|device| &device.name == target))
+                    .map_or(0, |idx| idx + 1);
+                self.picker = Some(TargetPicker { devices, selected });
+                self.input_mode = InputMode::Picker;
+            }
+            Notif::Streams(streams) => {
+                // Keep the selection when the list is refreshed after routing
+                let selected = self
+                    .stream_pane
+                    .as_ref()
+                    .map_or(0, |pane| pane.selected)
+                    .min(streams.len().saturating_sub(1));
+                self.stream_pane = Some(StreamPane { streams, selected });
+                self.input_mode = InputMode::Streams;
+            }
+            Notif::Routed(before) => {
+                self.routed.insert(before.id, before);
             }
+            Notif::Restored(id) => {
+                self.routed.remove(&id);
+            }
+        }
+    }
+
+    fn on_graph_event(&mut self, event: GraphEvent) {
+        match event {
+            // The module manager unloads the old EQ once its replacement is up
+            GraphEvent::GlobalRemoved { id }
+                if self.active_node_id == Some(id) && self.loading > 0 =>
+            {
+                self.active_node_id = None;
+            }
+            GraphEvent::GlobalRemoved { id } if self.active_node_id == Some(id) => {
+                tracing::warn!(id, "EQ node removed");
+                self.active_node_id = None;
+                self.status = Some(Err("EQ node was removed".to_string()));
+            }
+            GraphEvent::NodeParam {
+                id,
+                param: Param::Format(format),
+            } if self.active_node_id == Some(id) => {
+                if let Some(rate) = format.rate {
+                    self.set_sample_rate(rate);
+                }
+            }
+            // Once loaded, the node's format is what counts
+            GraphEvent::ClockChanged { clock } if self.active_node_id.is_none() => {
+                self.sample_rate = clock.effective_rate();
+            }
+            _ => {}
+        }
+    }
+
+    /// Recompute and push every band when the rate the EQ runs at changes
+    fn set_sample_rate(&mut self, rate: u32) {
+        if rate == self.sample_rate {
+            return;
+        }
+
+        tracing::info!(old = self.sample_rate, new = rate, "Sample rate changed");
+        self.sample_rate = rate;
+        if let Some(node_id) = self.active_node_id {
+            self.sync(node_id, rate);
+            self.status = Some(Ok(format!("Sample rate changed to {rate} Hz")));
         }
     }
 
@@ -360,8 +547,9 @@ where
         node_id: u32,
         updates: impl IntoIterator<Item = (FilterId, UpdateFilter), IntoIter: Send> + Send + 'static,
     ) {
+        let backend = self.backend.clone();
         self.schedule(async move {
-            match update_filters(node_id, updates).await {
+            match update_filters(&*backend, node_id, updates).await {
                 Ok(()) => Ok(None),
                 Err(err) => Err(err.to_string()),
             }
@@ -371,12 +559,12 @@ where
     /// Sync preamp gain to PipeWire
     fn sync_preamp(&self, node_id: u32) {
         let update = self.eq.build_preamp_update();
-        self.apply_updates(node_id, [(FilterId::Preamp, update)]);
+        self.apply_updates(node_id, [(FilterId::preamp(), update)]);
     }
 
     /// Sync a specific filter band to PipeWire
     fn sync_filter(&self, node_id: u32, band_idx: usize, sample_rate: u32) {
-        let band_id = FilterId::Index(NonZero::new(band_idx + 1).unwrap());
+        let band_id = FilterId::index(NonZero::new(band_idx + 1).unwrap());
         let update = self.eq.build_filter_update(band_idx, sample_rate);
         self.apply_updates(node_id, [(band_id, update)]);
     }
@@ -384,10 +572,10 @@ where
     fn sync(&self, node_id: u32, sample_rate: u32) {
         let mut updates = Vec::with_capacity(self.eq.filters.len() + 1);
 
-        updates.push((FilterId::Preamp, self.eq.build_preamp_update()));
+        updates.push((FilterId::preamp(), self.eq.build_preamp_update()));
 
         for idx in 0..self.eq.filters.len() {
-            let id = FilterId::Index(NonZero::new(idx + 1).unwrap());
+            let id = FilterId::index(NonZero::new(idx + 1).unwrap());
             updates.push((id, self.eq.build_filter_update(idx, sample_rate)));
         }
 
@@ -407,6 +595,7 @@ where
         match &self.input_mode {
             InputMode::Normal => self.handle_normal_key(key),
             InputMode::Command => self.handle_command_key(key),
+            InputMode::Picker | InputMode::Streams => self.handle_picker_key(key),
         }
     }
 
@@ -422,6 +611,107 @@ where
         Ok(ControlFlow::Continue(()))
     }
 
+    fn handle_picker_key(&mut self, key: KeyEvent) -> io::Result<ControlFlow<()>> {
+        assert!(matches!(
+            self.input_mode,
+            InputMode::Picker | InputMode::Streams
+        ));
+        match self.config.keymap.get(&self.input_mode, &key) {
+            Some(action) => self.perform(*action),
+            None => Ok(ControlFlow::Continue(())),
+        }
+    }
+
+    fn open_target_picker(&mut self) {
+        let notifs_tx = self.notifs_tx.clone();
+        let backend = self.backend.clone();
+        let direction = self.eq.direction;
+        self.schedule(async move {
+            let devices = list_devices(&*backend, direction)
+                .await
+                .map_err(|err| err.to_string())?;
+            let _ = notifs_tx.send(Notif::Devices(devices)).await;
+            Ok(None)
+        });
+    }
+
+    fn open_stream_pane(&mut self) {
+        let notifs_tx = self.notifs_tx.clone();
+        let backend = self.backend.clone();
+        let direction = self.eq.direction;
+        self.schedule(async move {
+            let streams = routing::list_streams(&*backend)
+                .await
+                .map_err(|err| err.to_string())?
+                .into_iter()
+                .filter(|stream| stream.direction == direction)
+                .collect();
+            let _ = notifs_tx.send(Notif::Streams(streams)).await;
+            Ok(None)
+        });
+    }
+
+    /// Move the selected stream onto the EQ, or put it back if it was already moved
+    fn toggle_stream_route(&mut self) {
+        let Some(pane) = &self.stream_pane else {
+            return;
+        };
+        let Some(stream) = pane.streams.get(pane.selected).cloned() else {
+            return;
+        };
+
+        let backend = self.backend.clone();
+        let notifs_tx = self.notifs_tx.clone();
+        // Marked as routed once the change is made, so a failed one is not undone on exit
+        match self.routed.get(&stream.id).cloned() {
+            Some(before) => self.schedule(async move {
+                routing::restore_stream(&*backend, &before)
+                    .await
+                    .map_err(|err| err.to_string())?;
+                let _ = notifs_tx.send(Notif::Restored(before.id)).await;
+                Ok(Some(format!("Restored {}", before.app)))
+            }),
+            None => {
+                let Some(node_id) = self.active_node_id else {
+                    self.status = Some(Err("no EQ loaded to route onto".to_string()));
+                    return;
+                };
+                self.schedule(async move {
+                    routing::route_stream(&*backend, &stream, node_id)
+                        .await
+                        .map_err(|err| format!("{err:#}"))?;
+                    let status = format!("Moved {} onto the EQ", stream.app);
+                    let _ = notifs_tx.send(Notif::Routed(stream)).await;
+                    Ok(Some(status))
+                });
+            }
+        }
+    }
+
+    fn confirm_target(&mut self) {
+        let Some(picker) = self.picker.take() else {
+            return;
+        };
+        self.input_mode = InputMode::Normal;
+
+        let target = match picker.selected {
+            0 => None,
+            idx => Some(picker.devices[idx - 1].name.clone()),
+        };
+        if target == self.eq.target {
+            return;
+        }
+
+        self.status = Some(Ok(format!(
+            "Bound to {}",
+            target.as_deref().unwrap_or("the default device")
+        )));
+        self.eq.target = target;
+        if self.active_node_id.is_some() {
+            self.load_module();
+        }
+    }
+
     fn cycle_view_mode(&mut self, _rotation: Rotation) {
         self.view_mode = match self.view_mode {
             ViewMode::Normal => ViewMode::Expert,
@@ -440,6 +730,8 @@ where
             Action::EnterMode { mode } => match mode {
                 InputMode::Normal => self.enter_normal_mode(),
                 InputMode::Command => self.enter_command_mode(),
+                InputMode::Picker => self.open_target_picker(),
+                InputMode::Streams => self.open_stream_pane(),
             },
             Action::ClearStatus => self.status = None,
             Action::ToggleHelp => self.show_help = !self.show_help,
@@ -494,6 +786,28 @@ where
             }
             Action::MoveCursorHome => self.command_cursor_pos = 0,
             Action::MoveCursorEnd => self.command_cursor_pos = self.command_buffer.len(),
+            Action::PickerNext => {
+                if let Some(picker) = &mut self.picker {
+                    picker.selected = (picker.selected + 1).min(picker.devices.len());
+                }
+            }
+            Action::PickerPrevious => {
+                if let Some(picker) = &mut self.picker {
+                    picker.selected = picker.selected.saturating_sub(1);
+                }
+            }
+            Action::PickerConfirm => self.confirm_target(),
+            Action::StreamNext => {
+                if let Some(pane) = &mut self.stream_pane {
+                    pane.selected = (pane.selected + 1).min(pane.streams.len().saturating_sub(1));
+                }
+            }
+            Action::StreamPrevious => {
+                if let Some(pane) = &mut self.stream_pane {
+                    pane.selected = pane.selected.saturating_sub(1);
+                }
+            }
+            Action::ToggleStreamRoute => self.toggle_stream_route(),
         }
 
         if let Some(node_id) = self.active_node_id {
@@ -531,14 +845,49 @@ where
     }
 
     fn load_module(&mut self) {
-        let _ = self.pw_tx.send(pw::Message::LoadModule {
-            name: "libpipewire-module-filter-chain".into(),
-            args: Box::new(self.eq.to_module_args(self.sample_rate)),
+        let args = match self
+            .eq
+            .to_module_args(self.sample_rate)
+            .and_then(|args| args.validate().map(|()| args))
+        {
+            Ok(args) => args,
+            Err(err) => {
+                tracing::error!(error = ?err, "failed to build module");
+                // The status line can't show the multi-line list of graph problems
+                let err = format!("{err:#}");
+                let err = err.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+                self.status = Some(Err(format!("failed to build module: {err}")));
+                return;
+            }
+        };
+
+        tracing::info!(
+            band_count = args.filter_graph.nodes.len(),
+            "Loading new module"
+        );
+        self.loading += 1;
+        let notifs_tx = self.notifs_tx.clone();
+        let modules = self.modules.clone();
+        let profile = self.eq.name.clone();
+        // Spawned rather than scheduled, so a load is never dropped between loading the module
+        // and recording it in the manager
+        while self.loads.try_join_next().is_some() {}
+        self.loads.spawn(async move {
+            // Replaces the module loaded before, moving its streams onto the new EQ
+            let loaded = modules
+                .lock()
+                .await
+                .load(&profile, args)
+                .await
+                .map_err(|err| format!("failed to load module: {err:#}"));
+            let _ = notifs_tx.send(Notif::ModuleLoaded(loaded)).await;
         });
     }
 
     fn enter_normal_mode(&mut self) {
         self.input_mode = InputMode::Normal;
+        self.picker = None;
+        self.stream_pane = None;
     }
 
     fn enter_command_mode(&mut self) {
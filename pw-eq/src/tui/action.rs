@@ -29,6 +29,12 @@ pub enum Action {
     MoveCursorRight,
     MoveCursorHome,
     MoveCursorEnd,
+    PickerNext,
+    PickerPrevious,
+    PickerConfirm,
+    StreamNext,
+    StreamPrevious,
+    ToggleStreamRoute,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -72,6 +78,8 @@ impl Action {
             Action::EnterMode { mode } => match mode {
                 InputMode::Normal => Some("normal mode"),
                 InputMode::Command => Some("command mode"),
+                InputMode::Picker => Some("output device"),
+                InputMode::Streams => Some("route streams"),
             },
             Action::ExecuteCommand
             | Action::ClearStatus
@@ -82,7 +90,13 @@ impl Action {
             | Action::MoveCursorLeft
             | Action::MoveCursorRight
             | Action::MoveCursorHome
-            | Action::MoveCursorEnd => None,
+            | Action::MoveCursorEnd
+            | Action::PickerNext
+            | Action::PickerPrevious
+            | Action::PickerConfirm
+            | Action::StreamNext
+            | Action::StreamPrevious
+            | Action::ToggleStreamRoute => None,
         }
     }
 }
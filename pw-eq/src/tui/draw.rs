@@ -1,5 +1,6 @@
-use super::{App, Eq, InputMode, ViewMode, theme::Theme};
+use super::{App, Eq, InputMode, StreamPane, TargetPicker, ViewMode, theme::Theme};
 use pw_util::module::FilterType;
+use pw_util::routing::Stream;
 use ratatui::{
     layout::Direction,
     prelude::{Backend, Constraint, Layout, Rect},
@@ -7,9 +8,11 @@ use ratatui::{
     symbols::Marker,
     text::{Line, Span},
     widgets::{
-        Axis, Block, Borders, Cell, Chart, Dataset, GraphType, Padding, Paragraph, Row, Table, Wrap,
+        Axis, Block, Borders, Cell, Chart, Clear, Dataset, GraphType, List, ListItem, ListState,
+        Padding, Paragraph, Row, Table, Wrap,
     },
 };
+use std::collections::HashMap;
 use std::io;
 
 impl<B> App<B>
@@ -68,10 +71,13 @@ where
             let mut header_spans = vec![
                 Span::styled(
                     format!(
-                        "PipeWire EQ: {} | Bands: {}/{} | Sample Rate: {:.0} Hz | Preamp: ",
+                        "PipeWire EQ: {} ({}) | Device: {} | Bands: {}/{} | Channels: {} | Sample Rate: {:.0} Hz | Preamp: ",
                         eq.name,
+                        eq.direction.media_class(),
+                        eq.target.as_deref().unwrap_or("default"),
                         eq.filters.len(),
                         eq.max_filters,
+                        eq.layout,
                         sample_rate
                     ),
                     Style::default().fg(theme.header),
@@ -119,9 +125,23 @@ where
                 InputMode::Normal => {
                     Paragraph::new("Press ? for help").style(Style::default().fg(theme.footer))
                 }
+                InputMode::Picker => Paragraph::new("Select the device to bind the EQ to")
+                    .style(Style::default().fg(theme.footer)),
+                InputMode::Streams => {
+                    Paragraph::new("Select a stream to move onto the EQ, or back off it")
+                        .style(Style::default().fg(theme.footer))
+                }
             };
             f.render_widget(footer, chunks[3]);
 
+            if let Some(picker) = &self.picker {
+                draw_target_picker(f, chunks[1], picker, eq.target.as_deref(), theme);
+            }
+
+            if let Some(pane) = &self.stream_pane {
+                draw_stream_pane(f, chunks[1], pane, &self.routed, theme);
+            }
+
             if let InputMode::Command = &self.input_mode {
                 f.set_cursor_position((
                     chunks[3].x + 1 + self.command_cursor_pos as u16,
@@ -133,6 +153,105 @@ where
     }
 }
 
+fn draw_target_picker(
+    f: &mut ratatui::Frame,
+    area: Rect,
+    picker: &TargetPicker,
+    current: Option<&str>,
+    theme: &Theme,
+) {
+    let items = std::iter::once(ListItem::new("Default device"))
+        .chain(picker.devices.iter().map(|device| {
+            let marker = if current == Some(device.name.as_str()) {
+                "* "
+            } else {
+                ""
+            };
+            ListItem::new(format!("{marker}{} ({})", device.description, device.name))
+        }))
+        .collect::<Vec<_>>();
+
+    let height = (items.len() as u16 + 2).min(area.height);
+    let width = area.width.saturating_sub(8).min(80);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title(" Bind to device ")
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(theme.border)),
+        )
+        .style(Style::default().fg(theme.header).bg(theme.background))
+        .highlight_style(Style::default().bg(theme.selected_row));
+
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(
+        list,
+        popup,
+        &mut ListState::default().with_selected(Some(picker.selected)),
+    );
+}
+
+fn draw_stream_pane(
+    f: &mut ratatui::Frame,
+    area: Rect,
+    pane: &StreamPane,
+    routed: &HashMap<u32, Stream>,
+    theme: &Theme,
+) {
+    let items = if pane.streams.is_empty() {
+        vec![ListItem::new("No streams")]
+    } else {
+        pane.streams
+            .iter()
+            .map(|stream| {
+                let marker = if routed.contains_key(&stream.id) {
+                    "* "
+                } else {
+                    ""
+                };
+                let label = match &stream.media_name {
+                    Some(media_name) => format!("{marker}{}: {media_name}", stream.app),
+                    None => format!("{marker}{}", stream.app),
+                };
+                ListItem::new(label)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let height = (items.len() as u16 + 2).min(area.height);
+    let width = area.width.saturating_sub(8).min(80);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title(" Route streams ")
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(theme.border)),
+        )
+        .style(Style::default().fg(theme.header).bg(theme.background))
+        .highlight_style(Style::default().bg(theme.selected_row));
+
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(
+        list,
+        popup,
+        &mut ListState::default().with_selected(Some(pane.selected)),
+    );
+}
+
 fn draw_filters_table(
     f: &mut ratatui::Frame,
     area: Rect,
@@ -1,8 +1,8 @@
 use pw_util::{
     apo::{self, FilterType},
     module::{
-        self, Control, Module, ModuleArgs, NodeKind, ParamEqConfig, ParamEqFilter,
-        RateAndBiquadCoefficients, RawNodeConfig,
+        self, COMMON_SAMPLE_RATES, ChannelLayout, Control, Direction, ExtraProps, Module,
+        ModuleArgs, NodeKind, ParamEqConfig, ParamEqFilter, PluginStage, RawNodeConfig,
     },
 };
 use strum::IntoEnumIterator;
@@ -19,6 +19,15 @@ pub(super) struct Eq {
     pub max_filters: usize,
     pub preamp: f64, // dB
     pub bypassed: bool,
+    pub layout: ChannelLayout,
+    pub plugins: Vec<PluginStage>,
+    pub direction: Direction,
+    /// Node name of the device the EQ is bound to, if not the default
+    pub target: Option<String>,
+    /// Rates to generate raw biquad coefficients for
+    pub sample_rates: Vec<u32>,
+    /// Extra module args and stream props
+    pub props: ExtraProps,
 }
 
 impl Eq {
@@ -37,7 +46,11 @@ impl Eq {
             })
     }
 
-    pub fn with_filters(name: String, filters: impl IntoIterator<Item = Filter>) -> Self {
+    pub fn with_filters(
+        name: String,
+        layout: ChannelLayout,
+        filters: impl IntoIterator<Item = Filter>,
+    ) -> Self {
         let filters = filters.into_iter().collect::<Vec<_>>();
         Self {
             name,
@@ -50,12 +63,19 @@ impl Eq {
             selected_idx: 0,
             max_filters: 31,
             bypassed: false,
+            layout,
+            plugins: Vec::new(),
+            direction: Direction::Sink,
+            target: None,
+            sample_rates: COMMON_SAMPLE_RATES.to_vec(),
+            props: ExtraProps::default(),
         }
     }
 
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, layout: ChannelLayout) -> Self {
         Self::with_filters(
             name,
+            layout,
             [
                 Filter {
                     frequency: 50.0,
@@ -186,20 +206,28 @@ impl Eq {
         self.bypassed = !self.bypassed;
     }
 
-    pub fn to_module_args(&self, rate: u32) -> ModuleArgs {
-        Module::from_kinds(
+    /// Module args with raw coefficients for each configured rate, plus the current `rate`
+    pub fn to_module_args(&self, rate: u32) -> anyhow::Result<ModuleArgs> {
+        let mut rates = self.sample_rates.clone();
+        rates.push(rate);
+        rates.sort_unstable();
+        rates.dedup();
+
+        let mut module = Module::from_kinds(
             &format!("{}-{}", self.name, self.filters.len()),
             self.preamp,
+            &self.layout,
             self.filters.iter().map(|band| NodeKind::Raw {
-                config: RawNodeConfig {
-                    coefficients: vec![RateAndBiquadCoefficients {
-                        rate,
-                        coefficients: band.biquad_coeffs(rate as f64),
-                    }],
-                },
+                config: RawNodeConfig::for_rates(rates.iter().copied(), |rate| {
+                    band.biquad_coeffs(rate)
+                }),
             }),
         )
-        .args
+        .with_direction(self.direction)
+        .with_target(self.target.clone())
+        .with_props(&self.props)?;
+        module.append_plugins(&self.plugins);
+        Ok(module.args)
     }
 
     /// Save current EQ configuration to a PipeWire filter-chain config file using param_eq
@@ -210,9 +238,10 @@ impl Eq {
     ) -> anyhow::Result<()> {
         let data = match format {
             Format::PwParamEq => {
-                let config = module::Config::from_kinds(
+                let mut module = Module::from_kinds(
                     &self.name,
                     self.preamp,
+                    &self.layout,
                     [NodeKind::ParamEq {
                         config: ParamEqConfig {
                             filters: self
@@ -224,14 +253,20 @@ impl Eq {
                                         freq: band.frequency,
                                         q: band.q,
                                         gain: band.gain,
+                                        ..Default::default()
                                     },
                                 })
                                 .collect(),
+                            ..Default::default()
                         },
                     }],
-                );
+                )
+                .with_direction(self.direction)
+                .with_target(self.target.clone())
+                .with_props(&self.props)?;
+                module.append_plugins(&self.plugins);
 
-                pw_util::to_spa_json(&config)
+                pw_util::to_spa_json(&module::Config::from(module))
             }
             Format::Apo => apo::Config {
                 preamp: self.preamp,
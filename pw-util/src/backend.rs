@@ -0,0 +1,328 @@
+//! The PipeWire operations pw-eq depends on, behind a trait so its flows can run against an
+//! in-memory graph
+
+#[cfg(any(test, feature = "fake"))]
+mod fake;
+mod manager;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::pin::Pin;
+
+use anyhow::{Context as _, Result};
+use futures_core::Stream;
+use pipewire::{channel, context::ContextRc, main_loop::MainLoopRc};
+use tokio::sync::{Mutex, oneshot};
+
+use crate::PwDumpObject;
+use crate::api::{self, ImplModule};
+use crate::control::{self, Controls};
+use crate::events::Event;
+use crate::metadata::{self, ClockSettings, DefaultNode, RouteOrigin};
+use crate::module::{Direction, ModuleArgs};
+
+#[cfg(any(test, feature = "fake"))]
+pub use self::fake::{Call, Fake};
+pub use self::manager::{LoadedModule, ModuleManager, wait_for_eq_node};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+pub type BoxStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+
+pub trait PwBackend: Send + Sync {
+    /// Every global with its info and params, in the model `pw-dump` produces
+    fn dump(&self) -> BoxFuture<'_, Result<Vec<PwDumpObject>>>;
+
+    /// Graph changes, starting with a `GlobalAdded` for every existing global
+    fn events(&self) -> BoxFuture<'_, Result<BoxStream<Event>>>;
+
+    fn get_default(&self, direction: Direction) -> BoxFuture<'_, Result<Option<DefaultNode>>>;
+
+    /// Make a sink the default sink, or a source the default source
+    fn set_default(&self, node_id: u32) -> BoxFuture<'_, Result<DefaultNode>>;
+
+    /// Clear the configured default sink or source, so the session manager picks one
+    fn clear_default(&self, direction: Direction) -> BoxFuture<'_, Result<()>>;
+
+    fn clock_settings(&self) -> BoxFuture<'_, Result<ClockSettings>>;
+
+    /// Set filter-chain controls (e.g. `pweq.filter_1:Freq`) on a node in one update
+    fn set_controls(&self, node_id: u32, controls: Vec<(String, f32)>)
+    -> BoxFuture<'_, Result<()>>;
+
+    /// Load a module into this process. It stays loaded until it is unloaded or the backend
+    /// is dropped.
+    fn load_module(&self, name: String, args: ModuleArgs) -> BoxFuture<'_, Result<u32>>;
+
+    fn unload_module(&self, module_id: u32) -> BoxFuture<'_, Result<()>>;
+
+    /// Destroy a global owned by another client, e.g. the nodes of a module the daemon loaded
+    fn destroy(&self, id: u32) -> BoxFuture<'_, Result<()>>;
+
+    /// The node each stream is pinned to, by stream id
+    fn stream_targets(&self) -> BoxFuture<'_, Result<HashMap<u32, u32>>>;
+
+    /// Ask the session manager to move a stream onto a node, or with `None` to let it follow
+    /// the default again
+    fn set_stream_target(&self, stream_id: u32, node_id: Option<u32>) -> BoxFuture<'_, Result<()>>;
+
+    /// Where each stream routed onto an EQ was before, by stream id
+    fn route_origins(&self) -> BoxFuture<'_, Result<HashMap<u32, RouteOrigin>>>;
+
+    /// Record where a stream was before it was routed, or with `None` forget it
+    fn set_route_origin(
+        &self,
+        stream_id: u32,
+        origin: Option<RouteOrigin>,
+    ) -> BoxFuture<'_, Result<()>>;
+}
+
+/// The PipeWire daemon, reached through the native client
+#[derive(Default)]
+pub struct PipeWire {
+    // Started on the first module load or control update, as most commands never need it
+    host: Mutex<Option<ModuleHost>>,
+    use_pw_dump: bool,
+}
+
+impl PipeWire {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Query the graph by running `pw-dump` instead of with the native registry client
+    pub fn with_pw_dump(mut self, enabled: bool) -> Self {
+        self.use_pw_dump = enabled;
+        self
+    }
+
+    async fn host(&self) -> Result<channel::Sender<HostMessage>> {
+        let mut host = self.host.lock().await;
+        if let Some(host) = &*host {
+            return Ok(host.tx.clone());
+        }
+
+        let spawned = ModuleHost::spawn().await?;
+        let tx = spawned.tx.clone();
+        *host = Some(spawned);
+        Ok(tx)
+    }
+
+    async fn request<T>(
+        &self,
+        message: impl FnOnce(oneshot::Sender<Result<T>>) -> HostMessage,
+    ) -> Result<T> {
+        let (reply, rx) = oneshot::channel();
+        if self.host().await?.send(message(reply)).is_err() {
+            anyhow::bail!("PipeWire module thread is not running");
+        }
+        rx.await.context("PipeWire module thread exited")?
+    }
+}
+
+impl PwBackend for PipeWire {
+    fn dump(&self) -> BoxFuture<'_, Result<Vec<PwDumpObject>>> {
+        if self.use_pw_dump {
+            Box::pin(crate::pw_dump())
+        } else {
+            Box::pin(crate::dump())
+        }
+    }
+
+    fn events(&self) -> BoxFuture<'_, Result<BoxStream<Event>>> {
+        Box::pin(async {
+            let events: BoxStream<Event> = Box::pin(crate::events::events().await?);
+            Ok(events)
+        })
+    }
+
+    fn get_default(&self, direction: Direction) -> BoxFuture<'_, Result<Option<DefaultNode>>> {
+        Box::pin(crate::blocking(move || metadata::get_default(direction)))
+    }
+
+    fn set_default(&self, node_id: u32) -> BoxFuture<'_, Result<DefaultNode>> {
+        Box::pin(crate::set_default(node_id))
+    }
+
+    fn clear_default(&self, direction: Direction) -> BoxFuture<'_, Result<()>> {
+        Box::pin(crate::blocking(move || metadata::clear_default(direction)))
+    }
+
+    fn clock_settings(&self) -> BoxFuture<'_, Result<ClockSettings>> {
+        Box::pin(crate::get_clock_settings())
+    }
+
+    fn set_controls(
+        &self,
+        node_id: u32,
+        controls: Vec<(String, f32)>,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let pod =
+                control::props_pod(controls.iter().map(|(name, value)| (name.as_str(), *value)))?;
+            match pod {
+                Some(pod) => {
+                    self.request(|reply| HostMessage::SetControls {
+                        node_id,
+                        pod,
+                        reply,
+                    })
+                    .await
+                }
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn load_module(&self, name: String, args: ModuleArgs) -> BoxFuture<'_, Result<u32>> {
+        let args = crate::to_spa_json(&args);
+        Box::pin(self.request(|reply| HostMessage::Load { name, args, reply }))
+    }
+
+    fn unload_module(&self, module_id: u32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.request(move |reply| HostMessage::Unload { module_id, reply }))
+    }
+
+    fn destroy(&self, id: u32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(crate::destroy(id))
+    }
+
+    fn stream_targets(&self) -> BoxFuture<'_, Result<HashMap<u32, u32>>> {
+        Box::pin(crate::blocking(metadata::get_stream_targets))
+    }
+
+    fn set_stream_target(&self, stream_id: u32, node_id: Option<u32>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(crate::blocking(move || {
+            metadata::set_stream_target(stream_id, node_id)
+        }))
+    }
+
+    fn route_origins(&self) -> BoxFuture<'_, Result<HashMap<u32, RouteOrigin>>> {
+        Box::pin(crate::blocking(metadata::get_route_origins))
+    }
+
+    fn set_route_origin(
+        &self,
+        stream_id: u32,
+        origin: Option<RouteOrigin>,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(crate::blocking(move || {
+            metadata::set_route_origin(stream_id, origin)
+        }))
+    }
+}
+
+enum HostMessage {
+    Load {
+        name: String,
+        args: String,
+        reply: oneshot::Sender<Result<u32>>,
+    },
+    Unload {
+        module_id: u32,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    SetControls {
+        node_id: u32,
+        pod: Vec<u8>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Terminate,
+}
+
+/// A PipeWire loop thread that owns the modules loaded into this process, and the nodes bound
+/// to set controls on
+struct ModuleHost {
+    tx: channel::Sender<HostMessage>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl ModuleHost {
+    async fn spawn() -> Result<Self> {
+        let (tx, rx) = channel::channel();
+        let (ready_tx, ready) = oneshot::channel();
+        let thread = std::thread::Builder::new()
+            .name("pw-modules".to_string())
+            .spawn(move || host_thread(rx, ready_tx))
+            .context("failed to spawn PipeWire module thread")?;
+
+        ready.await.context("PipeWire module thread exited")??;
+        Ok(Self {
+            tx,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for ModuleHost {
+    fn drop(&mut self) {
+        let _ = self.tx.send(HostMessage::Terminate);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn host_thread(rx: channel::Receiver<HostMessage>, ready: oneshot::Sender<Result<()>>) {
+    let setup = || -> Result<(MainLoopRc, ContextRc)> {
+        let mainloop = MainLoopRc::new(None).context("failed to create PipeWire main loop")?;
+        let context =
+            ContextRc::new(&mainloop, None).context("failed to create PipeWire context")?;
+        Ok((mainloop, context))
+    };
+    let (mainloop, context) = match setup() {
+        Ok(setup) => setup,
+        Err(err) => {
+            let _ = ready.send(Err(err));
+            return;
+        }
+    };
+
+    // Modules are unloaded when the attached receiver is dropped, after the loop quits
+    let modules = RefCell::new(HashMap::<u32, ImplModule>::new());
+    // Connected on the first update, and again if the daemon drops the connection
+    let controls = RefCell::new(None::<Controls>);
+    let _rx = rx.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |message| match message {
+            HostMessage::Load { name, args, reply } => {
+                let result = api::load_module(&context, &name, &args).map(|module| {
+                    let id = module.info().id();
+                    modules.borrow_mut().insert(id, module);
+                    id
+                });
+                let _ = reply.send(result);
+            }
+            HostMessage::Unload { module_id, reply } => {
+                let result = modules
+                    .borrow_mut()
+                    .remove(&module_id)
+                    .map(drop)
+                    .with_context(|| format!("module {module_id} was not loaded by this process"));
+                let _ = reply.send(result);
+            }
+            HostMessage::SetControls {
+                node_id,
+                pod,
+                reply,
+            } => {
+                let mut controls = controls.borrow_mut();
+                if controls.as_ref().is_none_or(Controls::is_broken) {
+                    match Controls::new(&context) {
+                        Ok(connected) => *controls = Some(connected),
+                        Err(err) => {
+                            let _ = reply.send(Err(err));
+                            return;
+                        }
+                    }
+                }
+                if let Some(controls) = &*controls {
+                    controls.set(node_id, &pod, reply);
+                }
+            }
+            HostMessage::Terminate => mainloop.quit(),
+        }
+    });
+
+    let _ = ready.send(Ok(()));
+    mainloop.run();
+}
@@ -0,0 +1,631 @@
+//! An in-memory graph that implements `PwBackend`, for testing flows without a PipeWire daemon
+
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use anyhow::{Context as _, Result};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use super::{BoxFuture, BoxStream, PwBackend};
+use crate::apo;
+use crate::events::Event;
+use crate::metadata::{ClockSettings, DefaultNode, RouteOrigin};
+use crate::module::{
+    ChannelLayout, Direction, FILTER_CHAIN_MODULE, MANAGED_PROP, Module, ModuleArgs,
+};
+use crate::registry::Param;
+use crate::{Prop, PwDumpObject, PwObjectInfo, PwObjectType};
+
+/// A request a `Fake` received, recorded in the order it arrived
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    SetDefault(u32),
+    ClearDefault(Direction),
+    SetControls {
+        node_id: u32,
+        controls: Vec<(String, f32)>,
+    },
+    LoadModule {
+        name: String,
+        media_name: String,
+    },
+    UnloadModule(u32),
+    Destroy(u32),
+    SetStreamTarget {
+        stream_id: u32,
+        node_id: Option<u32>,
+    },
+    SetRouteOrigin {
+        stream_id: u32,
+        origin: Option<RouteOrigin>,
+    },
+}
+
+/// A scriptable graph. Loading a filter-chain module adds the two nodes PipeWire would create
+/// for it, and every change is also sent to open event streams.
+#[derive(Default)]
+pub struct Fake {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    objects: BTreeMap<u32, PwDumpObject>,
+    next_id: u32,
+    default_sink: Option<String>,
+    default_source: Option<String>,
+    clock: ClockSettings,
+    /// Objects created by each loaded module
+    modules: HashMap<u32, Vec<u32>>,
+    /// The node each stream is pinned to
+    stream_targets: HashMap<u32, u32>,
+    /// Where each routed stream was before
+    route_origins: HashMap<u32, RouteOrigin>,
+    calls: Vec<Call>,
+    fail_next: Option<String>,
+    subscribers: Vec<mpsc::UnboundedSender<Event>>,
+}
+
+impl Fake {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from a recorded graph, e.g. `pw-dump` output parsed with `parse_dump`
+    pub fn with_objects(objects: impl IntoIterator<Item = PwDumpObject>) -> Self {
+        let fake = Self::new();
+        {
+            let mut state = fake.state();
+            state.objects = objects.into_iter().map(|obj| (obj.id, obj)).collect();
+            state.next_id = state.objects.keys().last().map_or(0, |id| id + 1);
+        }
+        fake
+    }
+
+    /// Add a node with the given props (a JSON object), returning its id
+    pub fn add_node(&self, props: Value) -> u32 {
+        self.state().add(PwObjectType::Node, props)
+    }
+
+    /// Link two nodes, as the session manager would for each channel of a stream
+    pub fn add_link(&self, output: u32, input: u32) -> u32 {
+        self.state().add_link(output, input)
+    }
+
+    /// The nodes a node is linked to, in either direction, one entry per link
+    pub fn links(&self, node_id: u32) -> Vec<u32> {
+        let state = self.state();
+        state
+            .objects
+            .values()
+            .filter_map(PwDumpObject::link_nodes)
+            .filter_map(
+                |(output, input)| match (output == node_id, input == node_id) {
+                    (true, _) => Some(input),
+                    (_, true) => Some(output),
+                    _ => None,
+                },
+            )
+            .collect()
+    }
+
+    /// Remove a global, as if its owner destroyed it
+    pub fn remove(&self, id: u32) {
+        self.state().remove(id);
+    }
+
+    /// Change the graph clock, as if `clock.rate` or `clock.force-rate` were set
+    pub fn set_clock(&self, clock: ClockSettings) {
+        let mut state = self.state();
+        state.clock = clock;
+        state.send(Event::ClockChanged { clock });
+    }
+
+    /// Make the next request fail with `message`
+    pub fn fail_next(&self, message: impl Into<String>) {
+        self.state().fail_next = Some(message.into());
+    }
+
+    /// The requests received so far
+    pub fn calls(&self) -> Vec<Call> {
+        self.state().calls.clone()
+    }
+
+    pub fn object(&self, id: u32) -> Option<PwDumpObject> {
+        self.state().objects.get(&id).cloned()
+    }
+
+    /// The ids of the objects a loaded module created
+    pub fn module_objects(&self, module_id: u32) -> Option<Vec<u32>> {
+        self.state().modules.get(&module_id).cloned()
+    }
+
+    /// Module args for an EQ with no filters
+    pub fn eq_args(name: &str, direction: Direction) -> ModuleArgs {
+        let apo = apo::Config {
+            preamp: 0.0,
+            filters: vec![],
+        };
+        Module::from_apo(name, &ChannelLayout::stereo(), &apo)
+            .with_direction(direction)
+            .args
+    }
+
+    /// Load an EQ with no filters, returning its EQ node
+    pub fn load_eq(&self, name: &str, direction: Direction) -> u32 {
+        self.load_eq_with(Self::eq_args(name, direction))
+    }
+
+    /// Load an EQ from its module args, returning its EQ node. Unlike with PipeWire, the node
+    /// exists as soon as the module is loaded.
+    pub fn load_eq_with(&self, args: ModuleArgs) -> u32 {
+        let module_id = self
+            .load(FILTER_CHAIN_MODULE.into(), args)
+            .expect("failed to load EQ");
+        let state = self.state();
+        state.modules[&module_id]
+            .iter()
+            .copied()
+            .find(|id| state.objects[id].prop(MANAGED_PROP) == Some(&Value::Bool(true)))
+            .expect("EQ module has no EQ node")
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Load a module, adding the two nodes of a filter-chain
+    fn load(&self, name: String, args: ModuleArgs) -> Result<u32> {
+        let call = Call::LoadModule {
+            name: name.clone(),
+            media_name: args.media_name.clone(),
+        };
+        self.call(Some(call)).map(|mut state| {
+            let module_id = state.add(
+                PwObjectType::Module,
+                serde_json::json!({ "module.name": name }),
+            );
+
+            let link_group = format!("filter-chain-{module_id}");
+            let sides = [
+                (
+                    serde_json::to_value(&args.capture_props).unwrap_or_default(),
+                    "Stream/Input/Audio",
+                ),
+                (
+                    serde_json::to_value(&args.playback_props).unwrap_or_default(),
+                    "Stream/Output/Audio",
+                ),
+            ];
+            let mut objects = vec![module_id];
+            for (side, stream_class) in sides {
+                let props = filter_chain_node(&args, module_id, &link_group, side, stream_class);
+                objects.push(state.add(PwObjectType::Node, props));
+            }
+            state.modules.insert(module_id, objects);
+            module_id
+        })
+    }
+
+    /// Record a request, failing it if a failure was scripted
+    fn call(&self, call: Option<Call>) -> Result<std::sync::MutexGuard<'_, State>> {
+        let mut state = self.state();
+        state.calls.extend(call);
+        match state.fail_next.take() {
+            Some(message) => Err(anyhow::anyhow!(message)),
+            None => Ok(state),
+        }
+    }
+}
+
+impl State {
+    fn send(&mut self, event: Event) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    fn add(&mut self, object_type: PwObjectType, props: Value) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let props = match props {
+            Value::Object(props) => props.into_iter().collect::<HashMap<_, _>>(),
+            _ => HashMap::new(),
+        };
+        let object = PwDumpObject {
+            id,
+            object_type: object_type.clone(),
+            version: Some(3),
+            permissions: Some(["r", "w", "x", "m"].map(String::from).to_vec()),
+            info: PwObjectInfo {
+                props: props.clone(),
+                ..Default::default()
+            },
+            props: Some(props.clone()),
+        };
+        self.objects.insert(id, object);
+
+        self.send(Event::GlobalAdded {
+            id,
+            object_type: object_type.clone(),
+            props: props.clone(),
+        });
+        if object_type == PwObjectType::Node {
+            self.send(Event::NodeInfo {
+                id,
+                info: PwObjectInfo {
+                    props,
+                    ..Default::default()
+                },
+            });
+        }
+        id
+    }
+
+    /// Remove a global, and the links to it if it is a node
+    fn remove(&mut self, id: u32) {
+        let links = self
+            .objects
+            .values()
+            .filter(|obj| {
+                obj.link_nodes()
+                    .is_some_and(|(output, input)| output == id || input == id)
+            })
+            .map(|obj| obj.id)
+            .collect::<Vec<_>>();
+        for link in links {
+            self.remove(link);
+        }
+        if self.objects.remove(&id).is_some() {
+            self.send(Event::GlobalRemoved { id });
+        }
+    }
+
+    fn add_link(&mut self, output: u32, input: u32) -> u32 {
+        let props = serde_json::json!({
+            "link.output.node": output,
+            "link.input.node": input,
+        });
+        self.add(PwObjectType::Link, props)
+    }
+
+    /// Relink a stream onto a node right away, where the session manager would take its time
+    fn relink(&mut self, stream_id: u32, node_id: u32) -> Result<()> {
+        anyhow::ensure!(
+            self.objects.contains_key(&node_id),
+            "node {node_id} not found"
+        );
+        let links = self
+            .objects
+            .values()
+            .filter_map(|obj| Some((obj.id, obj.link_nodes()?)))
+            .filter(|(_, (output, input))| *output == stream_id || *input == stream_id)
+            .collect::<Vec<_>>();
+        for (id, (output, _)) in links {
+            self.remove(id);
+            if output == stream_id {
+                self.add_link(stream_id, node_id);
+            } else {
+                self.add_link(node_id, stream_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the streams that aren't pinned onto a new default node, except the streams of its
+    /// own filter-chain
+    fn follow_default(&mut self, node_id: u32, direction: Direction) -> Result<()> {
+        let link_group = self.objects[&node_id].prop("node.link-group").cloned();
+        let streams = self
+            .objects
+            .values()
+            .filter(|obj| !self.stream_targets.contains_key(&obj.id))
+            .filter(|obj| {
+                link_group.is_none() || obj.prop("node.link-group") != link_group.as_ref()
+            })
+            .map(|obj| obj.id)
+            .filter(|&id| self.stream_direction(id) == Some(direction))
+            .collect::<Vec<_>>();
+        for stream in streams {
+            self.relink(stream, node_id)?;
+        }
+        Ok(())
+    }
+
+    /// `Sink` for playback streams, `Source` for capture streams
+    fn stream_direction(&self, stream_id: u32) -> Option<Direction> {
+        let class = self
+            .objects
+            .get(&stream_id)?
+            .prop("media.class")?
+            .as_str()?;
+        match class {
+            "Stream/Output/Audio" => Some(Direction::Sink),
+            "Stream/Input/Audio" => Some(Direction::Source),
+            _ => None,
+        }
+    }
+
+    /// The default node a stream follows when it isn't pinned
+    fn stream_default(&self, stream_id: u32) -> Option<u32> {
+        let name = match self.stream_direction(stream_id)? {
+            Direction::Sink => self.default_sink.as_ref(),
+            Direction::Source => self.default_source.as_ref(),
+        };
+        self.default_node(name)?.id
+    }
+
+    fn node_name(&self, id: u32) -> Option<&str> {
+        self.objects.get(&id)?.info.props.get("node.name")?.as_str()
+    }
+
+    fn default_node(&self, name: Option<&String>) -> Option<DefaultNode> {
+        let name = name?;
+        let id = self
+            .objects
+            .values()
+            .find(|obj| {
+                obj.info.props.get("node.name").and_then(Value::as_str) == Some(name.as_str())
+            })
+            .map(|obj| obj.id);
+        Some(DefaultNode {
+            id,
+            name: name.clone(),
+        })
+    }
+}
+
+/// Props of one of the nodes a filter-chain module creates: the module-level props plus the
+/// props of its side, which is a stream unless it sets its own media class
+fn filter_chain_node(
+    args: &ModuleArgs,
+    module_id: u32,
+    link_group: &str,
+    side: Value,
+    stream_class: &str,
+) -> Value {
+    let mut props = match side {
+        Value::Object(props) => props,
+        _ => serde_json::Map::new(),
+    };
+    props
+        .entry("media.class")
+        .or_insert_with(|| stream_class.into());
+    props.insert("media.name".into(), args.media_name.clone().into());
+    props.insert(
+        "node.description".into(),
+        args.node_description.clone().into(),
+    );
+    props.insert("node.link-group".into(), link_group.into());
+    props.insert("module.id".into(), module_id.into());
+    Value::Object(props)
+}
+
+impl PwBackend for Fake {
+    fn dump(&self) -> BoxFuture<'_, Result<Vec<PwDumpObject>>> {
+        let result = self
+            .call(None)
+            .map(|state| state.objects.values().cloned().collect());
+        Box::pin(async { result })
+    }
+
+    fn events(&self) -> BoxFuture<'_, Result<BoxStream<Event>>> {
+        let result = self.call(None).map(|mut state| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            for obj in state.objects.values() {
+                let _ = tx.send(Event::GlobalAdded {
+                    id: obj.id,
+                    object_type: obj.object_type.clone(),
+                    props: obj.props.clone().unwrap_or_default(),
+                });
+                if obj.object_type == PwObjectType::Node {
+                    let _ = tx.send(Event::NodeInfo {
+                        id: obj.id,
+                        info: PwObjectInfo {
+                            props: obj.info.props.clone(),
+                            ..Default::default()
+                        },
+                    });
+                }
+            }
+            state.subscribers.push(tx);
+            let events: BoxStream<Event> = Box::pin(Receiver(rx));
+            events
+        });
+        Box::pin(async { result })
+    }
+
+    fn get_default(&self, direction: Direction) -> BoxFuture<'_, Result<Option<DefaultNode>>> {
+        let result = self.call(None).map(|state| match direction {
+            Direction::Sink => state.default_node(state.default_sink.as_ref()),
+            Direction::Source => state.default_node(state.default_source.as_ref()),
+        });
+        Box::pin(async { result })
+    }
+
+    fn set_default(&self, node_id: u32) -> BoxFuture<'_, Result<DefaultNode>> {
+        let result = self
+            .call(Some(Call::SetDefault(node_id)))
+            .and_then(|mut state| {
+                let node = state
+                    .objects
+                    .get(&node_id)
+                    .with_context(|| format!("node {node_id} not found"))?;
+                let direction = match node.info.props.get("media.class").and_then(Value::as_str) {
+                    Some(class) if class.contains("Sink") => Direction::Sink,
+                    Some(class) if class.contains("Source") => Direction::Source,
+                    _ => anyhow::bail!("node {node_id} is not a sink or source"),
+                };
+                let name = state.node_name(node_id).unwrap_or_default().to_string();
+                match direction {
+                    Direction::Sink => state.default_sink = Some(name.clone()),
+                    Direction::Source => state.default_source = Some(name.clone()),
+                }
+                state.follow_default(node_id, direction)?;
+
+                let node = DefaultNode {
+                    id: Some(node_id),
+                    name,
+                };
+                state.send(Event::DefaultChanged {
+                    direction,
+                    node: Some(node.clone()),
+                });
+                Ok(node)
+            });
+        Box::pin(async { result })
+    }
+
+    fn clear_default(&self, direction: Direction) -> BoxFuture<'_, Result<()>> {
+        let result = self
+            .call(Some(Call::ClearDefault(direction)))
+            .map(|mut state| {
+                // No other device for the session manager to fall back to
+                match direction {
+                    Direction::Sink => state.default_sink = None,
+                    Direction::Source => state.default_source = None,
+                }
+                state.send(Event::DefaultChanged {
+                    direction,
+                    node: None,
+                });
+            });
+        Box::pin(async { result })
+    }
+
+    fn clock_settings(&self) -> BoxFuture<'_, Result<ClockSettings>> {
+        let result = self.call(None).map(|state| state.clock);
+        Box::pin(async { result })
+    }
+
+    fn set_controls(
+        &self,
+        node_id: u32,
+        controls: Vec<(String, f32)>,
+    ) -> BoxFuture<'_, Result<()>> {
+        let call = Call::SetControls {
+            node_id,
+            controls: controls.clone(),
+        };
+        let result = self.call(Some(call)).and_then(|mut state| {
+            let node = state
+                .objects
+                .get_mut(&node_id)
+                .with_context(|| format!("node {node_id} not found"))?;
+
+            let props = &mut node.info.params.props;
+            let idx = match props.iter().position(|prop| !prop.params.0.is_empty()) {
+                Some(idx) => idx,
+                None => {
+                    props.push(Prop::default());
+                    props.len() - 1
+                }
+            };
+            let prop = &mut props[idx];
+            for (name, value) in controls {
+                prop.params.0.insert(name, Value::from(value));
+            }
+
+            let param = Param::Props(prop.clone());
+            state.send(Event::NodeParam { id: node_id, param });
+            Ok(())
+        });
+        Box::pin(async { result })
+    }
+
+    fn load_module(&self, name: String, args: ModuleArgs) -> BoxFuture<'_, Result<u32>> {
+        let result = self.load(name, args);
+        Box::pin(async { result })
+    }
+
+    fn unload_module(&self, module_id: u32) -> BoxFuture<'_, Result<()>> {
+        let result = self
+            .call(Some(Call::UnloadModule(module_id)))
+            .and_then(|mut state| {
+                let objects = state.modules.remove(&module_id).with_context(|| {
+                    format!("module {module_id} was not loaded by this process")
+                })?;
+                // The module's nodes go before the module itself
+                for id in objects.into_iter().rev() {
+                    state.remove(id);
+                }
+                Ok(())
+            });
+        Box::pin(async { result })
+    }
+
+    fn destroy(&self, id: u32) -> BoxFuture<'_, Result<()>> {
+        let result = self.call(Some(Call::Destroy(id))).and_then(|mut state| {
+            anyhow::ensure!(state.objects.contains_key(&id), "object {id} not found");
+            // Destroying a module unloads it, taking its nodes with it
+            let objects = state.modules.remove(&id).unwrap_or_else(|| vec![id]);
+            for id in objects.into_iter().rev() {
+                state.remove(id);
+            }
+            Ok(())
+        });
+        Box::pin(async { result })
+    }
+
+    fn stream_targets(&self) -> BoxFuture<'_, Result<HashMap<u32, u32>>> {
+        let result = self.call(None).map(|state| state.stream_targets.clone());
+        Box::pin(async { result })
+    }
+
+    fn set_stream_target(&self, stream_id: u32, node_id: Option<u32>) -> BoxFuture<'_, Result<()>> {
+        let call = Call::SetStreamTarget { stream_id, node_id };
+        let result = self.call(Some(call)).and_then(|mut state| {
+            let node_id = match node_id {
+                Some(node_id) => {
+                    state.stream_targets.insert(stream_id, node_id);
+                    Some(node_id)
+                }
+                None => {
+                    state.stream_targets.remove(&stream_id);
+                    state.stream_default(stream_id)
+                }
+            };
+            match node_id {
+                Some(node_id) => state.relink(stream_id, node_id),
+                // Nothing to follow, as with no default device
+                None => Ok(()),
+            }
+        });
+        Box::pin(async { result })
+    }
+
+    fn route_origins(&self) -> BoxFuture<'_, Result<HashMap<u32, RouteOrigin>>> {
+        let result = self.call(None).map(|state| state.route_origins.clone());
+        Box::pin(async { result })
+    }
+
+    fn set_route_origin(
+        &self,
+        stream_id: u32,
+        origin: Option<RouteOrigin>,
+    ) -> BoxFuture<'_, Result<()>> {
+        let call = Call::SetRouteOrigin { stream_id, origin };
+        let result = self.call(Some(call)).map(|mut state| match origin {
+            Some(origin) => {
+                state.route_origins.insert(stream_id, origin);
+            }
+            None => {
+                state.route_origins.remove(&stream_id);
+            }
+        });
+        Box::pin(async { result })
+    }
+}
+
+struct Receiver(mpsc::UnboundedReceiver<Event>);
+
+impl futures_core::Stream for Receiver {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.0.poll_recv(cx)
+    }
+}
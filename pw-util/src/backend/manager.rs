@@ -0,0 +1,428 @@
+//! Tracks the filter-chain modules this process loaded, one per profile, so replacing a profile
+//! moves its streams onto the new EQ and nothing is left behind on shutdown
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+
+use super::{BoxStream, PwBackend};
+use crate::PwDumpObject;
+use crate::events::Event;
+use crate::module::{Direction, FILTER_CHAIN_MODULE, MANAGED_PROP, ModuleArgs};
+
+/// How long a freshly loaded module gets to create its node
+const NODE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the session manager gets to move streams onto a replacement node before the old
+/// one is unloaded anyway
+const RELINK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A module loaded for a profile and the EQ node it created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadedModule {
+    pub module_id: u32,
+    pub node_id: u32,
+    pub direction: Direction,
+}
+
+/// The filter-chain modules loaded by this process, keyed by profile
+pub struct ModuleManager {
+    backend: Arc<dyn PwBackend>,
+    modules: BTreeMap<String, LoadedModule>,
+}
+
+impl ModuleManager {
+    pub fn new(backend: Arc<dyn PwBackend>) -> Self {
+        Self {
+            backend,
+            modules: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, profile: &str) -> Option<LoadedModule> {
+        self.modules.get(profile).copied()
+    }
+
+    pub fn loaded(&self) -> impl Iterator<Item = (&str, LoadedModule)> {
+        self.modules
+            .iter()
+            .map(|(profile, module)| (profile.as_str(), *module))
+    }
+
+    /// Load a filter-chain for a profile and wait for its EQ node. A module already loaded for
+    /// the profile is replaced: its streams are moved onto the new node before it is unloaded.
+    pub async fn load(&mut self, profile: &str, args: ModuleArgs) -> Result<LoadedModule> {
+        let direction = args.direction();
+        let media_name = args.media_name.clone();
+        let old = self.get(profile);
+
+        let module_id = self
+            .backend
+            .load_module(FILTER_CHAIN_MODULE.into(), args)
+            .await?;
+        // The replaced node has the same name, so it must not be mistaken for the new one
+        let except = old.map(|old| old.node_id);
+        let node_id = match wait_for_eq_node(
+            &*self.backend,
+            &media_name,
+            direction,
+            except.as_slice(),
+            NODE_TIMEOUT,
+        )
+        .await
+        {
+            Ok(node_id) => node_id,
+            Err(err) => {
+                let _ = self.backend.unload_module(module_id).await;
+                return Err(err);
+            }
+        };
+
+        let loaded = LoadedModule {
+            module_id,
+            node_id,
+            direction,
+        };
+        self.modules.insert(profile.to_string(), loaded);
+
+        if let Some(old) = old {
+            // Unload the old module even if its streams could not all be moved
+            let relinked = self.relink(old, node_id).await;
+            self.backend
+                .unload_module(old.module_id)
+                .await
+                .with_context(|| format!("failed to unload replaced module {}", old.module_id))?;
+            relinked.context("failed to move streams onto the replacement EQ")?;
+        }
+        Ok(loaded)
+    }
+
+    /// Unload the module loaded for a profile
+    pub async fn unload(&mut self, profile: &str) -> Result<()> {
+        let module = self
+            .modules
+            .remove(profile)
+            .with_context(|| format!("no module loaded for '{profile}'"))?;
+        self.backend.unload_module(module.module_id).await
+    }
+
+    /// Unload every module, attempting all of them before reporting the first failure
+    pub async fn shutdown(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for (profile, module) in std::mem::take(&mut self.modules) {
+            let unloaded = self
+                .backend
+                .unload_module(module.module_id)
+                .await
+                .with_context(|| format!("failed to unload module for '{profile}'"));
+            if result.is_ok() {
+                result = unloaded;
+            }
+        }
+        result
+    }
+
+    /// Move the old node's streams onto the new one, and wait (briefly) for the session manager
+    /// to link them so they don't fall back to another device in between. Streams pinned to the
+    /// old node are pinned to the new one, and if the old node was the default the new one
+    /// takes its place, which moves the streams that follow the default.
+    async fn relink(&self, old: LoadedModule, node_id: u32) -> Result<()> {
+        let objects = self.backend.dump().await?;
+        let targets = self.backend.stream_targets().await?;
+        let was_default = self
+            .backend
+            .get_default(old.direction)
+            .await?
+            .is_some_and(|default| default.id == Some(old.node_id));
+
+        let pinned = targets
+            .iter()
+            .filter(|&(_, &target)| target == old.node_id)
+            .map(|(&stream, _)| stream)
+            .collect::<BTreeSet<_>>();
+        let mut pending = linked_streams(&objects, old.node_id, old.direction);
+        pending.retain(|stream| {
+            pinned.contains(stream) || (was_default && !targets.contains_key(stream))
+        });
+
+        let mut events = self.backend.events().await?;
+        for &stream in &pinned {
+            self.backend
+                .set_stream_target(stream, Some(node_id))
+                .await?;
+        }
+        if was_default {
+            self.backend.set_default(node_id).await?;
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let linked = async {
+            while let Some(event) = next(&mut events).await {
+                let Event::GlobalAdded { props, .. } = event else {
+                    continue;
+                };
+                let node = |key| props.get(key)?.as_u64()?.try_into().ok();
+                if let (Some(output), Some(input)) =
+                    (node("link.output.node"), node("link.input.node"))
+                    && let Some(stream) = linked_stream(old.direction, node_id, output, input)
+                {
+                    pending.remove(&stream);
+                    if pending.is_empty() {
+                        break;
+                    }
+                }
+            }
+        };
+        let _ = tokio::time::timeout(RELINK_TIMEOUT, linked).await;
+        Ok(())
+    }
+}
+
+/// Wait for the node of an EQ to appear, ignoring the nodes in `except`. A module loaded
+/// in-process exports its node asynchronously, so right after loading it may not be in the
+/// registry yet.
+pub async fn wait_for_eq_node(
+    backend: &dyn PwBackend,
+    profile: &str,
+    direction: Direction,
+    except: &[u32],
+    timeout: Duration,
+) -> Result<u32> {
+    // The stream starts with the info of every existing node, so nothing can be missed
+    let mut events = backend.events().await?;
+    let wait = async {
+        while let Some(event) = next(&mut events).await {
+            let Event::NodeInfo { id, info } = event else {
+                continue;
+            };
+            let props = &info.props;
+            if !except.contains(&id)
+                && props.get(MANAGED_PROP) == Some(&true.into())
+                && props.get("media.name").and_then(|v| v.as_str()) == Some(profile)
+                && props.get("media.class").and_then(|v| v.as_str())
+                    == Some(direction.media_class())
+            {
+                return Ok(id);
+            }
+        }
+        anyhow::bail!("lost connection to PipeWire while waiting for EQ '{profile}'")
+    };
+
+    tokio::time::timeout(timeout, wait)
+        .await
+        .with_context(|| format!("timed out waiting for EQ '{profile}'"))?
+}
+
+/// The streams linked to an EQ node: the streams playing into a sink EQ, or recording from a
+/// source EQ
+fn linked_streams(objects: &[PwDumpObject], node_id: u32, direction: Direction) -> BTreeSet<u32> {
+    let is_stream = |id: u32| {
+        objects.iter().any(|obj| {
+            obj.id == id
+                && obj
+                    .prop("media.class")
+                    .and_then(|v| v.as_str())
+                    .is_some_and(|class| class.starts_with("Stream/"))
+        })
+    };
+    objects
+        .iter()
+        .filter_map(PwDumpObject::link_nodes)
+        .filter_map(|(output, input)| linked_stream(direction, node_id, output, input))
+        .filter(|&stream| is_stream(stream))
+        .collect()
+}
+
+/// The other end of a link that carries audio into a sink EQ or out of a source EQ
+fn linked_stream(direction: Direction, node_id: u32, output: u32, input: u32) -> Option<u32> {
+    match direction {
+        Direction::Sink if input == node_id => Some(output),
+        Direction::Source if output == node_id => Some(input),
+        _ => None,
+    }
+}
+
+async fn next(events: &mut BoxStream<Event>) -> Option<Event> {
+    std::future::poll_fn(|cx| events.as_mut().poll_next(cx)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::{ModuleManager, wait_for_eq_node};
+    use crate::backend::{Call, Fake, PwBackend as _};
+    use crate::module::Direction;
+
+    fn add_stream(fake: &Fake, node_id: u32) -> u32 {
+        let stream = fake.add_node(json!({
+            "node.name": "firefox",
+            "media.class": "Stream/Output/Audio",
+        }));
+        fake.add_link(stream, node_id);
+        fake.add_link(stream, node_id);
+        stream
+    }
+
+    #[tokio::test]
+    async fn test_replace_relinks_streams() {
+        let fake = Arc::new(Fake::new());
+        let mut manager = ModuleManager::new(fake.clone());
+
+        let old = manager
+            .load("pweq", Fake::eq_args("pweq", Direction::Sink))
+            .await
+            .unwrap();
+        fake.set_default(old.node_id).await.unwrap();
+        let following = add_stream(&fake, old.node_id);
+        let pinned = add_stream(&fake, old.node_id);
+        fake.set_stream_target(pinned, Some(old.node_id))
+            .await
+            .unwrap();
+
+        let new = manager
+            .load("pweq", Fake::eq_args("pweq", Direction::Sink))
+            .await
+            .unwrap();
+        assert_ne!(new.node_id, old.node_id);
+        assert_eq!(manager.get("pweq"), Some(new));
+        assert_eq!(fake.links(following), vec![new.node_id; 2]);
+        assert_eq!(fake.links(pinned), vec![new.node_id; 2]);
+
+        // Only the stream that was pinned is pinned to the new node
+        let targets = fake.stream_targets().await.unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[&pinned], new.node_id);
+
+        // The old module and both of its nodes are gone
+        assert_eq!(fake.module_objects(old.module_id), None);
+        assert!(fake.object(old.node_id).is_none());
+        assert!(fake.calls().ends_with(&[
+            Call::SetStreamTarget {
+                stream_id: pinned,
+                node_id: Some(new.node_id),
+            },
+            Call::SetDefault(new.node_id),
+            Call::UnloadModule(old.module_id),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_replace_leaves_other_streams() {
+        let fake = Arc::new(Fake::new());
+        let mut manager = ModuleManager::new(fake.clone());
+
+        // Linked to the EQ but neither pinned to it nor following it as the default
+        let old = manager
+            .load("pweq", Fake::eq_args("pweq", Direction::Sink))
+            .await
+            .unwrap();
+        let stream = add_stream(&fake, old.node_id);
+
+        manager
+            .load("pweq", Fake::eq_args("pweq", Direction::Sink))
+            .await
+            .unwrap();
+        assert!(fake.links(stream).is_empty());
+        assert!(
+            !fake
+                .calls()
+                .iter()
+                .any(|call| matches!(call, Call::SetStreamTarget { .. } | Call::SetDefault(_)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unload_and_shutdown() {
+        let fake = Arc::new(Fake::new());
+        let mut manager = ModuleManager::new(fake.clone());
+
+        let sink = manager
+            .load("music", Fake::eq_args("music", Direction::Sink))
+            .await
+            .unwrap();
+        let source = manager
+            .load("mic", Fake::eq_args("mic", Direction::Source))
+            .await
+            .unwrap();
+        let other = manager
+            .load("voice", Fake::eq_args("voice", Direction::Sink))
+            .await
+            .unwrap();
+
+        manager.unload("music").await.unwrap();
+        assert_eq!(fake.module_objects(sink.module_id), None);
+        assert_eq!(
+            manager.unload("music").await.unwrap_err().to_string(),
+            "no module loaded for 'music'"
+        );
+
+        // A failed unload doesn't stop the rest from being cleaned up
+        fake.fail_next("module is busy");
+        let err = manager.shutdown().await.unwrap_err();
+        assert_eq!(err.to_string(), "failed to unload module for 'mic'");
+        assert_eq!(manager.loaded().count(), 0);
+        assert!(fake.module_objects(source.module_id).is_some());
+        assert_eq!(fake.module_objects(other.module_id), None);
+    }
+
+    #[tokio::test]
+    async fn test_load_failure() {
+        let fake = Arc::new(Fake::new());
+        let mut manager = ModuleManager::new(fake.clone());
+
+        fake.fail_next("no such module");
+        let err = manager
+            .load("pweq", Fake::eq_args("pweq", Direction::Sink))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "no such module");
+        assert_eq!(manager.get("pweq"), None);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_eq_node() {
+        let fake = Fake::new();
+        let err = wait_for_eq_node(
+            &fake,
+            "late",
+            Direction::Sink,
+            &[],
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "timed out waiting for EQ 'late'");
+
+        // A node that appears while waiting is picked up from the event stream
+        let props = json!({
+            "node.name": "effect_output.pweq.late",
+            "media.name": "late",
+            "media.class": "Audio/Sink",
+            "pweq.managed": true,
+        });
+        let wait = wait_for_eq_node(&fake, "late", Direction::Sink, &[], Duration::from_secs(1));
+        let add = async { fake.add_node(props.clone()) };
+        let (found, added) = tokio::join!(wait, add);
+        assert_eq!(found.unwrap(), added);
+
+        // Excluded nodes are skipped
+        let err = wait_for_eq_node(
+            &fake,
+            "late",
+            Direction::Sink,
+            &[added],
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "timed out waiting for EQ 'late'");
+    }
+}
@@ -0,0 +1,273 @@
+//! Filter-chain controls, set live through a node's `Props` param
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use anyhow::Context as _;
+use pipewire::{
+    context::ContextRc,
+    core::{self, CoreRc, PW_ID_CORE},
+    node::Node,
+    permissions::PermissionFlags,
+    proxy::{ProxyListener, ProxyT},
+    registry::{self, GlobalObject, RegistryRc},
+    spa::{
+        param::ParamType,
+        pod::{Object, Pod, Property, Value as PodValue, serialize::PodSerializer},
+        sys::{SPA_PROP_params, SPA_TYPE_OBJECT_Props},
+        utils::{dict::DictRef, result::AsyncSeq},
+    },
+    types::ObjectType,
+};
+use tokio::sync::oneshot;
+
+/// An error PipeWire reported for a request, with its negative errno-style code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PwError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl fmt::Display for PwError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errno = std::io::Error::from_raw_os_error(-self.code);
+        write!(f, "{} ({errno})", self.message)
+    }
+}
+
+impl std::error::Error for PwError {}
+
+/// Serialize filter-chain controls into a single `Props` update, or `None` if there are none.
+/// Controls are named `<node>:<port>`, e.g. `pweq.filter_1:Freq`.
+pub fn props_pod<'a>(
+    controls: impl IntoIterator<Item = (&'a str, f32)>,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let params = controls
+        .into_iter()
+        .flat_map(|(name, value)| [PodValue::String(name.to_string()), PodValue::Float(value)])
+        .collect::<Vec<_>>();
+    if params.is_empty() {
+        return Ok(None);
+    }
+
+    let props = PodValue::Object(Object {
+        type_: SPA_TYPE_OBJECT_Props,
+        id: ParamType::Props.as_raw(),
+        properties: vec![Property::new(SPA_PROP_params, PodValue::Struct(params))],
+    });
+    let (bytes, _) = PodSerializer::serialize(Cursor::new(vec![]), &props)
+        .context("failed to serialize Props")?;
+    Ok(Some(bytes.into_inner()))
+}
+
+type Reply = oneshot::Sender<anyhow::Result<()>>;
+
+struct BoundNode {
+    node: Node,
+    error: Rc<RefCell<Option<PwError>>>,
+    _listener: ProxyListener,
+}
+
+/// A connection for setting controls that keeps each node it has updated bound, so a drag in
+/// the TUI is one `set_param` per step rather than a new connection. It must stay on the thread
+/// running the loop of the context it was created from.
+pub struct Controls {
+    // Fields drop in order: the bound nodes and listeners before the core they belong to
+    nodes: Rc<RefCell<HashMap<u32, BoundNode>>>,
+    // Updates waiting for the daemon to answer the sync sent after them
+    pending: Rc<RefCell<Vec<(AsyncSeq, u32, Reply)>>>,
+    broken: Rc<Cell<bool>>,
+    _core_listener: core::Listener,
+    _registry_listener: registry::Listener,
+    registry: RegistryRc,
+    core: CoreRc,
+}
+
+impl Controls {
+    pub fn new(context: &ContextRc) -> anyhow::Result<Self> {
+        let core = context
+            .connect_rc(None)
+            .context("failed to connect to PipeWire")?;
+        let registry = core.get_registry_rc().context("failed to get registry")?;
+        let nodes = Rc::new(RefCell::new(HashMap::<u32, BoundNode>::new()));
+        let pending = Rc::new(RefCell::new(Vec::<(AsyncSeq, u32, Reply)>::new()));
+        let broken = Rc::new(Cell::new(false));
+
+        let core_listener = core
+            .add_listener_local()
+            .done({
+                let nodes = nodes.clone();
+                let pending = pending.clone();
+                move |id, seq| {
+                    if id != PW_ID_CORE {
+                        return;
+                    }
+                    let done = pending
+                        .borrow_mut()
+                        .extract_if(.., |(pending, _, _)| *pending == seq)
+                        .collect::<Vec<_>>();
+                    for (_, node_id, reply) in done {
+                        let error = nodes
+                            .borrow()
+                            .get(&node_id)
+                            .and_then(|node| node.error.take());
+                        let result = match error {
+                            // The proxy may be dead, so bind the node again next time
+                            Some(error) => {
+                                nodes.borrow_mut().remove(&node_id);
+                                Err(anyhow::Error::new(error)
+                                    .context(format!("failed to set controls on node {node_id}")))
+                            }
+                            None => Ok(()),
+                        };
+                        let _ = reply.send(result);
+                    }
+                }
+            })
+            .error({
+                let pending = pending.clone();
+                let broken = broken.clone();
+                move |id, _seq, code, message| {
+                    // Errors on a node are reported to its proxy
+                    if id != PW_ID_CORE {
+                        return;
+                    }
+                    broken.set(true);
+                    let error = PwError {
+                        code,
+                        message: message.to_string(),
+                    };
+                    for (_, node_id, reply) in pending.borrow_mut().drain(..) {
+                        let _ = reply.send(Err(anyhow::Error::new(error.clone())
+                            .context(format!("failed to set controls on node {node_id}"))));
+                    }
+                }
+            })
+            .register();
+
+        let registry_listener = registry
+            .add_listener_local()
+            .global_remove({
+                let nodes = nodes.clone();
+                move |id| {
+                    nodes.borrow_mut().remove(&id);
+                }
+            })
+            .register();
+
+        Ok(Self {
+            nodes,
+            pending,
+            broken,
+            _core_listener: core_listener,
+            _registry_listener: registry_listener,
+            registry,
+            core,
+        })
+    }
+
+    /// Whether the connection failed, in which case a new one must be made
+    pub fn is_broken(&self) -> bool {
+        self.broken.get()
+    }
+
+    /// Send a `Props` pod from `props_pod` to a node, binding it if this is its first update.
+    /// `reply` is answered once the daemon has processed the update.
+    pub fn set(&self, node_id: u32, pod: &[u8], reply: Reply) {
+        match self.send(node_id, pod) {
+            Ok(seq) => self.pending.borrow_mut().push((seq, node_id, reply)),
+            Err(err) => {
+                let _ = reply.send(Err(err));
+            }
+        }
+    }
+
+    fn send(&self, node_id: u32, pod: &[u8]) -> anyhow::Result<AsyncSeq> {
+        let pod = Pod::from_bytes(pod).context("invalid Props pod")?;
+        let mut nodes = self.nodes.borrow_mut();
+        let node = match nodes.entry(node_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(self.bind(node_id)?),
+        };
+        node.node.set_param(ParamType::Props, 0, pod);
+        self.core.sync(0).context("failed to sync with PipeWire")
+    }
+
+    /// Bind a node by id. An unknown id or a global of another type is reported to the proxy
+    /// when the daemon processes the bind.
+    fn bind(&self, node_id: u32) -> anyhow::Result<BoundNode> {
+        let global = GlobalObject::<&DictRef> {
+            id: node_id,
+            permissions: PermissionFlags::empty(),
+            type_: ObjectType::Node,
+            version: 0,
+            props: None,
+        };
+        let node = self
+            .registry
+            .bind::<Node, _>(&global)
+            .with_context(|| format!("failed to bind node {node_id}"))?;
+        let error = Rc::new(RefCell::new(None));
+        let listener = node
+            .upcast_ref()
+            .add_listener_local()
+            .error({
+                let error = error.clone();
+                move |_seq, code, message| {
+                    *error.borrow_mut() = Some(PwError {
+                        code,
+                        message: message.to_string(),
+                    });
+                }
+            })
+            .register();
+
+        Ok(BoundNode {
+            node,
+            error,
+            _listener: listener,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pipewire::spa::{
+        param::ParamType,
+        pod::{Object, Property, Value, deserialize::PodDeserializer},
+        sys::{SPA_PROP_params, SPA_TYPE_OBJECT_Props},
+    };
+
+    use super::props_pod;
+
+    #[test]
+    fn test_props_pod() {
+        assert_eq!(props_pod([]).unwrap(), None);
+
+        let bytes = props_pod([("pweq.filter_1:Freq", 1000.0), ("pweq.filter_1:Q", 0.7)])
+            .unwrap()
+            .unwrap();
+        let (rest, value) = PodDeserializer::deserialize_any_from(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            value,
+            Value::Object(Object {
+                type_: SPA_TYPE_OBJECT_Props,
+                id: ParamType::Props.as_raw(),
+                properties: vec![Property::new(
+                    SPA_PROP_params,
+                    Value::Struct(vec![
+                        Value::String("pweq.filter_1:Freq".to_string()),
+                        Value::Float(1000.0),
+                        Value::String("pweq.filter_1:Q".to_string()),
+                        Value::Float(0.7),
+                    ]),
+                )],
+            })
+        );
+    }
+}
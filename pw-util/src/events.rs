@@ -0,0 +1,288 @@
+//! Stream of graph changes, driven by a PipeWire loop thread and consumed from tokio
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use anyhow::Context as _;
+use pipewire::{
+    metadata::{Metadata, MetadataListener},
+    node::{Node, NodeListener},
+    types::ObjectType,
+};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::metadata::{self, ClockSettings, DefaultNode};
+use crate::module::Direction;
+use crate::registry::{self, Connection, Param};
+use crate::{PwObjectInfo, PwObjectType};
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    GlobalAdded {
+        id: u32,
+        object_type: PwObjectType,
+        props: HashMap<String, Value>,
+    },
+    GlobalRemoved {
+        id: u32,
+    },
+    /// A node's info changed. Only props and fields are set, params come as `NodeParam`.
+    NodeInfo {
+        id: u32,
+        info: PwObjectInfo,
+    },
+    /// A node param was (re-)enumerated, after it changed or when the node first appeared
+    NodeParam {
+        id: u32,
+        param: Param,
+    },
+    /// The default sink or source the session manager uses changed
+    DefaultChanged {
+        direction: Direction,
+        node: Option<DefaultNode>,
+    },
+    /// `clock.rate` or `clock.force-rate` changed
+    ClockChanged {
+        clock: ClockSettings,
+    },
+}
+
+/// Graph events, starting with a `GlobalAdded` for every existing global.
+/// Dropping the stream stops its loop thread.
+pub struct Events {
+    rx: mpsc::UnboundedReceiver<Event>,
+    stop: pipewire::channel::Sender<()>,
+}
+
+impl futures_core::Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+    }
+}
+
+/// Connect on a new thread and stream its events
+pub async fn events() -> anyhow::Result<Events> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (stop, stop_rx) = pipewire::channel::channel();
+    let (ready_tx, ready) = oneshot::channel();
+
+    std::thread::Builder::new()
+        .name("pw-events".to_string())
+        .spawn(move || {
+            let conn = match Connection::new() {
+                Ok(conn) => conn,
+                Err(err) => {
+                    let _ = ready_tx.send(Err(err));
+                    return;
+                }
+            };
+            let _stop = stop_rx.attach(conn.mainloop().loop_(), {
+                let mainloop = conn.mainloop().clone();
+                move |()| mainloop.quit()
+            });
+            // End the stream if the connection to the daemon breaks
+            let _core = conn
+                .core()
+                .add_listener_local()
+                .error({
+                    let mainloop = conn.mainloop().clone();
+                    move |id, _seq, _res, _message| {
+                        if id == pipewire::core::PW_ID_CORE {
+                            mainloop.quit();
+                        }
+                    }
+                })
+                .register();
+            let _listeners = listen(&conn, tx);
+            let _ = ready_tx.send(Ok(()));
+            conn.mainloop().run();
+        })
+        .context("failed to spawn PipeWire event thread")?;
+
+    ready.await.context("PipeWire event thread exited")??;
+    Ok(Events { rx, stop })
+}
+
+/// Proxies bound by the event thread, kept alive until the global is removed
+#[derive(Default)]
+struct Bound {
+    nodes: HashMap<u32, (Node, NodeListener)>,
+    node_names: HashMap<u32, String>,
+    metadata: Vec<(u32, Metadata, MetadataListener)>,
+}
+
+fn listen(conn: &Connection, tx: mpsc::UnboundedSender<Event>) -> impl Sized {
+    let bound = Rc::new(RefCell::new(Bound::default()));
+    let weak_registry = conn.registry().downgrade();
+
+    let listener = conn
+        .registry()
+        .add_listener_local()
+        .global({
+            let bound = bound.clone();
+            let tx = tx.clone();
+            move |global| {
+                let object_type = PwObjectType::from(global.type_.to_str());
+                let props = global.props.map(registry::dict_to_map).unwrap_or_default();
+                let Some(registry) = weak_registry.upgrade() else {
+                    return;
+                };
+
+                match global.type_ {
+                    ObjectType::Node => {
+                        let Ok(node) = registry.bind::<Node, _>(global) else {
+                            return;
+                        };
+                        let listener = node_listener(&node, global.id, &bound, &tx);
+                        node.subscribe_params(&Param::NODE_TYPES);
+
+                        let mut bound = bound.borrow_mut();
+                        if let Some(Value::String(name)) = props.get("node.name") {
+                            bound.node_names.insert(global.id, name.clone());
+                        }
+                        bound.nodes.insert(global.id, (node, listener));
+                    }
+                    ObjectType::Metadata => {
+                        match global.props.and_then(|props| props.get("metadata.name")) {
+                            Some(name @ ("default" | "settings")) => {
+                                let Ok(metadata) = registry.bind::<Metadata, _>(global) else {
+                                    return;
+                                };
+                                let listener = if name == "default" {
+                                    default_listener(&metadata, &bound, &tx)
+                                } else {
+                                    settings_listener(&metadata, &tx)
+                                };
+                                let mut bound = bound.borrow_mut();
+                                bound.metadata.push((global.id, metadata, listener));
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+
+                let _ = tx.send(Event::GlobalAdded {
+                    id: global.id,
+                    object_type,
+                    props,
+                });
+            }
+        })
+        .global_remove({
+            let bound = bound.clone();
+            move |id| {
+                let mut bound = bound.borrow_mut();
+                bound.nodes.remove(&id);
+                bound.node_names.remove(&id);
+                bound
+                    .metadata
+                    .retain(|(metadata_id, ..)| *metadata_id != id);
+                let _ = tx.send(Event::GlobalRemoved { id });
+            }
+        })
+        .register();
+
+    (listener, bound)
+}
+
+fn node_listener(
+    node: &Node,
+    id: u32,
+    bound: &Rc<RefCell<Bound>>,
+    tx: &mpsc::UnboundedSender<Event>,
+) -> NodeListener {
+    node.add_listener_local()
+        .info({
+            let bound = Rc::downgrade(bound);
+            let tx = tx.clone();
+            move |node_info| {
+                let mut info = PwObjectInfo::default();
+                registry::node_info(&mut info, node_info);
+                // The name can change after the global was announced
+                if let (Some(bound), Some(Value::String(name))) =
+                    (bound.upgrade(), info.props.get("node.name"))
+                {
+                    bound.borrow_mut().node_names.insert(id, name.clone());
+                }
+                let _ = tx.send(Event::NodeInfo { id, info });
+            }
+        })
+        .param({
+            let tx = tx.clone();
+            move |_seq, param, _index, _next, pod| {
+                if let Some(param) = pod.and_then(|pod| Param::from_pod(param, pod)) {
+                    let _ = tx.send(Event::NodeParam { id, param });
+                }
+            }
+        })
+        .register()
+}
+
+fn default_listener(
+    metadata: &Metadata,
+    bound: &Rc<RefCell<Bound>>,
+    tx: &mpsc::UnboundedSender<Event>,
+) -> MetadataListener {
+    let bound = Rc::downgrade(bound);
+    let tx = tx.clone();
+    metadata
+        .add_listener_local()
+        .property(move |subject, key, _type, value| {
+            if subject != pipewire::core::PW_ID_CORE {
+                return 0;
+            }
+            let Some((key, direction)) =
+                key.and_then(|key| Some((key, Direction::from_default_key(key)?)))
+            else {
+                return 0;
+            };
+
+            let node = value
+                .and_then(|value| metadata::parse_name(key, value).ok())
+                .map(|name| DefaultNode {
+                    id: bound.upgrade().and_then(|bound| {
+                        let bound = bound.borrow();
+                        let mut names = bound.node_names.iter();
+                        names.find(|(_, node)| **node == name).map(|(&id, _)| id)
+                    }),
+                    name,
+                });
+            let _ = tx.send(Event::DefaultChanged { direction, node });
+            0
+        })
+        .register()
+}
+
+fn settings_listener(metadata: &Metadata, tx: &mpsc::UnboundedSender<Event>) -> MetadataListener {
+    let tx = tx.clone();
+    let clock = Cell::new(ClockSettings::default());
+    metadata
+        .add_listener_local()
+        .property(move |subject, key, _type, value| {
+            if subject != pipewire::core::PW_ID_CORE {
+                return 0;
+            }
+            let Some(key) = key else { return 0 };
+
+            let mut settings = clock.get();
+            if settings.update(key, value) {
+                clock.set(settings);
+                let _ = tx.send(Event::ClockChanged { clock: settings });
+            }
+            0
+        })
+        .register()
+}
@@ -0,0 +1,332 @@
+//! The signal path through the graph, built from Link and Port objects, for tracing what plays
+//! into a node and where an EQ's output goes
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_json::Value;
+
+use crate::module::MANAGED_PROP;
+use crate::{PwDumpObject, PwObjectType};
+
+/// Paths longer than this are cut short, as a guard against loops through the graph
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphNode {
+    pub id: u32,
+    pub name: String,
+    /// `application.name` for streams, `pweq.<name>` for EQs, otherwise the node name
+    pub label: String,
+    pub media_class: Option<String>,
+    /// Nodes of one filter-chain share a link group and pass audio between them internally
+    pub link_group: Option<String>,
+    pub managed: bool,
+}
+
+impl GraphNode {
+    fn from_object(obj: &PwDumpObject) -> Self {
+        let prop = |key| obj.prop(key).and_then(Value::as_str).map(str::to_string);
+        let name = prop("node.name").unwrap_or_else(|| format!("node {}", obj.id));
+        let managed = obj.prop(MANAGED_PROP) == Some(&Value::Bool(true));
+        let label = if managed {
+            format!("pweq.{}", prop("media.name").as_deref().unwrap_or(&name))
+        } else {
+            prop("application.name").unwrap_or_else(|| name.clone())
+        };
+
+        Self {
+            id: obj.id,
+            name,
+            label,
+            media_class: prop("media.class"),
+            link_group: prop("node.link-group"),
+            managed,
+        }
+    }
+
+    /// Whether audio flows into the node from links, rather than out of it: sinks, and the
+    /// streams that record from a source
+    fn is_input(&self) -> bool {
+        self.media_class
+            .as_deref()
+            .is_some_and(|class| class.ends_with("/Sink") || class.starts_with("Stream/Input/"))
+    }
+}
+
+/// Nodes and the links between them, one edge per linked pair of nodes however many ports are
+/// linked
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    pub nodes: BTreeMap<u32, GraphNode>,
+    edges: BTreeSet<(u32, u32)>,
+}
+
+impl Graph {
+    pub fn from_objects(objects: &[PwDumpObject]) -> Self {
+        let nodes = objects
+            .iter()
+            .filter(|obj| obj.object_type == PwObjectType::Node)
+            .map(|obj| (obj.id, GraphNode::from_object(obj)))
+            .collect::<BTreeMap<_, _>>();
+
+        // Links name their ports, and the nodes too in most versions
+        let port_nodes = objects
+            .iter()
+            .filter(|obj| obj.object_type == PwObjectType::Port)
+            .filter_map(|obj| Some((obj.id, as_id(obj.prop("node.id"))?)))
+            .collect::<HashMap<_, _>>();
+        let mut edges = objects
+            .iter()
+            .filter(|obj| obj.object_type == PwObjectType::Link)
+            .filter_map(|link| {
+                let node = |side: &str| {
+                    as_id(link.prop(&format!("link.{side}.node")))
+                        .or_else(|| as_id(link.info.fields.get(&format!("{side}-node-id"))))
+                        .or_else(|| {
+                            let port = as_id(link.prop(&format!("link.{side}.port")))?;
+                            port_nodes.get(&port).copied()
+                        })
+                };
+                Some((node("output")?, node("input")?))
+            })
+            .collect::<BTreeSet<_>>();
+
+        // A filter-chain passes audio from the node that receives it to the node that sends it on
+        let mut groups = BTreeMap::<&str, Vec<&GraphNode>>::new();
+        for node in nodes.values() {
+            if let Some(group) = &node.link_group {
+                groups.entry(group).or_default().push(node);
+            }
+        }
+        for members in groups.values() {
+            for input in members.iter().filter(|node| node.is_input()) {
+                for output in members.iter().filter(|node| !node.is_input()) {
+                    edges.insert((input.id, output.id));
+                }
+            }
+        }
+
+        Self { nodes, edges }
+    }
+
+    /// Nodes that send audio to a node
+    pub fn inputs(&self, id: u32) -> impl Iterator<Item = u32> + '_ {
+        self.edges
+            .iter()
+            .filter(move |&&(_, input)| input == id)
+            .map(|&(output, _)| output)
+    }
+
+    /// Nodes a node sends audio to
+    pub fn outputs(&self, id: u32) -> impl Iterator<Item = u32> + '_ {
+        self.edges
+            .iter()
+            .filter(move |&&(output, _)| output == id)
+            .map(|&(_, input)| input)
+    }
+
+    /// Every path that ends at a node, from nodes that nothing plays into
+    pub fn chains_into(&self, id: u32) -> Vec<Vec<u32>> {
+        let mut chains = self.walk(id, &|id| self.inputs(id).collect());
+        for chain in &mut chains {
+            chain.reverse();
+        }
+        chains
+    }
+
+    /// Every path that starts at a node, up to nodes that play into nothing
+    pub fn chains_from(&self, id: u32) -> Vec<Vec<u32>> {
+        self.walk(id, &|id| self.outputs(id).collect())
+    }
+
+    fn walk(&self, id: u32, next: &dyn Fn(u32) -> Vec<u32>) -> Vec<Vec<u32>> {
+        let mut chains = vec![];
+        let mut stack = vec![vec![id]];
+        while let Some(chain) = stack.pop() {
+            let last = *chain.last().unwrap();
+            let nexts = next(last)
+                .into_iter()
+                .filter(|id| !chain.contains(id))
+                .collect::<Vec<_>>();
+            if nexts.is_empty() || chain.len() >= MAX_DEPTH {
+                chains.push(chain);
+                continue;
+            }
+            for id in nexts.into_iter().rev() {
+                let mut chain = chain.clone();
+                chain.push(id);
+                stack.push(chain);
+            }
+        }
+        chains
+    }
+
+    /// A path as `Firefox -> pweq.focal -> alsa_output.usb-...`, showing the nodes of a
+    /// filter-chain once
+    pub fn format_chain(&self, chain: &[u32]) -> String {
+        let mut labels = Vec::<&str>::with_capacity(chain.len());
+        let mut group = None;
+        for id in chain {
+            let Some(node) = self.nodes.get(id) else {
+                continue;
+            };
+            if node.link_group.is_some() && node.link_group == group {
+                // Name the filter-chain after its EQ node, whichever side it was entered from
+                if node.managed {
+                    *labels.last_mut().unwrap() = &node.label;
+                }
+                continue;
+            }
+            group = node.link_group.clone();
+            labels.push(&node.label);
+        }
+        labels.join(" -> ")
+    }
+
+    /// The nodes in the same filter-chain as a node, including itself
+    pub fn group(&self, id: u32) -> Vec<u32> {
+        let group = self
+            .nodes
+            .get(&id)
+            .and_then(|node| node.link_group.as_ref());
+        match group {
+            Some(group) => self
+                .nodes
+                .values()
+                .filter(|node| node.link_group.as_ref() == Some(group))
+                .map(|node| node.id)
+                .collect(),
+            None => vec![id],
+        }
+    }
+
+    /// Nodes outside a node's filter-chain that play into it
+    pub fn external_inputs(&self, id: u32) -> Vec<u32> {
+        let group = self.group(id);
+        group
+            .iter()
+            .flat_map(|&id| self.inputs(id))
+            .filter(|id| !group.contains(id))
+            .collect()
+    }
+
+    /// Nodes outside a node's filter-chain that it plays into
+    pub fn external_outputs(&self, id: u32) -> Vec<u32> {
+        let group = self.group(id);
+        group
+            .iter()
+            .flat_map(|&id| self.outputs(id))
+            .filter(|id| !group.contains(id))
+            .collect()
+    }
+}
+
+fn as_id(value: Option<&Value>) -> Option<u32> {
+    value?.as_u64()?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Graph;
+    use crate::{PwDumpObject, PwObjectInfo, PwObjectType};
+
+    fn object(id: u32, object_type: PwObjectType, props: serde_json::Value) -> PwDumpObject {
+        let serde_json::Value::Object(props) = props else {
+            unreachable!()
+        };
+        let props = props.into_iter().collect();
+        PwDumpObject {
+            id,
+            object_type,
+            version: None,
+            permissions: None,
+            info: PwObjectInfo {
+                props,
+                ..Default::default()
+            },
+            props: None,
+        }
+    }
+
+    #[test]
+    fn test_signal_chain() {
+        let objects = vec![
+            object(
+                1,
+                PwObjectType::Node,
+                json!({
+                    "node.name": "Firefox",
+                    "application.name": "Firefox",
+                    "media.class": "Stream/Output/Audio",
+                }),
+            ),
+            object(
+                2,
+                PwObjectType::Node,
+                json!({
+                    "node.name": "effect_input.pweq.focal",
+                    "media.name": "focal",
+                    "media.class": "Audio/Sink",
+                    "node.link-group": "filter-chain-7",
+                    "pweq.managed": true,
+                }),
+            ),
+            object(
+                3,
+                PwObjectType::Node,
+                json!({
+                    "node.name": "effect_output.pweq.focal",
+                    "media.name": "focal",
+                    "media.class": "Stream/Output/Audio",
+                    "node.link-group": "filter-chain-7",
+                }),
+            ),
+            object(
+                4,
+                PwObjectType::Node,
+                json!({
+                    "node.name": "alsa_output.usb-focal",
+                    "media.class": "Audio/Sink",
+                }),
+            ),
+            // Linked through node ids
+            object(
+                10,
+                PwObjectType::Link,
+                json!({ "link.output.node": 1, "link.input.node": 2 }),
+            ),
+            object(
+                11,
+                PwObjectType::Link,
+                json!({ "link.output.node": 1, "link.input.node": 2 }),
+            ),
+            // Linked through ports only
+            object(20, PwObjectType::Port, json!({ "node.id": 3 })),
+            object(21, PwObjectType::Port, json!({ "node.id": 4 })),
+            object(
+                22,
+                PwObjectType::Link,
+                json!({ "link.output.port": 20, "link.input.port": 21 }),
+            ),
+        ];
+        let graph = Graph::from_objects(&objects);
+
+        let chains = graph.chains_into(4);
+        assert_eq!(chains, vec![vec![1, 2, 3, 4]]);
+        assert_eq!(
+            graph.format_chain(&chains[0]),
+            "Firefox -> pweq.focal -> alsa_output.usb-focal"
+        );
+        assert_eq!(graph.chains_from(2), vec![vec![2, 3, 4]]);
+        assert_eq!(
+            graph.format_chain(&[2, 3, 4]),
+            "pweq.focal -> alsa_output.usb-focal"
+        );
+
+        assert_eq!(graph.external_inputs(2), vec![1]);
+        assert_eq!(graph.external_outputs(2), vec![4]);
+        assert_eq!(graph.external_inputs(1), Vec::<u32>::new());
+    }
+}
@@ -4,61 +4,150 @@ pub use pipewire;
 pub mod api;
 
 pub mod apo;
+pub mod backend;
+pub mod control;
+pub mod events;
+pub mod graph;
+pub mod metadata;
 pub mod module;
+pub mod props;
+pub mod registry;
+pub mod routing;
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use tokio::process::Command;
 
-use self::serde_ex::KeyValuePairs;
+use self::metadata::{ClockSettings, DefaultNode};
+use self::serde_ex::{KeyValuePairs, lenient, lenient_vec};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PwDumpObject {
     pub id: u32,
     #[serde(rename = "type")]
     pub object_type: PwObjectType,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub version: Option<u32>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub permissions: Option<Vec<String>>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub info: PwObjectInfo,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub props: Option<HashMap<String, serde_json::Value>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+impl PwDumpObject {
+    /// Sample rate of the negotiated format, if the node is running
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.info
+            .params
+            .format
+            .iter()
+            .find_map(|format| format.rate)
+    }
+
+    /// A property from the object's info, falling back to the props of its global
+    pub fn prop(&self, key: &str) -> Option<&serde_json::Value> {
+        self.info
+            .props
+            .get(key)
+            .or_else(|| self.props.as_ref()?.get(key))
+    }
+
+    /// The output and input node of a link
+    pub fn link_nodes(&self) -> Option<(u32, u32)> {
+        if self.object_type != PwObjectType::Link {
+            return None;
+        }
+        let node = |key| self.prop(key)?.as_u64()?.try_into().ok();
+        Some((node("link.output.node")?, node("link.input.node")?))
+    }
+
+    /// The route a device is using in each direction, e.g. headphones rather than speakers
+    pub fn active_route(&self, direction: ParamDirection) -> Option<&Route> {
+        self.info
+            .params
+            .route
+            .iter()
+            .find(|route| route.direction == Some(direction))
+    }
+}
+
+/// The interface of a global, e.g. `PipeWire:Interface:Node`. Interfaces newer than this
+/// crate are kept as `Unknown` rather than failing the whole dump.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
 pub enum PwObjectType {
-    #[serde(rename = "PipeWire:Interface:Core")]
     Core,
-    #[serde(rename = "PipeWire:Interface:Module")]
     Module,
-    #[serde(rename = "PipeWire:Interface:Client")]
     Client,
-    #[serde(rename = "PipeWire:Interface:SecurityContext")]
     SecurityContext,
-    #[serde(rename = "PipeWire:Interface:Profiler")]
     Profiler,
-    #[serde(rename = "PipeWire:Interface:Factory")]
     Factory,
-    #[serde(rename = "PipeWire:Interface:Device")]
     Device,
-    #[serde(rename = "PipeWire:Interface:Metadata")]
     Metadata,
-    #[serde(rename = "PipeWire:Interface:Node")]
     Node,
-    #[serde(rename = "PipeWire:Interface:Port")]
     Port,
-    #[serde(rename = "PipeWire:Interface:Link")]
     Link,
+    Unknown(String),
+}
+
+impl PwObjectType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PwObjectType::Core => "PipeWire:Interface:Core",
+            PwObjectType::Module => "PipeWire:Interface:Module",
+            PwObjectType::Client => "PipeWire:Interface:Client",
+            PwObjectType::SecurityContext => "PipeWire:Interface:SecurityContext",
+            PwObjectType::Profiler => "PipeWire:Interface:Profiler",
+            PwObjectType::Factory => "PipeWire:Interface:Factory",
+            PwObjectType::Device => "PipeWire:Interface:Device",
+            PwObjectType::Metadata => "PipeWire:Interface:Metadata",
+            PwObjectType::Node => "PipeWire:Interface:Node",
+            PwObjectType::Port => "PipeWire:Interface:Port",
+            PwObjectType::Link => "PipeWire:Interface:Link",
+            PwObjectType::Unknown(type_) => type_,
+        }
+    }
+}
+
+impl From<&str> for PwObjectType {
+    fn from(type_: &str) -> Self {
+        match type_ {
+            "PipeWire:Interface:Core" => PwObjectType::Core,
+            "PipeWire:Interface:Module" => PwObjectType::Module,
+            "PipeWire:Interface:Client" => PwObjectType::Client,
+            "PipeWire:Interface:SecurityContext" => PwObjectType::SecurityContext,
+            "PipeWire:Interface:Profiler" => PwObjectType::Profiler,
+            "PipeWire:Interface:Factory" => PwObjectType::Factory,
+            "PipeWire:Interface:Device" => PwObjectType::Device,
+            "PipeWire:Interface:Metadata" => PwObjectType::Metadata,
+            "PipeWire:Interface:Node" => PwObjectType::Node,
+            "PipeWire:Interface:Port" => PwObjectType::Port,
+            "PipeWire:Interface:Link" => PwObjectType::Link,
+            type_ => PwObjectType::Unknown(type_.to_string()),
+        }
+    }
+}
+
+impl From<String> for PwObjectType {
+    fn from(type_: String) -> Self {
+        type_.as_str().into()
+    }
+}
+
+impl From<PwObjectType> for String {
+    fn from(type_: PwObjectType) -> Self {
+        type_.as_str().to_string()
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PwObjectInfo {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub props: HashMap<String, serde_json::Value>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub params: PwParams,
     #[serde(flatten)]
     pub fields: HashMap<String, serde_json::Value>,
@@ -67,17 +156,115 @@ pub struct PwObjectInfo {
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PwParams {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub enum_format: Vec<serde_json::Value>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub format: Vec<Format>,
+    #[serde(default, deserialize_with = "lenient_vec")]
     pub prop_info: Vec<PwPropInfo>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient_vec")]
     pub props: Vec<Prop>,
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub enum_route: Vec<Route>,
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub route: Vec<Route>,
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub enum_profile: Vec<Profile>,
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub profile: Vec<Profile>,
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub latency: Vec<Latency>,
+    #[serde(default, deserialize_with = "lenient_vec")]
+    pub process_latency: Vec<ProcessLatency>,
+}
+
+/// A negotiated format. Enumerated formats hold choices instead, so `EnumFormat` stays untyped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Format {
+    pub media_type: Option<String>,
+    pub media_subtype: Option<String>,
+    /// Sample format, e.g. `F32P` or `S16LE`
+    pub format: Option<String>,
+    pub rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// Channel positions, e.g. `FL`
+    pub position: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParamDirection {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Availability {
+    #[default]
+    Unknown,
+    No,
+    Yes,
+}
+
+/// A device port such as headphones or speakers (`Route`), or one it offers (`EnumRoute`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Route {
+    pub index: u32,
+    pub direction: Option<ParamDirection>,
+    /// Index of the card device the route is active on, only set on `Route`
+    pub device: Option<u32>,
+    pub name: String,
+    pub description: Option<String>,
+    pub priority: Option<u32>,
+    pub available: Availability,
+    /// Indices of the profiles that include the route
+    pub profiles: Vec<u32>,
+    /// Indices of the card devices the route can be used with
+    pub devices: Vec<u32>,
+    /// Volume and mute of the route, only set on `Route`
+    pub props: Option<serde_json::Value>,
+    pub save: Option<bool>,
+}
+
+/// A device profile such as `output:analog-stereo` (`Profile`), or one it offers (`EnumProfile`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub index: u32,
+    pub name: String,
+    pub description: Option<String>,
+    pub priority: Option<u32>,
+    pub available: Availability,
+    pub save: Option<bool>,
+}
+
+/// Latency a node reports on its ports, in quantums, samples and nanoseconds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Latency {
+    pub direction: Option<ParamDirection>,
+    pub min_quantum: f64,
+    pub max_quantum: f64,
+    pub min_rate: u32,
+    pub max_rate: u32,
+    pub min_ns: u64,
+    pub max_ns: u64,
+}
+
+/// Latency the node itself adds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessLatency {
+    pub quantum: f64,
+    pub rate: u32,
+    pub ns: u64,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Prop {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub params: KeyValuePairs<HashMap<String, serde_json::Value>>,
     #[serde(flatten)]
     pub fields: HashMap<String, serde_json::Value>,
@@ -87,13 +274,19 @@ pub struct Prop {
 pub struct PwPropInfo {
     pub id: Option<String>,
     pub name: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "lenient")]
     pub description: String,
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub type_: serde_json::Value,
 }
 
+/// Every global, collected with the native registry client
 pub async fn dump() -> Result<Vec<PwDumpObject>> {
+    blocking(registry::dump).await
+}
+
+/// Every global, by running `pw-dump` and parsing its output
+pub async fn pw_dump() -> Result<Vec<PwDumpObject>> {
     let output = Command::new("pw-dump")
         .output()
         .await
@@ -108,54 +301,272 @@ pub async fn dump() -> Result<Vec<PwDumpObject>> {
 
     let json_str = String::from_utf8(output.stdout).context("pw-dump output is not valid UTF-8")?;
 
-    let objects: Vec<PwDumpObject> =
-        serde_json::from_str(&json_str).context("Failed to parse pw-dump JSON")?;
+    parse_dump(&json_str)
+}
+
+/// Parse `pw-dump` output, skipping objects too malformed to have an id and type
+pub fn parse_dump(json: &str) -> Result<Vec<PwDumpObject>> {
+    let objects: Vec<serde_json::Value> =
+        serde_json::from_str(json).context("Failed to parse pw-dump JSON")?;
 
-    Ok(objects)
+    Ok(objects
+        .into_iter()
+        .filter_map(|object| {
+            let id = object.get("id").cloned();
+            let object_type = object.get("type").cloned();
+            serde_json::from_value(object)
+                .inspect_err(|err| {
+                    tracing::warn!(?id, ?object_type, %err, "skipping malformed pw-dump object");
+                })
+                .ok()
+        })
+        .collect())
 }
 
-pub async fn set_default(node_id: u32) -> Result<()> {
-    let output = Command::new("wpctl")
-        .arg("set-default")
-        .arg(node_id.to_string())
-        .output()
-        .await
-        .context("Failed to execute wpctl")?;
+/// Make a sink the default sink, or a source the default source
+pub async fn set_default(node_id: u32) -> Result<DefaultNode> {
+    blocking(move || metadata::set_default(node_id)).await
+}
 
-    if !output.status.success() {
-        anyhow::bail!("wpctl failed: {}", String::from_utf8_lossy(&output.stderr));
-    }
+pub async fn get_clock_settings() -> Result<ClockSettings> {
+    blocking(metadata::get_clock_settings).await
+}
 
-    Ok(())
+/// Destroy a global, e.g. a node of a module loaded by the daemon, which can't be unloaded
+pub async fn destroy(id: u32) -> Result<()> {
+    blocking(move || registry::destroy(id)).await
 }
 
-pub async fn get_default_audio_sink() -> Result<u32> {
-    let output = Command::new("wpctl")
-        .arg("inspect")
-        .arg("@DEFAULT_AUDIO_SINK@")
-        .output()
+/// Run a native client call on a blocking thread, as each one drives its own main loop
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
         .await
-        .context("Failed to execute wpctl")?;
+        .context("PipeWire client panicked")?
+}
 
-    if !output.status.success() {
-        anyhow::bail!("wpctl failed: {}", String::from_utf8_lossy(&output.stderr));
+pub fn to_spa_json<T: serde::Serialize>(value: &T) -> String {
+    let json_value = serde_json::to_value(value).expect("Failed to serialize to JSON value");
+    self::module::SpaJson::new(&json_value).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Availability, ParamDirection, PwDumpObject, PwObjectType};
+    use crate::props::filter_controls;
+
+    fn find(objects: &[PwDumpObject], id: u32) -> &PwDumpObject {
+        objects.iter().find(|obj| obj.id == id).unwrap()
     }
 
-    // Parse output to extract id from first line (e.g., "id 123, type PipeWire:Interface:Node")
-    let stdout = String::from_utf8(output.stdout).context("wpctl output is not valid UTF-8")?;
-    let first_line = stdout.lines().next().context("wpctl output is empty")?;
+    // The fixtures are hand-written in the shape of `pw-dump` output, not captured from a running
+    // daemon, so they cover the cases in their names rather than any PipeWire version
+    #[test]
+    fn test_dump_analog_card() {
+        let objects = super::parse_dump(include_str!(
+            "../tests/fixtures/synthetic-dump-analog-card.json"
+        ))
+        .unwrap();
+        assert_eq!(objects.len(), 9);
+        assert_eq!(objects[2].object_type, PwObjectType::Profiler);
 
-    // Extract id from "id <number>,"
-    let id_str = first_line
-        .split_whitespace()
-        .nth(1)
-        .and_then(|s| s.trim_end_matches(',').parse::<u32>().ok())
-        .context("Failed to parse node id from wpctl output")?;
+        let card = find(&objects, 45);
+        let route = card.active_route(ParamDirection::Output).unwrap();
+        assert_eq!(route.available, Availability::No);
+        assert_eq!(card.info.params.profile[0].name, "output:analog-stereo");
 
-    Ok(id_str)
-}
+        let sink = find(&objects, 52);
+        assert_eq!(sink.sample_rate(), Some(48000));
+        // Latency without nanosecond bounds
+        assert_eq!(sink.info.params.latency[1].min_rate, 1536);
+        assert_eq!(sink.info.params.latency[1].min_ns, 0);
 
-pub fn to_spa_json<T: serde::Serialize>(value: &T) -> String {
-    let json_value = serde_json::to_value(value).expect("Failed to serialize to JSON value");
-    self::module::SpaJson::new(&json_value).to_string()
+        let eq = find(&objects, 70);
+        assert_eq!(eq.info.props["pweq.managed"], true);
+        let controls = filter_controls(&eq.info.params);
+        assert_eq!(controls[0].value("Gain"), Some(-2.0));
+    }
+
+    #[test]
+    fn test_dump_headset_routes() {
+        let objects = super::parse_dump(include_str!(
+            "../tests/fixtures/synthetic-dump-headset-routes.json"
+        ))
+        .unwrap();
+        assert_eq!(objects.len(), 9);
+        assert_eq!(objects[1].object_type, PwObjectType::SecurityContext);
+
+        let card = find(&objects, 48);
+        assert_eq!(card.info.params.enum_route.len(), 3);
+        let headphones = card.active_route(ParamDirection::Output).unwrap();
+        assert_eq!(headphones.description.as_deref(), Some("Headphones"));
+        assert_eq!(headphones.save, Some(true));
+        let mic = card.active_route(ParamDirection::Input).unwrap();
+        assert_eq!(mic.available, Availability::Unknown);
+
+        // A suspended node has no negotiated format
+        assert_eq!(find(&objects, 56).sample_rate(), None);
+
+        let eq = find(&objects, 90);
+        assert_eq!(eq.sample_rate(), Some(44100));
+        assert_eq!(eq.info.params.format[0].position, ["MONO"]);
+        let controls = filter_controls(&eq.info.params);
+        assert_eq!(controls.len(), 2);
+        assert_eq!(controls[1].controls["Q"].default, Some(0.707));
+        assert_eq!(controls[1].value("Freq"), Some(120.0));
+    }
+
+    #[test]
+    fn test_dump_pro_audio() {
+        let objects = super::parse_dump(include_str!(
+            "../tests/fixtures/synthetic-dump-pro-audio.json"
+        ))
+        .unwrap();
+        assert_eq!(objects.len(), 6);
+
+        let card = find(&objects, 44);
+        // Pro Audio has no routes
+        assert!(card.active_route(ParamDirection::Output).is_none());
+        assert_eq!(card.info.params.profile[0].name, "pro-audio");
+
+        let eq = find(&objects, 101);
+        assert_eq!(eq.sample_rate(), Some(96000));
+        // The rate of an enumerated format is a range, not a rate
+        assert!(eq.info.params.enum_format[0]["rate"].is_object());
+        let controls = filter_controls(&eq.info.params);
+        let nodes = controls
+            .iter()
+            .map(|node| node.node.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(nodes, ["pweq.filter_FL_1", "pweq.filter_FR_1"]);
+        assert_eq!(controls[1].value("Gain"), Some(1.5));
+    }
+
+    #[test]
+    fn test_unknown_types_and_malformed_fields() {
+        let objects = super::parse_dump(
+            &serde_json::json!([
+                { "id": 0, "type": "PipeWire:Interface:Core" },
+                { "id": 7, "type": "PipeWire:Interface:Teleporter", "version": 1 },
+                { "type": "PipeWire:Interface:Node" },
+                {
+                    "id": 12,
+                    "type": "PipeWire:Interface:Node",
+                    "version": "3",
+                    "permissions": null,
+                    "info": {
+                        "props": { "node.name": "sink" },
+                        "params": {
+                            "Format": [ "not a format", { "rate": 48000 } ],
+                            "PropInfo": [ { "name": "x:Gain", "description": null } ],
+                            "Latency": { "direction": "Input" }
+                        }
+                    }
+                }
+            ])
+            .to_string(),
+        )
+        .unwrap();
+
+        // The object without an id is skipped
+        assert_eq!(objects.len(), 3);
+        let unknown = &objects[1];
+        assert_eq!(
+            unknown.object_type,
+            PwObjectType::Unknown("PipeWire:Interface:Teleporter".to_string())
+        );
+        assert_eq!(unknown.version, Some(1));
+        // Unknown types are written back out as they came in
+        assert_eq!(
+            serde_json::to_value(&unknown.object_type).unwrap(),
+            "PipeWire:Interface:Teleporter"
+        );
+
+        let node = &objects[2];
+        assert_eq!(node.version, None);
+        assert_eq!(node.permissions, None);
+        assert_eq!(node.info.props["node.name"], "sink");
+        assert_eq!(node.sample_rate(), Some(48000));
+        assert_eq!(node.info.params.prop_info[0].description, "");
+        assert!(node.info.params.latency.is_empty());
+    }
+
+    #[test]
+    fn test_device_and_node_params() {
+        // Hand-written in the shape `pw-dump` gives a sound card and one of its sinks
+        let objects: Vec<PwDumpObject> = serde_json::from_value(serde_json::json!([
+            {
+                "id": 48,
+                "type": "PipeWire:Interface:Device",
+                "info": {
+                    "params": {
+                        "EnumProfile": [
+                            { "index": 0, "name": "off", "description": "Off", "priority": 0, "available": "yes" },
+                            { "index": 1, "name": "output:analog-stereo", "priority": 6500, "available": "yes" }
+                        ],
+                        "Profile": [
+                            { "index": 1, "name": "output:analog-stereo", "save": true }
+                        ],
+                        "Route": [
+                            {
+                                "index": 2,
+                                "direction": "Output",
+                                "device": 4,
+                                "name": "analog-output-headphones",
+                                "description": "Headphones",
+                                "priority": 9900,
+                                "available": "yes",
+                                "info": [ 1, "port.type", "headphones" ],
+                                "profiles": [ 1 ],
+                                "props": { "mute": false, "channelVolumes": [ 0.5, 0.5 ] },
+                                "devices": [ 4 ],
+                                "save": true
+                            }
+                        ]
+                    }
+                }
+            },
+            {
+                "id": 57,
+                "type": "PipeWire:Interface:Node",
+                "info": {
+                    "params": {
+                        "Format": [
+                            {
+                                "mediaType": "audio",
+                                "mediaSubtype": "raw",
+                                "format": "F32P",
+                                "rate": 96000,
+                                "channels": 2,
+                                "position": [ "FL", "FR" ]
+                            }
+                        ],
+                        "Latency": [
+                            { "direction": "Input", "minQuantum": 0.0, "maxQuantum": 0.0, "minRate": 0, "maxRate": 0, "minNs": 0, "maxNs": 0 },
+                            { "direction": "Output", "minQuantum": 1.0, "maxQuantum": 1.0, "minRate": 512, "maxRate": 512, "minNs": 0, "maxNs": 0 }
+                        ],
+                        "ProcessLatency": [
+                            { "quantum": 0.0, "rate": 256, "ns": 0 }
+                        ]
+                    }
+                }
+            }
+        ]))
+        .unwrap();
+
+        let device = &objects[0];
+        let params = &device.info.params;
+        assert_eq!(params.enum_profile.len(), 2);
+        assert_eq!(params.profile[0].name, "output:analog-stereo");
+        let route = device.active_route(ParamDirection::Output).unwrap();
+        assert_eq!(route.name, "analog-output-headphones");
+        assert_eq!(route.available, Availability::Yes);
+        assert_eq!(route.device, Some(4));
+        assert!(device.active_route(ParamDirection::Input).is_none());
+
+        let node = &objects[1];
+        assert_eq!(node.sample_rate(), Some(96000));
+        assert_eq!(node.info.params.format[0].position, ["FL", "FR"]);
+        assert_eq!(node.info.params.latency[1].max_rate, 512);
+        assert_eq!(node.info.params.process_latency[0].rate, 256);
+    }
 }
@@ -0,0 +1,418 @@
+//! Default sink and source, read and written through the `default` metadata object that the
+//! session manager maintains, and the graph clock from the `settings` metadata
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use anyhow::Context as _;
+use pipewire::{
+    metadata::{Metadata, MetadataListener},
+    types::ObjectType,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::module::Direction;
+use crate::registry::Connection;
+
+/// Type of the JSON values stored in the `default` metadata
+const JSON_TYPE: &str = "Spa:String:JSON";
+
+/// Metadata key, set on a stream, of the node the session manager should link it to
+const TARGET_KEY: &str = "target.object";
+
+/// Metadata key, set on a stream routed onto an EQ, of where it was before
+const ORIGIN_KEY: &str = "pweq.route.origin";
+
+/// Where a stream routed onto an EQ was before, to put it back there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteOrigin {
+    /// Pinned to this node
+    Pinned(u32),
+    /// Following the default
+    Default,
+}
+
+/// `pweq.route.origin` values are `{ "target": "<object.serial>" }`, with a null target for a
+/// stream that followed the default
+#[derive(Serialize, Deserialize)]
+struct OriginValue {
+    target: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultNode {
+    /// Id of the node with this name, if it currently exists
+    pub id: Option<u32>,
+    pub name: String,
+}
+
+/// Metadata values are `{ "name": "<node.name>" }`
+#[derive(Serialize, Deserialize)]
+struct NameValue {
+    name: String,
+}
+
+/// Node name in a `default` metadata value
+pub(crate) fn parse_name(key: &str, value: &str) -> anyhow::Result<String> {
+    let NameValue { name } = serde_json::from_str(value)
+        .with_context(|| format!("invalid `{key}` metadata value: {value}"))?;
+    Ok(name)
+}
+
+impl Direction {
+    /// The direction whose in-use default a metadata key holds
+    pub(crate) fn from_default_key(key: &str) -> Option<Self> {
+        [Direction::Sink, Direction::Source]
+            .into_iter()
+            .find(|direction| direction.default_key() == key)
+    }
+
+    /// Metadata key of the default the session manager is currently using
+    fn default_key(self) -> &'static str {
+        match self {
+            Direction::Sink => "default.audio.sink",
+            Direction::Source => "default.audio.source",
+        }
+    }
+
+    /// Metadata key of the default the user chose, which the session manager follows when the
+    /// node is available
+    fn configured_default_key(self) -> &'static str {
+        match self {
+            Direction::Sink => "default.configured.audio.sink",
+            Direction::Source => "default.configured.audio.source",
+        }
+    }
+}
+
+struct Node {
+    id: u32,
+    name: String,
+    media_class: Option<String>,
+    serial: Option<String>,
+}
+
+/// A metadata object with its properties, and the nodes they may refer to
+struct NamedMetadata {
+    _listener: MetadataListener,
+    metadata: Metadata,
+    properties: Rc<RefCell<HashMap<String, String>>>,
+    /// `target.object` of each stream that has one
+    targets: Rc<RefCell<HashMap<u32, String>>>,
+    /// `pweq.route.origin` of each stream that has one
+    origins: Rc<RefCell<HashMap<u32, String>>>,
+    nodes: Vec<Node>,
+    conn: Connection,
+}
+
+impl NamedMetadata {
+    fn new(name: &'static str) -> anyhow::Result<Self> {
+        let conn = Connection::new()?;
+        let nodes = Rc::new(RefCell::new(vec![]));
+        let metadata = Rc::new(RefCell::new(None));
+
+        let registry_listener = conn
+            .registry()
+            .add_listener_local()
+            .global({
+                let nodes = nodes.clone();
+                let metadata = metadata.clone();
+                let registry = conn.registry().downgrade();
+                move |global| {
+                    let Some(props) = global.props else { return };
+                    match global.type_ {
+                        ObjectType::Node => {
+                            if let Some(name) = props.get("node.name") {
+                                nodes.borrow_mut().push(Node {
+                                    id: global.id,
+                                    name: name.to_string(),
+                                    media_class: props.get("media.class").map(str::to_string),
+                                    serial: props.get("object.serial").map(str::to_string),
+                                });
+                            }
+                        }
+                        ObjectType::Metadata if props.get("metadata.name") == Some(name) => {
+                            if let Some(registry) = registry.upgrade() {
+                                *metadata.borrow_mut() = registry.bind::<Metadata, _>(global).ok();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            })
+            .register();
+        conn.roundtrip()?;
+        drop(registry_listener);
+
+        let metadata = metadata
+            .take()
+            .with_context(|| format!("`{name}` metadata not found"))?;
+        let properties = Rc::new(RefCell::new(HashMap::new()));
+        let targets = Rc::new(RefCell::new(HashMap::new()));
+        let origins = Rc::new(RefCell::new(HashMap::new()));
+        let listener = metadata
+            .add_listener_local()
+            .property({
+                let properties = properties.clone();
+                let targets = targets.clone();
+                let origins = origins.clone();
+                move |subject, key, _type, value| {
+                    if subject == pipewire::core::PW_ID_CORE {
+                        let mut properties = properties.borrow_mut();
+                        match (key, value) {
+                            (Some(key), Some(value)) => {
+                                properties.insert(key.to_string(), value.to_string());
+                            }
+                            (Some(key), None) => {
+                                properties.remove(key);
+                            }
+                            (None, _) => properties.clear(),
+                        }
+                    } else {
+                        // A null key clears every property of the subject
+                        for (stream_key, values) in [(TARGET_KEY, &targets), (ORIGIN_KEY, &origins)]
+                        {
+                            if key.is_some_and(|key| key != stream_key) {
+                                continue;
+                            }
+                            let mut values = values.borrow_mut();
+                            match value {
+                                Some(value) if key.is_some() => {
+                                    values.insert(subject, value.to_string());
+                                }
+                                _ => {
+                                    values.remove(&subject);
+                                }
+                            }
+                        }
+                    }
+                    0
+                }
+            })
+            .register();
+        // Binding makes the daemon send every current property
+        conn.roundtrip()?;
+
+        Ok(Self {
+            _listener: listener,
+            metadata,
+            properties,
+            targets,
+            origins,
+            nodes: nodes.take(),
+            conn,
+        })
+    }
+
+    fn default_node(&self, key: &str) -> anyhow::Result<Option<DefaultNode>> {
+        let Some(value) = self.properties.borrow().get(key).cloned() else {
+            return Ok(None);
+        };
+        let name = parse_name(key, &value)?;
+        let id = self
+            .nodes
+            .iter()
+            .find(|node| node.name == name)
+            .map(|node| node.id);
+
+        Ok(Some(DefaultNode { id, name }))
+    }
+
+    /// The node a `target.object` value names: its object.serial, or its node.name when set by
+    /// hand
+    fn target_node(&self, target: &str) -> Option<&Node> {
+        self.nodes
+            .iter()
+            .find(|node| node.serial.as_deref() == Some(target) || node.name == target)
+    }
+
+    /// The object.serial `target.object` values refer to a node by
+    fn node_serial(&self, node_id: u32) -> anyhow::Result<&str> {
+        self.nodes
+            .iter()
+            .find(|node| node.id == node_id)
+            .with_context(|| format!("node {node_id} not found"))?
+            .serial
+            .as_deref()
+            .with_context(|| format!("node {node_id} has no object.serial"))
+    }
+
+    fn set_default_node(&self, key: &str, name: &str) -> anyhow::Result<()> {
+        let value = serde_json::to_string(&NameValue {
+            name: name.to_string(),
+        })?;
+        self.metadata.set_property(
+            pipewire::core::PW_ID_CORE,
+            key,
+            Some(JSON_TYPE),
+            Some(&value),
+        );
+        self.conn.roundtrip()
+    }
+}
+
+/// Graph clock settings from the `settings` metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSettings {
+    /// `clock.rate`, the rate the graph runs at unless it is forced or a stream needs another
+    pub rate: u32,
+    /// `clock.force-rate`, if set
+    pub force_rate: Option<u32>,
+}
+
+impl Default for ClockSettings {
+    fn default() -> Self {
+        Self {
+            rate: DEFAULT_CLOCK_RATE,
+            force_rate: None,
+        }
+    }
+}
+
+/// PipeWire's `clock.rate` when none is configured
+const DEFAULT_CLOCK_RATE: u32 = 48000;
+
+impl ClockSettings {
+    pub fn effective_rate(&self) -> u32 {
+        self.force_rate.unwrap_or(self.rate)
+    }
+
+    /// Apply a `settings` metadata property, returning whether it changed the clock
+    pub(crate) fn update(&mut self, key: &str, value: Option<&str>) -> bool {
+        let before = *self;
+        let value = value.and_then(|value| value.parse::<u32>().ok());
+        match key {
+            "clock.rate" => self.rate = value.unwrap_or(DEFAULT_CLOCK_RATE),
+            // A forced rate of 0 means the rate isn't forced
+            "clock.force-rate" => self.force_rate = value.filter(|&rate| rate != 0),
+            _ => {}
+        }
+        *self != before
+    }
+}
+
+pub fn get_clock_settings() -> anyhow::Result<ClockSettings> {
+    let settings = NamedMetadata::new("settings")?;
+    let mut clock = ClockSettings::default();
+    for (key, value) in settings.properties.borrow().iter() {
+        clock.update(key, Some(value));
+    }
+    Ok(clock)
+}
+
+/// The default sink or source the session manager is currently using, if there is one
+pub fn get_default(direction: Direction) -> anyhow::Result<Option<DefaultNode>> {
+    NamedMetadata::new("default")?.default_node(direction.default_key())
+}
+
+/// The default sink or source the user chose, which may differ from the one in use if the chosen
+/// node is unavailable
+pub fn get_configured_default(direction: Direction) -> anyhow::Result<Option<DefaultNode>> {
+    NamedMetadata::new("default")?.default_node(direction.configured_default_key())
+}
+
+/// Make a sink the configured default sink, or a source the configured default source
+pub fn set_default(node_id: u32) -> anyhow::Result<DefaultNode> {
+    let defaults = NamedMetadata::new("default")?;
+    let node = defaults
+        .nodes
+        .iter()
+        .find(|node| node.id == node_id)
+        .with_context(|| format!("node {node_id} not found"))?;
+
+    let direction = match node.media_class.as_deref() {
+        Some(class) if class.contains("Sink") => Direction::Sink,
+        Some(class) if class.contains("Source") => Direction::Source,
+        class => anyhow::bail!(
+            "node {node_id} is not a sink or source (media.class {})",
+            class.unwrap_or("unset")
+        ),
+    };
+
+    defaults.set_default_node(direction.configured_default_key(), &node.name)?;
+    Ok(DefaultNode {
+        id: Some(node.id),
+        name: node.name.clone(),
+    })
+}
+
+/// Forget the default sink or source the user chose, so the session manager picks one by
+/// priority
+pub fn clear_default(direction: Direction) -> anyhow::Result<()> {
+    let defaults = NamedMetadata::new("default")?;
+    defaults.metadata.set_property(
+        pipewire::core::PW_ID_CORE,
+        direction.configured_default_key(),
+        None,
+        None,
+    );
+    defaults.conn.roundtrip()
+}
+
+/// The node each stream is pinned to with `target.object`. Targets naming a node that doesn't
+/// exist are left out.
+pub fn get_stream_targets() -> anyhow::Result<HashMap<u32, u32>> {
+    let defaults = NamedMetadata::new("default")?;
+    let targets = defaults.targets.borrow();
+    let targets = targets
+        .iter()
+        .filter_map(|(&stream_id, target)| Some((stream_id, defaults.target_node(target)?.id)))
+        .collect();
+    Ok(targets)
+}
+
+/// Move a stream onto another node, as `pw-metadata <stream> target.object <serial>` does, or
+/// with `None` let it follow the default again. The session manager relinks the stream
+/// asynchronously.
+pub fn set_stream_target(stream_id: u32, node_id: Option<u32>) -> anyhow::Result<()> {
+    let defaults = NamedMetadata::new("default")?;
+    let serial = node_id
+        .map(|node_id| defaults.node_serial(node_id))
+        .transpose()?;
+
+    let type_ = serial.is_some().then_some("Spa:Id");
+    defaults
+        .metadata
+        .set_property(stream_id, TARGET_KEY, type_, serial);
+    defaults.conn.roundtrip()
+}
+
+/// Where each stream routed onto an EQ was before, by stream id. An origin naming a node that
+/// no longer exists is taken as the default.
+pub fn get_route_origins() -> anyhow::Result<HashMap<u32, RouteOrigin>> {
+    let defaults = NamedMetadata::new("default")?;
+    let origins = defaults.origins.borrow();
+    let mut routed = HashMap::new();
+    for (&stream_id, value) in origins.iter() {
+        let OriginValue { target } = serde_json::from_str(value)
+            .with_context(|| format!("invalid `{ORIGIN_KEY}` metadata value: {value}"))?;
+        let origin = match target.and_then(|target| defaults.target_node(&target)) {
+            Some(node) => RouteOrigin::Pinned(node.id),
+            None => RouteOrigin::Default,
+        };
+        routed.insert(stream_id, origin);
+    }
+    Ok(routed)
+}
+
+/// Record where a stream was before it was routed onto an EQ, or with `None` forget it
+pub fn set_route_origin(stream_id: u32, origin: Option<RouteOrigin>) -> anyhow::Result<()> {
+    let defaults = NamedMetadata::new("default")?;
+    let value = match origin {
+        Some(RouteOrigin::Pinned(node_id)) => Some(OriginValue {
+            target: Some(defaults.node_serial(node_id)?.to_string()),
+        }),
+        Some(RouteOrigin::Default) => Some(OriginValue { target: None }),
+        None => None,
+    };
+    let value = value
+        .map(|value| serde_json::to_string(&value))
+        .transpose()?;
+
+    let type_ = value.is_some().then_some(JSON_TYPE);
+    defaults
+        .metadata
+        .set_property(stream_id, ORIGIN_KEY, type_, value.as_deref());
+    defaults.conn.roundtrip()
+}
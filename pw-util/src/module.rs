@@ -1,32 +1,64 @@
 use anyhow::Context as _;
 
 use crate::apo;
-use std::{fmt, path::Path};
+
+mod diff;
+mod validate;
+
+pub use self::diff::{Change, Diff};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 // Property to mark nodes as managed by pw-eq
-// Ensure this matches the field name in CaptureProps
+// Ensure this matches the field name in CaptureProps and PlaybackProps
 pub const MANAGED_PROP: &str = "pweq.managed";
 pub const FILTER_PREFIX: &str = "pweq.filter_";
+pub const PLUGIN_PREFIX: &str = "pweq.plugin_";
 
+/// A PipeWire config fragment (e.g. a file in `pipewire.conf.d`).
+/// Sections other than `context.modules` are preserved as-is.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Config {
-    #[serde(rename = "context.modules")]
-    pub context_modules: Vec<Module>,
+    #[serde(rename = "context.modules", default)]
+    pub context_modules: Vec<ContextModule>,
+    #[serde(flatten)]
+    pub extra: spa_json::Map<String, spa_json::Value>,
 }
 
 impl Config {
-    pub fn from_kinds(name: &str, preamp: f64, kinds: impl IntoIterator<Item = NodeKind>) -> Self {
+    pub fn from_kinds(
+        name: &str,
+        preamp: f64,
+        layout: &ChannelLayout,
+        kinds: impl IntoIterator<Item = NodeKind>,
+    ) -> Self {
         Config {
-            context_modules: vec![Module::from_kinds(name, preamp, kinds)],
+            context_modules: vec![Module::from_kinds(name, preamp, layout, kinds).into()],
+            extra: Default::default(),
         }
     }
 
-    pub fn from_apo(name: &str, apo: &apo::Config) -> Self {
+    pub fn from_apo(name: &str, layout: &ChannelLayout, apo: &apo::Config) -> Self {
         Config {
-            context_modules: vec![Module::from_apo(name, apo)],
+            context_modules: vec![Module::from_apo(name, layout, apo).into()],
+            extra: Default::default(),
         }
     }
 
+    pub fn from_channel_chains(
+        name: &str,
+        layout: &ChannelLayout,
+        chains: impl IntoIterator<Item = ChannelChain>,
+    ) -> anyhow::Result<Self> {
+        Ok(Config {
+            context_modules: vec![Module::from_channel_chains(name, layout, chains)?.into()],
+            extra: Default::default(),
+        })
+    }
+
     pub fn parse_file(path: &Path) -> anyhow::Result<Self> {
         use std::fs::File;
         use std::io::BufReader;
@@ -35,253 +67,1286 @@ impl Config {
         let config = spa_json::from_reader(reader).context("Failed to parse SPA JSON config")?;
         Ok(config)
     }
+
+    pub fn filter_chains_mut(&mut self) -> impl Iterator<Item = &mut Module> {
+        self.context_modules
+            .iter_mut()
+            .filter_map(|module| match module {
+                ContextModule::FilterChain(module) => Some(module.as_mut()),
+                ContextModule::Other(_) => None,
+            })
+    }
+
+    pub fn filter_chains(&self) -> impl Iterator<Item = &Module> {
+        self.context_modules
+            .iter()
+            .filter_map(|module| match module {
+                ContextModule::FilterChain(module) => Some(module.as_ref()),
+                ContextModule::Other(_) => None,
+            })
+    }
+}
+
+impl From<Module> for Config {
+    fn from(module: Module) -> Self {
+        Config {
+            context_modules: vec![module.into()],
+            extra: Default::default(),
+        }
+    }
+}
+
+pub const FILTER_CHAIN_MODULE: &str = "libpipewire-module-filter-chain";
+
+/// An entry of `context.modules`. Only filter-chain modules are modelled, others are kept verbatim.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(untagged)]
+pub enum ContextModule {
+    FilterChain(Box<Module>),
+    Other(spa_json::Value),
+}
+
+impl From<Module> for ContextModule {
+    fn from(module: Module) -> Self {
+        ContextModule::FilterChain(Box::new(module))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ContextModule {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = spa_json::Value::deserialize(deserializer)?;
+        let is_filter_chain = matches!(
+            &value,
+            spa_json::Value::Object(map)
+                if matches!(map.get("name"), Some(spa_json::Value::String(name)) if name == FILTER_CHAIN_MODULE)
+        );
+
+        if is_filter_chain {
+            // Surface errors in filter-chain modules rather than silently treating them as opaque
+            Module::deserialize(value)
+                .map(ContextModule::from)
+                .map_err(serde::de::Error::custom)
+        } else {
+            Ok(ContextModule::Other(value))
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Module {
     pub name: String,
     pub args: ModuleArgs,
+    /// Other module keys such as `flags` and `condition`
+    #[serde(flatten)]
+    pub extra: spa_json::Map<String, spa_json::Value>,
 }
 
 impl Module {
-    pub fn from_kinds(name: &str, preamp: f64, kinds: impl IntoIterator<Item = NodeKind>) -> Self {
-        let mut kinds = kinds.into_iter().peekable();
+    /// Build a filter-chain module from a single chain of filters.
+    /// filter-chain instantiates one copy of the chain per channel in `layout`.
+    pub fn from_kinds(
+        name: &str,
+        preamp: f64,
+        layout: &ChannelLayout,
+        kinds: impl IntoIterator<Item = NodeKind>,
+    ) -> Self {
+        let (nodes, links) = build_chain(None, preamp, kinds);
+        Self::filter_chain(
+            name,
+            layout,
+            FilterGraph {
+                nodes: nodes.into_boxed_slice(),
+                links,
+                inputs: vec![],
+                outputs: vec![],
+                extra: Default::default(),
+            },
+        )
+    }
 
-        let preamp_node = Node {
-            node_type: NodeType::Builtin,
-            name: format!("{FILTER_PREFIX}preamp"),
-            kind: NodeKind::HighShelf {
-                control: Control {
-                    // pipewire biquad high-shelf has a special case for freq=0 that applies gain uniformly
-                    freq: 0.0,
-                    q: 0.0,
-                    gain: preamp,
-                },
+    /// Build a filter-chain module with an independent chain per channel.
+    /// Every position in `layout` must have exactly one chain.
+    pub fn from_channel_chains(
+        name: &str,
+        layout: &ChannelLayout,
+        chains: impl IntoIterator<Item = ChannelChain>,
+    ) -> anyhow::Result<Self> {
+        let mut chains = chains.into_iter().collect::<Vec<_>>();
+        let mut nodes = vec![];
+        let mut links = vec![];
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+
+        for &position in layout.positions() {
+            let idx = chains
+                .iter()
+                .position(|chain| chain.position == position)
+                .with_context(|| format!("missing filter chain for channel {position}"))?;
+            let chain = chains.remove(idx);
+
+            let (chain_nodes, chain_links) = build_chain(Some(position), chain.preamp, chain.kinds);
+            // build_chain always yields at least the preamp or param_eq node
+            inputs.push(format!("{}:In", chain_nodes[0].name));
+            outputs.push(format!("{}:Out", chain_nodes[chain_nodes.len() - 1].name));
+            nodes.extend(chain_nodes);
+            links.extend(chain_links);
+        }
+
+        if let Some(chain) = chains.first() {
+            anyhow::bail!(
+                "filter chain for channel {} is not part of the {layout} layout",
+                chain.position
+            );
+        }
+
+        Ok(Self::filter_chain(
+            name,
+            layout,
+            FilterGraph {
+                nodes: nodes.into_boxed_slice(),
+                links,
+                inputs,
+                outputs,
+                extra: Default::default(),
             },
+        ))
+    }
+
+    /// Append plugin stages after the filters. Graphs with explicit outputs (per-channel chains)
+    /// get a copy of every stage for each output.
+    pub fn append_plugins(&mut self, plugins: &[PluginStage]) {
+        if plugins.is_empty() {
+            return;
+        }
+
+        let graph = &mut self.args.filter_graph;
+        let mut nodes = std::mem::take(&mut graph.nodes).into_vec();
+        // Without explicit outputs filter-chain uses the outputs of the last node
+        let last_output = nodes.last().map(|node| match node.node_type {
+            NodeType::Builtin => format!("{}:Out", node.name),
+            _ => format!("{}:0", node.name),
+        });
+
+        let mut append = |channel: Option<AudioPosition>, mut tail: Option<String>| {
+            for (i, stage) in plugins.iter().enumerate() {
+                let node = stage.to_node(plugin_node_name(channel, i + 1));
+                if let Some(output) = tail {
+                    graph.links.push(Link {
+                        output,
+                        input: stage.input_port(&node.name),
+                    });
+                }
+                tail = Some(stage.output_port(&node.name));
+                nodes.push(node);
+            }
+            tail
         };
 
-        let nodes: Vec<Node> = if let Some(NodeKind::ParamEq { config }) = kinds.peek() {
-            // If using param_eq, integrate preamp into that node
-            let mut filters = config.filters.clone();
-            filters.insert(
-                0,
-                ParamEqFilter {
-                    ty: FilterType::HighShelf,
-                    control: Control {
-                        freq: 0.0,
-                        q: 0.0,
-                        gain: preamp,
-                    },
-                },
-            );
-            let param_eq_node = Node {
-                node_type: NodeType::Builtin,
-                name: format!("{FILTER_PREFIX}1"),
-                kind: NodeKind::ParamEq {
-                    config: ParamEqConfig { filters },
-                },
-            };
-            vec![param_eq_node]
+        if graph.outputs.is_empty() {
+            append(None, last_output);
         } else {
-            std::iter::once(preamp_node)
-                .chain(kinds.enumerate().map(|(i, kind)| Node {
-                    node_type: NodeType::Builtin,
-                    name: format!("{FILTER_PREFIX}{}", i + 1),
-                    kind,
-                }))
-                .collect()
-        };
+            for (i, output) in std::mem::take(&mut graph.outputs).into_iter().enumerate() {
+                let channel = self
+                    .args
+                    .audio_position
+                    .get(i)
+                    .copied()
+                    .unwrap_or(AudioPosition::Aux(i as u32));
+                let tail = append(Some(channel), Some(output)).expect("plugins is not empty");
+                graph.outputs.push(tail);
+            }
+        }
 
-        let links: Vec<Link> = (0..nodes.len().saturating_sub(1))
-            .map(|i| Link {
-                output: format!("{}:Out", nodes[i].name),
-                input: format!("{}:In", nodes[i + 1].name),
-            })
-            .collect();
+        graph.nodes = nodes.into_boxed_slice();
+    }
+
+    /// Expose the EQ as a virtual sink (the default) or as a virtual source that filters a
+    /// capture device.
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        let name = &self.args.media_name;
+        let (capture, playback) = (&mut self.args.capture_props, &mut self.args.playback_props);
+        let target = capture
+            .target_object
+            .take()
+            .or(playback.target_object.take());
+        match direction {
+            Direction::Sink => {
+                capture.node_name = format!("effect_output.pweq.{name}");
+                capture.node_passive = None;
+                capture.media_class = Some(direction.media_class().to_string());
+                capture.pweq_managed = true;
+                playback.node_name = format!("effect_input.pweq.{name}");
+                playback.node_passive = Some(false);
+                playback.media_class = None;
+                playback.pweq_managed = false;
+            }
+            Direction::Source => {
+                // Capture from the source device, without keeping it busy while unused
+                capture.node_name = format!("effect_input.pweq.source.{name}");
+                capture.node_passive = Some(true);
+                capture.media_class = None;
+                capture.pweq_managed = false;
+                playback.node_name = format!("effect_output.pweq.source.{name}");
+                playback.node_passive = None;
+                playback.media_class = Some(direction.media_class().to_string());
+                playback.pweq_managed = true;
+            }
+        }
+        self.args.set_target(target);
+        self
+    }
+
+    /// Bind the EQ to a device by node name, or follow the default device if `None`
+    pub fn with_target(mut self, target: Option<String>) -> Self {
+        self.args.set_target(target);
+        self
+    }
+
+    /// Merge extra properties into the module args and stream props, overriding existing keys
+    pub fn with_props(mut self, props: &ExtraProps) -> anyhow::Result<Self> {
+        merge_props(&mut self.args.capture_props, &props.capture_props)
+            .context("invalid capture.props")?;
+        merge_props(&mut self.args.playback_props, &props.playback_props)
+            .context("invalid playback.props")?;
+        merge_props(&mut self.args, &props.args).context("invalid module args")?;
+        Ok(self)
+    }
 
-        let audio_position = vec![AudioPosition::FrontLeft, AudioPosition::FrontRight];
+    fn filter_chain(name: &str, layout: &ChannelLayout, filter_graph: FilterGraph) -> Self {
         Module {
-            name: "libpipewire-module-filter-chain".to_string(),
+            name: FILTER_CHAIN_MODULE.to_string(),
             args: ModuleArgs {
                 node_description: format!("{name} equalizer"),
                 media_name: name.to_string(),
-                audio_channels: audio_position.len(),
-                audio_position,
-                filter_graph: FilterGraph {
-                    nodes: nodes.into_boxed_slice(),
-                    links,
-                },
-                playback_props: PlaybackProps {
-                    node_name: format!("effect_input.pweq.{name}"),
-                    node_passive: false,
-                },
-                capture_props: CaptureProps {
-                    node_name: format!("effect_output.pweq.{name}"),
-                    media_class: "Audio/Sink".to_string(),
-                    pweq_managed: true,
-                },
+                audio_channels: Some(layout.channels()),
+                audio_position: layout.positions().to_vec(),
+                filter_graph,
+                playback_props: Default::default(),
+                capture_props: Default::default(),
+                extra: Default::default(),
             },
+            extra: Default::default(),
         }
+        .with_direction(Direction::Sink)
     }
 
-    pub fn from_apo(name: &str, apo: &apo::Config) -> Self {
-        let kinds = apo.filters.iter().map(|filter| {
-            let control = Control {
-                freq: filter.frequency,
-                q: filter.q,
-                gain: filter.gain,
-            };
-            match filter.filter_type {
-                FilterType::LowShelf => NodeKind::LowShelf { control },
-                FilterType::LowPass => NodeKind::LowPass { control },
-                FilterType::Peaking => NodeKind::Peaking { control },
-                FilterType::BandPass => NodeKind::BandPass { control },
-                FilterType::Notch => NodeKind::Notch { control },
-                FilterType::HighPass => NodeKind::HighPass { control },
-                FilterType::HighShelf => NodeKind::HighShelf { control },
+    /// Convert a linear chain of biquad or `param_eq` nodes back into APO form.
+    /// A uniform high-shelf at 0 Hz (as written by pw-eq) is treated as the preamp.
+    /// Graphs with a separate chain per channel are an error, as APO has one filter list.
+    pub fn to_apo(&self) -> anyhow::Result<apo::Config> {
+        let graph = &self.args.filter_graph;
+        let chains = graph
+            .inputs
+            .iter()
+            .map(|input| {
+                input
+                    .split_once(':')
+                    .map_or(input.as_str(), |(node, _)| node)
+            })
+            .collect::<std::collections::BTreeSet<_>>()
+            .len();
+        anyhow::ensure!(
+            chains <= 1,
+            "cannot convert a filter chain per channel ({chains} chains) to a single filter list"
+        );
+
+        let mut preamp = 0.0;
+        let mut filters = vec![];
+
+        let mut push = |ty: FilterType, control: &Control| {
+            if ty == FilterType::HighShelf && control.freq == 0.0 {
+                preamp += control.gain;
+                return;
             }
-        });
 
-        Self::from_kinds(name, apo.preamp, kinds)
+            filters.push(apo::Filter {
+                number: filters.len() as u32 + 1,
+                enabled: true,
+                filter_type: ty,
+                frequency: control.freq,
+                gain: control.gain,
+                q: control.q,
+            });
+        };
+
+        for node in graph.chain()? {
+            // Plugin stages are not part of the EQ
+            if node.node_type != NodeType::Builtin {
+                continue;
+            }
+
+            match &node.kind {
+                NodeKind::ParamEq { config } => {
+                    for filter in config.load_filters()? {
+                        push(filter.ty, &filter.control);
+                    }
+                }
+                NodeKind::Raw { .. } => {
+                    anyhow::bail!("cannot convert raw biquad node `{}` to a filter", node.name)
+                }
+                NodeKind::Other(other) => {
+                    anyhow::bail!(
+                        "cannot convert `{}` node `{}` to a filter",
+                        other.label.as_deref().unwrap_or_default(),
+                        node.name
+                    )
+                }
+                kind => {
+                    let (ty, control) = kind.biquad().expect("remaining node kinds are biquads");
+                    push(ty, control);
+                }
+            }
+        }
+
+        Ok(apo::Config { preamp, filters })
+    }
+
+    pub fn from_apo(name: &str, layout: &ChannelLayout, apo: &apo::Config) -> Self {
+        Self::from_kinds(name, apo.preamp, layout, apo_kinds(apo))
+    }
+
+    /// Build a module with a single `param_eq` node that loads its filters (and preamp) from an
+    /// APO file when the module is loaded, rather than inlining them.
+    pub fn from_apo_file(name: &str, layout: &ChannelLayout, path: &Path) -> anyhow::Result<Self> {
+        // param_eq filters up to 8 channels with one set of filters
+        const MAX_PORTS: usize = 8;
+        if layout.channels() > MAX_PORTS {
+            anyhow::bail!(
+                "param_eq supports at most {MAX_PORTS} channels, got {}",
+                layout.channels()
+            );
+        }
+
+        let node = Node {
+            node_type: NodeType::Builtin,
+            name: filter_node_name(None, 1),
+            plugin: None,
+            kind: NodeKind::ParamEq {
+                config: ParamEqConfig {
+                    filename: Some(path.to_path_buf()),
+                    ..Default::default()
+                },
+            },
+        };
+        let ports = |prefix: &str| {
+            (1..=layout.channels())
+                .map(|port| format!("{}:{prefix} {port}", node.name))
+                .collect()
+        };
+        let inputs = ports("In");
+        let outputs = ports("Out");
+
+        Ok(Self::filter_chain(
+            name,
+            layout,
+            FilterGraph {
+                nodes: Box::new([node]),
+                links: vec![],
+                inputs,
+                outputs,
+                extra: Default::default(),
+            },
+        ))
+    }
+}
+
+/// A chain of filters applied to a single channel
+#[derive(Debug, Clone)]
+pub struct ChannelChain {
+    pub position: AudioPosition,
+    pub preamp: f64,
+    pub kinds: Vec<NodeKind>,
+}
+
+impl ChannelChain {
+    pub fn from_apo(position: AudioPosition, apo: &apo::Config) -> Self {
+        Self {
+            position,
+            preamp: apo.preamp,
+            kinds: apo_kinds(apo).collect(),
+        }
+    }
+}
+
+fn apo_kinds(apo: &apo::Config) -> impl Iterator<Item = NodeKind> + '_ {
+    apo.filters.iter().map(|filter| {
+        let control = Control {
+            freq: filter.frequency,
+            q: filter.q,
+            gain: filter.gain,
+            ..Default::default()
+        };
+        match filter.filter_type {
+            FilterType::LowShelf => NodeKind::LowShelf { control },
+            FilterType::LowPass => NodeKind::LowPass { control },
+            FilterType::Peaking => NodeKind::Peaking { control },
+            FilterType::BandPass => NodeKind::BandPass { control },
+            FilterType::Notch => NodeKind::Notch { control },
+            FilterType::HighPass => NodeKind::HighPass { control },
+            FilterType::HighShelf => NodeKind::HighShelf { control },
+        }
+    })
+}
+
+/// Name of the filter-chain node for a band, e.g. `pweq.filter_3`, or `pweq.filter_FL_3` in
+/// per-channel chains. Controls are addressed as `<node name>:<control>`.
+pub fn filter_node_name(channel: Option<AudioPosition>, band: impl fmt::Display) -> String {
+    match channel {
+        Some(position) => format!("{FILTER_PREFIX}{position}_{band}"),
+        None => format!("{FILTER_PREFIX}{band}"),
+    }
+}
+
+/// Inverse of [`filter_node_name`], returning the channel and band parts of the name
+pub fn parse_filter_node_name(name: &str) -> Option<(Option<AudioPosition>, &str)> {
+    let suffix = name.strip_prefix(FILTER_PREFIX)?;
+    match suffix.split_once('_') {
+        Some((position, band)) => Some((Some(position.parse().ok()?), band)),
+        None => Some((None, suffix)),
+    }
+}
+
+/// Name of the filter-chain node for a plugin stage, e.g. `pweq.plugin_1` or `pweq.plugin_FL_1`
+pub fn plugin_node_name(channel: Option<AudioPosition>, index: usize) -> String {
+    match channel {
+        Some(position) => format!("{PLUGIN_PREFIX}{position}_{index}"),
+        None => format!("{PLUGIN_PREFIX}{index}"),
     }
 }
 
+/// Build a linear chain with a leading preamp, returning its nodes and the links between them
+fn build_chain(
+    channel: Option<AudioPosition>,
+    preamp: f64,
+    kinds: impl IntoIterator<Item = NodeKind>,
+) -> (Vec<Node>, Vec<Link>) {
+    let mut kinds = kinds.into_iter().peekable();
+
+    let preamp_node = Node {
+        node_type: NodeType::Builtin,
+        name: filter_node_name(channel, "preamp"),
+        plugin: None,
+        kind: NodeKind::HighShelf {
+            control: Control {
+                // pipewire biquad high-shelf has a special case for freq=0 that applies gain uniformly
+                freq: 0.0,
+                q: 0.0,
+                gain: preamp,
+                ..Default::default()
+            },
+        },
+    };
+
+    let nodes: Vec<Node> = if let Some(NodeKind::ParamEq { config }) = kinds.peek() {
+        // If using param_eq, integrate preamp into that node
+        let mut filters = config.filters.clone();
+        filters.insert(
+            0,
+            ParamEqFilter {
+                ty: FilterType::HighShelf,
+                control: Control {
+                    freq: 0.0,
+                    q: 0.0,
+                    gain: preamp,
+                    ..Default::default()
+                },
+            },
+        );
+        let param_eq_node = Node {
+            node_type: NodeType::Builtin,
+            name: filter_node_name(channel, 1),
+            plugin: None,
+            kind: NodeKind::ParamEq {
+                config: ParamEqConfig {
+                    filters,
+                    ..Default::default()
+                },
+            },
+        };
+        vec![param_eq_node]
+    } else {
+        std::iter::once(preamp_node)
+            .chain(kinds.enumerate().map(|(i, kind)| Node {
+                node_type: NodeType::Builtin,
+                name: filter_node_name(channel, i + 1),
+                plugin: None,
+                kind,
+            }))
+            .collect()
+    };
+
+    let links: Vec<Link> = (0..nodes.len().saturating_sub(1))
+        .map(|i| Link {
+            output: format!("{}:Out", nodes[i].name),
+            input: format!("{}:In", nodes[i + 1].name),
+        })
+        .collect();
+
+    (nodes, links)
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ModuleArgs {
-    #[serde(rename = "node.description")]
+    #[serde(
+        rename = "node.description",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
     pub node_description: String,
-    #[serde(rename = "media.name")]
+    #[serde(
+        rename = "media.name",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
     pub media_name: String,
     #[serde(rename = "filter.graph")]
     pub filter_graph: FilterGraph,
-    #[serde(rename = "audio.channels")]
-    pub audio_channels: usize,
+    #[serde(
+        rename = "audio.channels",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub audio_channels: Option<usize>,
+    // Older pw-eq versions wrote this key with an underscore, which PipeWire ignores
+    #[serde(
+        rename = "audio.position",
+        alias = "audio_position",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
     pub audio_position: Vec<AudioPosition>,
-    #[serde(rename = "playback.props")]
+    #[serde(rename = "playback.props", default)]
     pub playback_props: PlaybackProps,
-    #[serde(rename = "capture.props")]
+    #[serde(rename = "capture.props", default)]
     pub capture_props: CaptureProps,
+    #[serde(flatten)]
+    pub extra: spa_json::Map<String, spa_json::Value>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+impl ModuleArgs {
+    pub fn direction(&self) -> Direction {
+        match self.playback_props.media_class.as_deref() {
+            Some(class) if class == Direction::Source.media_class() => Direction::Source,
+            _ => Direction::Sink,
+        }
+    }
+
+    /// The device the EQ is bound to: the playback target of a sink, or the capture target of a
+    /// source
+    pub fn target(&self) -> Option<&str> {
+        match self.direction() {
+            Direction::Sink => self.playback_props.target_object.as_deref(),
+            Direction::Source => self.capture_props.target_object.as_deref(),
+        }
+    }
+
+    pub fn set_target(&mut self, target: Option<String>) {
+        match self.direction() {
+            Direction::Sink => self.playback_props.target_object = target,
+            Direction::Source => self.capture_props.target_object = target,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct PlaybackProps {
-    #[serde(rename = "node.name")]
+    #[serde(
+        rename = "node.name",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
     pub node_name: String,
-    #[serde(rename = "node.passive")]
-    pub node_passive: bool,
+    #[serde(
+        rename = "node.passive",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub node_passive: Option<bool>,
+    #[serde(
+        rename = "target.object",
+        alias = "node.target",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub target_object: Option<String>,
+    #[serde(
+        rename = "media.class",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub media_class: Option<String>,
+    // Set instead of the capture side in source mode
+    #[serde(default, rename = "pweq.managed", skip_serializing_if = "is_false")]
+    pub pweq_managed: bool,
+    #[serde(flatten)]
+    pub extra: spa_json::Map<String, spa_json::Value>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct CaptureProps {
-    #[serde(rename = "node.name")]
+    #[serde(
+        rename = "node.name",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
     pub node_name: String,
-    #[serde(rename = "media.class")]
-    pub media_class: String,
+    #[serde(
+        rename = "node.passive",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub node_passive: Option<bool>,
+    #[serde(
+        rename = "target.object",
+        alias = "node.target",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub target_object: Option<String>,
+    #[serde(
+        rename = "media.class",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub media_class: Option<String>,
     // Ensure this rename matches the constant MANAGED_PROP
-    #[serde(default, rename = "pweq.managed")]
+    #[serde(default, rename = "pweq.managed", skip_serializing_if = "is_false")]
     pub pweq_managed: bool,
+    #[serde(flatten)]
+    pub extra: spa_json::Map<String, spa_json::Value>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// Extra properties to set on an EQ, e.g. `node.latency` or `node.autoconnect`
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ExtraProps {
+    /// Top-level module args
+    pub args: spa_json::Map<String, spa_json::Value>,
+    #[serde(rename = "capture.props")]
+    pub capture_props: spa_json::Map<String, spa_json::Value>,
+    #[serde(rename = "playback.props")]
+    pub playback_props: spa_json::Map<String, spa_json::Value>,
+}
+
+impl ExtraProps {
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty() && self.capture_props.is_empty() && self.playback_props.is_empty()
+    }
+
+    /// Right-biased merge, keys in `other` win
+    pub fn merge(mut self, other: ExtraProps) -> Self {
+        self.args.extend(other.args);
+        self.capture_props.extend(other.capture_props);
+        self.playback_props.extend(other.playback_props);
+        self
+    }
+}
+
+// Round-trip through a map so modelled keys (e.g. `media.class`) land in their typed fields
+fn merge_props<T>(
+    value: &mut T,
+    props: &spa_json::Map<String, spa_json::Value>,
+) -> anyhow::Result<()>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    if props.is_empty() {
+        return Ok(());
+    }
+
+    let spa_json::Value::Object(mut map) = spa_json::to_value(&*value)? else {
+        unreachable!("props serialize to a map")
+    };
+    map.extend(props.clone());
+    *value = T::deserialize(spa_json::Value::Object(map))?;
+    Ok(())
+}
+
+/// Whether an EQ filters playback or capture
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Direction {
+    /// A virtual `Audio/Sink` that applications play into
+    #[default]
+    Sink,
+    /// A virtual `Audio/Source` that reads from a capture device (e.g. a microphone)
+    Source,
+}
+
+impl Direction {
+    pub fn from_source_flag(source: bool) -> Self {
+        if source {
+            Direction::Source
+        } else {
+            Direction::Sink
+        }
+    }
+
+    pub fn media_class(self) -> &'static str {
+        match self {
+            Direction::Sink => "Audio/Sink",
+            Direction::Source => "Audio/Source",
+        }
+    }
+}
+
+/// SPA channel position, serialized using PipeWire's short names (e.g. `FL`, `LFE`, `AUX3`)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum AudioPosition {
-    #[serde(rename = "FL")]
+    Mono,
     FrontLeft,
-    #[serde(rename = "FR")]
     FrontRight,
-    #[serde(rename = "FC")]
     FrontCenter,
-    #[serde(rename = "LFE")]
     LowFrequency,
-    #[serde(rename = "SL")]
     SideLeft,
-    #[serde(rename = "SR")]
     SideRight,
-    #[serde(rename = "BL")]
+    RearLeft,
+    RearRight,
     BackLeft,
-    #[serde(rename = "BR")]
     BackRight,
+    Aux(u32),
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct FilterGraph {
-    pub nodes: Box<[Node]>,
-    #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    pub links: Vec<Link>,
+impl fmt::Display for AudioPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioPosition::Mono => write!(f, "MONO"),
+            AudioPosition::FrontLeft => write!(f, "FL"),
+            AudioPosition::FrontRight => write!(f, "FR"),
+            AudioPosition::FrontCenter => write!(f, "FC"),
+            AudioPosition::LowFrequency => write!(f, "LFE"),
+            AudioPosition::SideLeft => write!(f, "SL"),
+            AudioPosition::SideRight => write!(f, "SR"),
+            AudioPosition::RearLeft => write!(f, "RL"),
+            AudioPosition::RearRight => write!(f, "RR"),
+            AudioPosition::BackLeft => write!(f, "BL"),
+            AudioPosition::BackRight => write!(f, "BR"),
+            AudioPosition::Aux(n) => write!(f, "AUX{n}"),
+        }
+    }
 }
 
-// Make this an enum of bq_raw and param_eq
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Node {
-    #[serde(rename = "type")]
-    pub node_type: NodeType,
-    pub name: String,
-    #[serde(flatten)]
-    pub kind: NodeKind,
-}
+impl FromStr for AudioPosition {
+    type Err = anyhow::Error;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-#[serde(tag = "label")]
-pub enum NodeKind {
-    #[serde(rename = "bq_peaking")]
-    Peaking { control: Control },
-    #[serde(rename = "bq_lowshelf")]
-    LowShelf { control: Control },
-    #[serde(rename = "bq_highshelf")]
-    HighShelf { control: Control },
-    #[serde(rename = "bq_lowpass")]
-    LowPass { control: Control },
-    #[serde(rename = "bq_bandpass")]
-    BandPass { control: Control },
-    #[serde(rename = "bq_notch")]
-    Notch { control: Control },
-    #[serde(rename = "bq_highpass")]
-    HighPass { control: Control },
-    #[serde(rename = "bq_raw")]
-    Raw { config: RawNodeConfig },
-    #[serde(rename = "param_eq")]
-    ParamEq { config: ParamEqConfig },
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let position = match s.to_ascii_uppercase().as_str() {
+            "MONO" => AudioPosition::Mono,
+            "FL" => AudioPosition::FrontLeft,
+            "FR" => AudioPosition::FrontRight,
+            "FC" => AudioPosition::FrontCenter,
+            "LFE" => AudioPosition::LowFrequency,
+            "SL" => AudioPosition::SideLeft,
+            "SR" => AudioPosition::SideRight,
+            "RL" => AudioPosition::RearLeft,
+            "RR" => AudioPosition::RearRight,
+            "BL" => AudioPosition::BackLeft,
+            "BR" => AudioPosition::BackRight,
+            other => {
+                let n = other
+                    .strip_prefix("AUX")
+                    .and_then(|n| n.parse().ok())
+                    .with_context(|| format!("unknown audio position: {s}"))?;
+                AudioPosition::Aux(n)
+            }
+        };
+        Ok(position)
+    }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct ParamEqConfig {
-    pub filters: Vec<ParamEqFilter>,
+impl serde::Serialize for AudioPosition {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct ParamEqFilter {
-    #[serde(rename = "type")]
-    pub ty: FilterType,
-    #[serde(flatten)]
-    pub control: Control,
+impl<'de> serde::Deserialize<'de> for AudioPosition {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct RawNodeConfig {
-    pub coefficients: Vec<RateAndBiquadCoefficients>,
+/// Ordered set of channel positions the filter-chain exposes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelLayout {
+    positions: Vec<AudioPosition>,
 }
 
-/// Sample rate mapped to biquad coefficients
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct RateAndBiquadCoefficients {
-    pub rate: u32,
-    #[serde(flatten)]
-    pub coefficients: BiquadCoefficients,
-}
+impl ChannelLayout {
+    pub fn new(positions: impl IntoIterator<Item = AudioPosition>) -> anyhow::Result<Self> {
+        let positions = positions.into_iter().collect::<Vec<_>>();
+        anyhow::ensure!(!positions.is_empty(), "channel layout must not be empty");
+        for (i, position) in positions.iter().enumerate() {
+            anyhow::ensure!(
+                !positions[..i].contains(position),
+                "duplicate channel position in layout: {position}"
+            );
+        }
+        Ok(Self { positions })
+    }
 
-#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
-/// Normalized biquad coefficients, with a0 = 1.0
-pub struct BiquadCoefficients {
-    pub b0: f64,
+    pub fn mono() -> Self {
+        Self {
+            positions: vec![AudioPosition::Mono],
+        }
+    }
+
+    pub fn stereo() -> Self {
+        Self {
+            positions: vec![AudioPosition::FrontLeft, AudioPosition::FrontRight],
+        }
+    }
+
+    pub fn surround_2_1() -> Self {
+        Self {
+            positions: vec![
+                AudioPosition::FrontLeft,
+                AudioPosition::FrontRight,
+                AudioPosition::LowFrequency,
+            ],
+        }
+    }
+
+    pub fn surround_5_1() -> Self {
+        Self {
+            positions: vec![
+                AudioPosition::FrontLeft,
+                AudioPosition::FrontRight,
+                AudioPosition::FrontCenter,
+                AudioPosition::LowFrequency,
+                AudioPosition::SideLeft,
+                AudioPosition::SideRight,
+            ],
+        }
+    }
+
+    pub fn surround_7_1() -> Self {
+        Self {
+            positions: vec![
+                AudioPosition::FrontLeft,
+                AudioPosition::FrontRight,
+                AudioPosition::FrontCenter,
+                AudioPosition::LowFrequency,
+                AudioPosition::RearLeft,
+                AudioPosition::RearRight,
+                AudioPosition::SideLeft,
+                AudioPosition::SideRight,
+            ],
+        }
+    }
+
+    /// `AUX0` to `AUX{n-1}`, for interfaces without a meaningful speaker arrangement
+    pub fn aux(n: u32) -> anyhow::Result<Self> {
+        Self::new((0..n).map(AudioPosition::Aux))
+    }
+
+    pub fn positions(&self) -> &[AudioPosition] {
+        &self.positions
+    }
+
+    pub fn channels(&self) -> usize {
+        self.positions.len()
+    }
+}
+
+impl Default for ChannelLayout {
+    fn default() -> Self {
+        Self::stereo()
+    }
+}
+
+impl fmt::Display for ChannelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let preset = [
+            ("mono", Self::mono()),
+            ("stereo", Self::stereo()),
+            ("2.1", Self::surround_2_1()),
+            ("5.1", Self::surround_5_1()),
+            ("7.1", Self::surround_7_1()),
+        ]
+        .into_iter()
+        .find(|(_, layout)| layout == self);
+
+        match preset {
+            Some((name, _)) => write!(f, "{name}"),
+            None => {
+                for (i, position) in self.positions.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{position}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for ChannelLayout {
+    type Err = anyhow::Error;
+
+    /// Accepts a preset (`mono`, `stereo`, `2.1`, `5.1`, `7.1`, `aux<N>`)
+    /// or a comma-separated list of positions (e.g. `FL,FR,AUX0`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mono" | "1.0" => Ok(Self::mono()),
+            "stereo" | "2.0" => Ok(Self::stereo()),
+            "2.1" => Ok(Self::surround_2_1()),
+            "5.1" => Ok(Self::surround_5_1()),
+            "7.1" => Ok(Self::surround_7_1()),
+            lower => {
+                if let Some(n) = lower.strip_prefix("aux")
+                    && let Ok(n) = n.parse::<u32>()
+                {
+                    return Self::aux(n);
+                }
+
+                Self::new(
+                    s.split(',')
+                        .map(|position| position.trim().parse())
+                        .collect::<anyhow::Result<Vec<_>>>()?,
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FilterGraph {
+    pub nodes: Box<[Node]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<Link>,
+    /// Graph input ports, one per channel. When empty, filter-chain duplicates the graph per channel.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>,
+    /// Graph output ports, one per channel. When empty, filter-chain duplicates the graph per channel.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<String>,
+    #[serde(flatten)]
+    pub extra: spa_json::Map<String, spa_json::Value>,
+}
+
+impl FilterGraph {
+    /// Nodes of a linear graph in processing order, starting from the first graph input.
+    pub fn chain(&self) -> anyhow::Result<Vec<&Node>> {
+        let node = |name: &str| {
+            self.nodes
+                .iter()
+                .find(|node| node.name == name)
+                .with_context(|| format!("link refers to unknown node `{name}`"))
+        };
+        fn port_node(port: &str) -> &str {
+            port.split_once(':').map_or(port, |(node, _)| node)
+        }
+
+        let mut current = match self.inputs.first() {
+            Some(input) => node(port_node(input))?,
+            // Without explicit inputs filter-chain uses the first node
+            None => match self.nodes.first() {
+                Some(first) => first,
+                None => return Ok(vec![]),
+            },
+        };
+
+        let mut chain = vec![current];
+        while let Some(link) = self
+            .links
+            .iter()
+            .find(|link| port_node(&link.output) == current.name)
+        {
+            current = node(port_node(&link.input))?;
+            anyhow::ensure!(
+                !chain.iter().any(|node| node.name == current.name),
+                "filter graph contains a cycle through `{}`",
+                current.name
+            );
+            chain.push(current);
+        }
+
+        Ok(chain)
+    }
+}
+
+// Make this an enum of bq_raw and param_eq
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Node {
+    #[serde(rename = "type")]
+    pub node_type: NodeType,
+    pub name: String,
+    /// Plugin path or URI of LADSPA and LV2 nodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugin: Option<String>,
+    #[serde(flatten)]
+    pub kind: NodeKind,
+}
+
+// `remote = "Self"` makes the derives inherent functions, wrapped below to reject malformed
+// builtins instead of keeping them as `Other`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "label", remote = "Self")]
+pub enum NodeKind {
+    #[serde(rename = "bq_peaking")]
+    Peaking {
+        #[serde(default = "Control::unset", skip_serializing_if = "Control::is_unset")]
+        control: Control,
+    },
+    #[serde(rename = "bq_lowshelf")]
+    LowShelf {
+        #[serde(default = "Control::unset", skip_serializing_if = "Control::is_unset")]
+        control: Control,
+    },
+    #[serde(rename = "bq_highshelf")]
+    HighShelf {
+        #[serde(default = "Control::unset", skip_serializing_if = "Control::is_unset")]
+        control: Control,
+    },
+    #[serde(rename = "bq_lowpass")]
+    LowPass {
+        #[serde(default = "Control::unset", skip_serializing_if = "Control::is_unset")]
+        control: Control,
+    },
+    #[serde(rename = "bq_bandpass")]
+    BandPass {
+        #[serde(default = "Control::unset", skip_serializing_if = "Control::is_unset")]
+        control: Control,
+    },
+    #[serde(rename = "bq_notch")]
+    Notch {
+        #[serde(default = "Control::unset", skip_serializing_if = "Control::is_unset")]
+        control: Control,
+    },
+    #[serde(rename = "bq_highpass")]
+    HighPass {
+        #[serde(default = "Control::unset", skip_serializing_if = "Control::is_unset")]
+        control: Control,
+    },
+    #[serde(rename = "bq_raw")]
+    Raw { config: RawNodeConfig },
+    #[serde(rename = "param_eq")]
+    ParamEq { config: ParamEqConfig },
+    /// Any other node (`mixer`, `delay`, `convolver`, plugins, ...), kept verbatim
+    #[serde(untagged)]
+    Other(OtherNode),
+}
+
+impl serde::Serialize for NodeKind {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        NodeKind::serialize(self, serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for NodeKind {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let kind = NodeKind::deserialize(deserializer)?;
+        // A modelled label only falls through to `Other` if its settings have the wrong types
+        // or unknown keys
+        if let NodeKind::Other(OtherNode {
+            label: Some(label), ..
+        }) = &kind
+        {
+            let field = match label.as_str() {
+                "bq_peaking" | "bq_lowshelf" | "bq_highshelf" | "bq_lowpass" | "bq_bandpass"
+                | "bq_notch" | "bq_highpass" => "control",
+                "bq_raw" | "param_eq" => "config",
+                _ => return Ok(kind),
+            };
+            return Err(serde::de::Error::custom(format!(
+                "malformed `{field}` for `{label}` node"
+            )));
+        }
+        Ok(kind)
+    }
+}
+
+/// A node with a label pw-eq doesn't model
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OtherNode {
+    /// Not required for LV2 nodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control: Option<spa_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<spa_json::Value>,
+    #[serde(flatten)]
+    pub extra: spa_json::Map<String, spa_json::Value>,
+}
+
+impl NodeKind {
+    /// The filter type and controls of a biquad node
+    pub fn biquad(&self) -> Option<(FilterType, &Control)> {
+        match self {
+            NodeKind::Peaking { control } => Some((FilterType::Peaking, control)),
+            NodeKind::LowShelf { control } => Some((FilterType::LowShelf, control)),
+            NodeKind::HighShelf { control } => Some((FilterType::HighShelf, control)),
+            NodeKind::LowPass { control } => Some((FilterType::LowPass, control)),
+            NodeKind::BandPass { control } => Some((FilterType::BandPass, control)),
+            NodeKind::Notch { control } => Some((FilterType::Notch, control)),
+            NodeKind::HighPass { control } => Some((FilterType::HighPass, control)),
+            NodeKind::Raw { .. } | NodeKind::ParamEq { .. } | NodeKind::Other(_) => None,
+        }
+    }
+}
+
+/// `param_eq` configuration, with filters inline and/or loaded from an APO file
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct ParamEqConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<ParamEqFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<PathBuf>,
+    /// Per-port variants (`filters1`, `filename2`, ...) and other keys
+    #[serde(flatten)]
+    pub extra: spa_json::Map<String, spa_json::Value>,
+}
+
+impl ParamEqConfig {
+    /// The inline filters followed by the filters of `filename`, if any.
+    /// The file's preamp is returned as a uniform high-shelf filter.
+    pub fn load_filters(&self) -> anyhow::Result<Vec<ParamEqFilter>> {
+        let mut filters = self.filters.clone();
+        if let Some(path) = &self.filename {
+            let apo = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?
+                .parse::<apo::Config>()?;
+            filters.push(ParamEqFilter {
+                ty: FilterType::HighShelf,
+                control: Control {
+                    freq: 0.0,
+                    q: 0.0,
+                    gain: apo.preamp,
+                    ..Default::default()
+                },
+            });
+            filters.extend(
+                apo.filters
+                    .iter()
+                    .filter(|filter| filter.enabled)
+                    .map(|filter| ParamEqFilter {
+                        ty: filter.filter_type,
+                        control: Control {
+                            freq: filter.frequency,
+                            q: filter.q,
+                            gain: filter.gain,
+                            ..Default::default()
+                        },
+                    }),
+            );
+        }
+        Ok(filters)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ParamEqFilter {
+    #[serde(rename = "type")]
+    pub ty: FilterType,
+    #[serde(flatten)]
+    pub control: Control,
+}
+
+/// Sample rates `bq_raw` coefficients are generated for by default
+pub const COMMON_SAMPLE_RATES: [u32; 6] = [44100, 48000, 88200, 96000, 176400, 192000];
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawNodeConfig {
+    pub coefficients: Vec<RateAndBiquadCoefficients>,
+}
+
+impl RawNodeConfig {
+    /// One coefficient set per rate, computed by `coefficients(rate)`
+    pub fn for_rates(
+        rates: impl IntoIterator<Item = u32>,
+        coefficients: impl Fn(f64) -> BiquadCoefficients,
+    ) -> Self {
+        Self {
+            coefficients: rates
+                .into_iter()
+                .map(|rate| RateAndBiquadCoefficients {
+                    rate,
+                    coefficients: coefficients(rate as f64),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Sample rate mapped to biquad coefficients
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RateAndBiquadCoefficients {
+    pub rate: u32,
+    #[serde(flatten)]
+    pub coefficients: BiquadCoefficients,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+/// Normalized biquad coefficients, with a0 = 1.0
+pub struct BiquadCoefficients {
+    pub b0: f64,
     pub b1: f64,
     pub b2: f64,
     pub a1: f64,
     pub a2: f64,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum NodeType {
     #[serde(rename = "builtin")]
     Builtin,
+    #[serde(rename = "ladspa")]
+    Ladspa,
+    #[serde(rename = "lv2")]
+    Lv2,
+}
+
+/// A LADSPA or LV2 plugin appended after the EQ, e.g.
+/// `{ type = ladspa plugin = fast_lookahead_limiter_1913 label = fastLookaheadLimiter control = { ... } }`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PluginStage {
+    #[serde(rename = "type")]
+    pub node_type: NodeType,
+    pub plugin: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "spa_json::Map::is_empty")]
+    pub control: spa_json::Map<String, spa_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<spa_json::Value>,
+    /// Port to link the previous stage into, by name or index. Defaults to the first port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    /// Port to link to the next stage, by name or index. Defaults to the first port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+impl PluginStage {
+    pub fn to_node(&self, name: String) -> Node {
+        Node {
+            node_type: self.node_type,
+            name,
+            plugin: Some(self.plugin.clone()),
+            kind: NodeKind::Other(OtherNode {
+                label: self.label.clone(),
+                control: (!self.control.is_empty())
+                    .then(|| spa_json::Value::Object(self.control.clone())),
+                config: self.config.clone(),
+                extra: Default::default(),
+            }),
+        }
+    }
+
+    fn input_port(&self, node: &str) -> String {
+        format!("{node}:{}", self.input.as_deref().unwrap_or("0"))
+    }
+
+    fn output_port(&self, node: &str) -> String {
+        format!("{node}:{}", self.output.as_deref().unwrap_or("0"))
+    }
 }
 
 #[derive(
@@ -304,14 +1369,122 @@ pub enum FilterType {
     HighShelf,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// Biquad controls. Any left out take PipeWire's builtin default of 0.
+#[derive(Debug, Clone, Default)]
 pub struct Control {
     pub freq: f64,
     pub q: f64,
     pub gain: f64,
+    /// The keys the controls are written under, spelled as in the config they were read from
+    pub keys: ControlKeys,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// The key each control was read from, or `None` if it was left out. Generated controls use
+/// the lowercase keys; PipeWire's own examples use the port names (`Freq`, `Q`, `Gain`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlKeys {
+    pub freq: Option<&'static str>,
+    pub q: Option<&'static str>,
+    pub gain: Option<&'static str>,
+}
+
+impl Default for ControlKeys {
+    fn default() -> Self {
+        Self {
+            freq: Some("freq"),
+            q: Some("q"),
+            gain: Some("gain"),
+        }
+    }
+}
+
+impl Control {
+    const KEYS: [&str; 6] = ["freq", "Freq", "q", "Q", "gain", "Gain"];
+
+    /// The controls of a biquad without a `control` block
+    fn unset() -> Self {
+        Self {
+            keys: ControlKeys {
+                freq: None,
+                q: None,
+                gain: None,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// The controls that were read, and those changed from PipeWire's default since
+    fn entries(&self) -> impl Iterator<Item = (&'static str, f64)> {
+        [
+            (self.keys.freq, "freq", self.freq),
+            (self.keys.q, "q", self.q),
+            (self.keys.gain, "gain", self.gain),
+        ]
+        .into_iter()
+        .filter_map(|(key, default_key, value)| match key {
+            Some(key) => Some((key, value)),
+            None => (value != 0.0).then_some((default_key, value)),
+        })
+    }
+
+    fn is_unset(&self) -> bool {
+        self.entries().next().is_none()
+    }
+}
+
+impl serde::Serialize for Control {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap as _;
+
+        let mut map = serializer.serialize_map(None)?;
+        for (key, value) in self.entries() {
+            map.serialize_entry(key, &value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Control {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Control;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a map of biquad controls")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Control, A::Error> {
+                use serde::de::Error as _;
+
+                let mut control = Control::unset();
+                while let Some(key) = map.next_key::<String>()? {
+                    let Some(&key) = Control::KEYS.iter().find(|&&known| known == key) else {
+                        return Err(A::Error::unknown_field(&key, &["Freq", "Q", "Gain"]));
+                    };
+                    let (value, read) = match key {
+                        "freq" | "Freq" => (&mut control.freq, &mut control.keys.freq),
+                        "q" | "Q" => (&mut control.q, &mut control.keys.q),
+                        _ => (&mut control.gain, &mut control.keys.gain),
+                    };
+                    if read.replace(key).is_some() {
+                        return Err(A::Error::duplicate_field(key));
+                    }
+                    *value = map.next_value()?;
+                }
+                Ok(control)
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Link {
     pub output: String,
     pub input: String,
@@ -342,7 +1515,15 @@ impl fmt::Display for SpaJson<'_> {
             serde_json::Value::Object(map) => {
                 writeln!(f, "{{")?;
                 for (key, value) in map {
-                    write!(f, "{:indent$}{} = ", "", key, indent = self.indent + 4)?;
+                    write!(f, "{:indent$}", "", indent = self.indent + 4)?;
+                    // Keys such as `"Gain 1"` need quoting to be read back
+                    if !key.is_empty()
+                        && !key.contains(|c: char| c.is_whitespace() || "\"#:,=[]{}".contains(c))
+                    {
+                        write!(f, "{key} = ")?;
+                    } else {
+                        write!(f, "{} = ", serde_json::Value::from(key.as_str()))?;
+                    }
                     write!(f, "{}", SpaJson::new(value).with_indent(self.indent + 4))?;
                     writeln!(f)?;
                 }
@@ -363,7 +1544,7 @@ impl fmt::Display for SpaJson<'_> {
                 }
                 Ok(())
             }
-            serde_json::Value::String(s) => write!(f, "\"{}\"", s),
+            serde_json::Value::String(_) => write!(f, "{}", self.value),
             serde_json::Value::Number(n) => write!(f, "{}", n),
             serde_json::Value::Bool(b) => write!(f, "{}", b),
             serde_json::Value::Null => write!(f, "null"),
@@ -376,20 +1557,24 @@ mod tests {
     use crate::{
         apo::{self},
         module::{
-            BiquadCoefficients, Control, FilterType, NodeKind, ParamEqConfig, ParamEqFilter,
-            RateAndBiquadCoefficients, RawNodeConfig,
+            AudioPosition, BiquadCoefficients, COMMON_SAMPLE_RATES, ChannelChain, ChannelLayout,
+            Control, Direction, ExtraProps, FilterGraph, FilterType, Module, ModuleArgs, Node,
+            NodeKind, NodeType, ParamEqConfig, ParamEqFilter, PlaybackProps, PluginStage,
+            RateAndBiquadCoefficients, RawNodeConfig, filter_node_name, parse_filter_node_name,
         },
         to_spa_json,
     };
     use expect_test::expect;
 
     use super::Config;
+    use std::path::Path;
 
     #[test]
     fn test_generate_config_from_raw() {
         let out = to_spa_json(&Config::from_kinds(
             "test-eq",
             0.0,
+            &ChannelLayout::stereo(),
             [NodeKind::Raw {
                 config: RawNodeConfig {
                     coefficients: vec![RateAndBiquadCoefficients {
@@ -452,7 +1637,7 @@ mod tests {
                                 ]
                             }
                             audio.channels = 2
-                            audio_position = [
+                            audio.position = [
                                 "FL"
                                 "FR"
                             ]
@@ -477,8 +1662,11 @@ mod tests {
         let out = to_spa_json(&Config::from_kinds(
             "param-eq",
             -4.2,
+            &ChannelLayout::stereo(),
             [NodeKind::ParamEq {
                 config: ParamEqConfig {
+                    filename: None,
+                    extra: Default::default(),
                     filters: vec![
                         ParamEqFilter {
                             ty: FilterType::LowShelf,
@@ -486,6 +1674,7 @@ mod tests {
                                 freq: 200.0,
                                 q: 0.707,
                                 gain: -6.0,
+                                ..Default::default()
                             },
                         },
                         ParamEqFilter {
@@ -494,6 +1683,7 @@ mod tests {
                                 freq: 1000.0,
                                 q: 1.0,
                                 gain: 3.0,
+                                ..Default::default()
                             },
                         },
                     ],
@@ -544,7 +1734,7 @@ mod tests {
                                 ]
                             }
                             audio.channels = 2
-                            audio_position = [
+                            audio.position = [
                                 "FL"
                                 "FR"
                             ]
@@ -588,7 +1778,11 @@ mod tests {
             ],
         };
 
-        let out = to_spa_json(&Config::from_apo("test-eq", &config));
+        let out = to_spa_json(&Config::from_apo(
+            "test-eq",
+            &ChannelLayout::stereo(),
+            &config,
+        ));
 
         expect![[r#"
             {
@@ -643,7 +1837,7 @@ mod tests {
                                 ]
                             }
                             audio.channels = 2
-                            audio_position = [
+                            audio.position = [
                                 "FL"
                                 "FR"
                             ]
@@ -662,4 +1856,907 @@ mod tests {
             }"#]]
         .assert_eq(&out);
     }
+
+    #[test]
+    fn test_parse_channel_layout() {
+        assert_eq!(
+            "5.1".parse::<ChannelLayout>().unwrap(),
+            ChannelLayout::surround_5_1()
+        );
+        assert_eq!(
+            "Stereo".parse::<ChannelLayout>().unwrap(),
+            ChannelLayout::stereo()
+        );
+        assert_eq!(
+            "aux3".parse::<ChannelLayout>().unwrap().positions(),
+            [
+                AudioPosition::Aux(0),
+                AudioPosition::Aux(1),
+                AudioPosition::Aux(2)
+            ]
+        );
+        assert_eq!(
+            "FL, FR, AUX7".parse::<ChannelLayout>().unwrap().positions(),
+            [
+                AudioPosition::FrontLeft,
+                AudioPosition::FrontRight,
+                AudioPosition::Aux(7)
+            ]
+        );
+        assert_eq!(
+            "FL,FR,LFE".parse::<ChannelLayout>().unwrap().to_string(),
+            "2.1"
+        );
+        assert_eq!(
+            "fl,aux2".parse::<ChannelLayout>().unwrap().to_string(),
+            "FL,AUX2"
+        );
+        assert!("FL,FL".parse::<ChannelLayout>().is_err());
+        assert!("FL,XX".parse::<ChannelLayout>().is_err());
+    }
+
+    #[test]
+    fn test_generate_config_with_surround_layout() {
+        let out = to_spa_json(&Config::from_kinds(
+            "living-room",
+            0.0,
+            &ChannelLayout::surround_5_1(),
+            [NodeKind::Peaking {
+                control: Control {
+                    freq: 80.0,
+                    q: 1.0,
+                    gain: -3.0,
+                    ..Default::default()
+                },
+            }],
+        ));
+
+        expect![[r#"
+            {
+                context.modules = [
+                    {
+                        name = "libpipewire-module-filter-chain"
+                        args = {
+                            node.description = "living-room equalizer"
+                            media.name = "living-room"
+                            filter.graph = {
+                                nodes = [
+                                    {
+                                        type = "builtin"
+                                        name = "pweq.filter_preamp"
+                                        label = "bq_highshelf"
+                                        control = {
+                                            freq = 0.0
+                                            q = 0.0
+                                            gain = 0.0
+                                        }
+                                    }
+                                    {
+                                        type = "builtin"
+                                        name = "pweq.filter_1"
+                                        label = "bq_peaking"
+                                        control = {
+                                            freq = 80.0
+                                            q = 1.0
+                                            gain = -3.0
+                                        }
+                                    }
+                                ]
+                                links = [
+                                    {
+                                        output = "pweq.filter_preamp:Out"
+                                        input = "pweq.filter_1:In"
+                                    }
+                                ]
+                            }
+                            audio.channels = 6
+                            audio.position = [
+                                "FL"
+                                "FR"
+                                "FC"
+                                "LFE"
+                                "SL"
+                                "SR"
+                            ]
+                            playback.props = {
+                                node.name = "effect_input.pweq.living-room"
+                                node.passive = false
+                            }
+                            capture.props = {
+                                node.name = "effect_output.pweq.living-room"
+                                media.class = "Audio/Sink"
+                                pweq.managed = true
+                            }
+                        }
+                    }
+                ]
+            }"#]]
+        .assert_eq(&out);
+    }
+
+    #[test]
+    fn test_generate_config_with_channel_chains() {
+        let peaking = |gain| NodeKind::Peaking {
+            control: Control {
+                freq: 1000.0,
+                q: 1.0,
+                gain,
+                ..Default::default()
+            },
+        };
+
+        let config = Config::from_channel_chains(
+            "matched",
+            &ChannelLayout::stereo(),
+            [
+                ChannelChain {
+                    position: AudioPosition::FrontRight,
+                    preamp: -2.0,
+                    kinds: vec![peaking(2.0)],
+                },
+                ChannelChain {
+                    position: AudioPosition::FrontLeft,
+                    preamp: -1.0,
+                    kinds: vec![peaking(1.0)],
+                },
+            ],
+        )
+        .unwrap();
+        let out = to_spa_json(&config);
+
+        expect![[r#"
+            {
+                context.modules = [
+                    {
+                        name = "libpipewire-module-filter-chain"
+                        args = {
+                            node.description = "matched equalizer"
+                            media.name = "matched"
+                            filter.graph = {
+                                nodes = [
+                                    {
+                                        type = "builtin"
+                                        name = "pweq.filter_FL_preamp"
+                                        label = "bq_highshelf"
+                                        control = {
+                                            freq = 0.0
+                                            q = 0.0
+                                            gain = -1.0
+                                        }
+                                    }
+                                    {
+                                        type = "builtin"
+                                        name = "pweq.filter_FL_1"
+                                        label = "bq_peaking"
+                                        control = {
+                                            freq = 1000.0
+                                            q = 1.0
+                                            gain = 1.0
+                                        }
+                                    }
+                                    {
+                                        type = "builtin"
+                                        name = "pweq.filter_FR_preamp"
+                                        label = "bq_highshelf"
+                                        control = {
+                                            freq = 0.0
+                                            q = 0.0
+                                            gain = -2.0
+                                        }
+                                    }
+                                    {
+                                        type = "builtin"
+                                        name = "pweq.filter_FR_1"
+                                        label = "bq_peaking"
+                                        control = {
+                                            freq = 1000.0
+                                            q = 1.0
+                                            gain = 2.0
+                                        }
+                                    }
+                                ]
+                                links = [
+                                    {
+                                        output = "pweq.filter_FL_preamp:Out"
+                                        input = "pweq.filter_FL_1:In"
+                                    }
+                                    {
+                                        output = "pweq.filter_FR_preamp:Out"
+                                        input = "pweq.filter_FR_1:In"
+                                    }
+                                ]
+                                inputs = [
+                                    "pweq.filter_FL_preamp:In"
+                                    "pweq.filter_FR_preamp:In"
+                                ]
+                                outputs = [
+                                    "pweq.filter_FL_1:Out"
+                                    "pweq.filter_FR_1:Out"
+                                ]
+                            }
+                            audio.channels = 2
+                            audio.position = [
+                                "FL"
+                                "FR"
+                            ]
+                            playback.props = {
+                                node.name = "effect_input.pweq.matched"
+                                node.passive = false
+                            }
+                            capture.props = {
+                                node.name = "effect_output.pweq.matched"
+                                media.class = "Audio/Sink"
+                                pweq.managed = true
+                            }
+                        }
+                    }
+                ]
+            }"#]]
+        .assert_eq(&out);
+
+        // One APO config can't hold a chain per channel
+        let err = config.filter_chains().next().unwrap().to_apo().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot convert a filter chain per channel (2 chains) to a single filter list"
+        );
+
+        assert!(
+            Config::from_channel_chains(
+                "missing",
+                &ChannelLayout::stereo(),
+                [ChannelChain {
+                    position: AudioPosition::FrontLeft,
+                    preamp: 0.0,
+                    kinds: vec![],
+                }],
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_filter_node_names() {
+        assert_eq!(filter_node_name(None, 3), "pweq.filter_3");
+        assert_eq!(
+            filter_node_name(Some(AudioPosition::FrontLeft), "preamp"),
+            "pweq.filter_FL_preamp"
+        );
+        assert_eq!(parse_filter_node_name("pweq.filter_3"), Some((None, "3")));
+        assert_eq!(
+            parse_filter_node_name("pweq.filter_AUX2_12"),
+            Some((Some(AudioPosition::Aux(2)), "12"))
+        );
+        assert_eq!(parse_filter_node_name("pweq.filter_XX_1"), None);
+        assert_eq!(parse_filter_node_name("other_1"), None);
+    }
+
+    #[test]
+    fn test_generate_source_config() {
+        let module = Module::from_kinds("mic", 0.0, &ChannelLayout::mono(), [])
+            .with_direction(Direction::Source);
+
+        expect![[r#"
+            {
+                node.name = "effect_input.pweq.source.mic"
+                node.passive = true
+            }"#]]
+        .assert_eq(&to_spa_json(&module.args.capture_props));
+        expect![[r#"
+            {
+                node.name = "effect_output.pweq.source.mic"
+                media.class = "Audio/Source"
+                pweq.managed = true
+            }"#]]
+        .assert_eq(&to_spa_json(&module.args.playback_props));
+    }
+
+    #[test]
+    fn test_target_binding() {
+        let module = Module::from_kinds("dac", 0.0, &ChannelLayout::stereo(), [])
+            .with_target(Some("alsa_output.usb-dac".to_string()));
+        assert_eq!(module.args.target(), Some("alsa_output.usb-dac"));
+        expect![[r#"
+            {
+                node.name = "effect_input.pweq.dac"
+                node.passive = false
+                target.object = "alsa_output.usb-dac"
+            }"#]]
+        .assert_eq(&to_spa_json(&module.args.playback_props));
+
+        // The target follows the device side of the EQ
+        let module = module.with_direction(Direction::Source);
+        assert_eq!(module.args.playback_props.target_object, None);
+        assert_eq!(
+            module.args.capture_props.target_object.as_deref(),
+            Some("alsa_output.usb-dac")
+        );
+
+        let props: PlaybackProps = spa_json::from_str(r#"{ node.target = "legacy" }"#).unwrap();
+        assert_eq!(props.target_object.as_deref(), Some("legacy"));
+    }
+
+    #[test]
+    fn test_extra_props() {
+        let defaults: ExtraProps = spa_json::from_str(
+            r#"{
+                args = { audio.rate = 48000 }
+                capture.props = { node.latency = 256/48000 priority.session = 2000 }
+            }"#,
+        )
+        .unwrap();
+        let props = defaults.merge(spa_json::from_str(
+            r#"{ capture.props = { priority.session = 500 target.object = "alsa_output.usb-dac" } }"#,
+        )
+        .unwrap());
+
+        let module = Module::from_kinds("dac", 0.0, &ChannelLayout::stereo(), [])
+            .with_props(&props)
+            .unwrap();
+        // Modelled keys are set through their typed fields
+        assert_eq!(
+            module.args.capture_props.target_object.as_deref(),
+            Some("alsa_output.usb-dac")
+        );
+        expect![[r#"
+            {
+                node.name = "effect_output.pweq.dac"
+                target.object = "alsa_output.usb-dac"
+                media.class = "Audio/Sink"
+                pweq.managed = true
+                node.latency = "256/48000"
+                priority.session = 500
+            }"#]]
+        .assert_eq(&to_spa_json(&module.args.capture_props));
+        assert_eq!(to_spa_json(&module.args.extra["audio.rate"]), "48000");
+
+        // Extra props survive a parse
+        let written = to_spa_json(&module.args);
+        let reparsed: ModuleArgs = spa_json::from_str(&written).unwrap();
+        assert_eq!(to_spa_json(&reparsed), written);
+
+        let err = Module::from_kinds("dac", 0.0, &ChannelLayout::stereo(), [])
+            .with_props(&spa_json::from_str(r#"{ args = { audio.channels = "two" } }"#).unwrap())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid module args");
+    }
+
+    #[test]
+    fn test_append_plugins() {
+        let plugins: Vec<PluginStage> = spa_json::from_str(
+            r#"[
+                {
+                    type = ladspa
+                    plugin = fast_lookahead_limiter_1913
+                    label = fastLookaheadLimiter
+                    control = { "Limit (dB)" = -1.0 }
+                    input = "Input 1"
+                    output = "Output 1"
+                }
+                { type = lv2 plugin = "http://lsp-plug.in/plugins/lv2/limiter_mono" }
+            ]"#,
+        )
+        .unwrap();
+
+        let peaking = NodeKind::Peaking {
+            control: Control {
+                freq: 1000.0,
+                q: 1.0,
+                gain: 1.0,
+                ..Default::default()
+            },
+        };
+
+        let mut module = Module::from_kinds("test", 0.0, &ChannelLayout::mono(), [peaking.clone()]);
+        module.append_plugins(&plugins);
+        let graph = &module.args.filter_graph;
+        expect![[r#"
+            [
+                {
+                    output = "pweq.filter_preamp:Out"
+                    input = "pweq.filter_1:In"
+                }
+                {
+                    output = "pweq.filter_1:Out"
+                    input = "pweq.plugin_1:Input 1"
+                }
+                {
+                    output = "pweq.plugin_1:Output 1"
+                    input = "pweq.plugin_2:0"
+                }
+            ]"#]]
+        .assert_eq(&to_spa_json(&graph.links));
+
+        // Plugin nodes round-trip
+        let written = to_spa_json(&graph.nodes[2..].to_vec());
+        expect![[r#"
+            [
+                {
+                    type = "ladspa"
+                    name = "pweq.plugin_1"
+                    plugin = "fast_lookahead_limiter_1913"
+                    label = "fastLookaheadLimiter"
+                    control = {
+                        "Limit (dB)" = -1.0
+                    }
+                }
+                {
+                    type = "lv2"
+                    name = "pweq.plugin_2"
+                    plugin = "http://lsp-plug.in/plugins/lv2/limiter_mono"
+                }
+            ]"#]]
+        .assert_eq(&written);
+        let reparsed: Vec<Node> = spa_json::from_str(&written).unwrap();
+        assert_eq!(reparsed[1].node_type, NodeType::Lv2);
+        assert_eq!(to_spa_json(&reparsed), written);
+
+        let mut module = Module::from_channel_chains(
+            "test",
+            &ChannelLayout::stereo(),
+            [AudioPosition::FrontLeft, AudioPosition::FrontRight].map(|position| ChannelChain {
+                position,
+                preamp: 0.0,
+                kinds: vec![peaking.clone()],
+            }),
+        )
+        .unwrap();
+        module.append_plugins(&plugins);
+        let graph = &module.args.filter_graph;
+        assert_eq!(graph.nodes.len(), 8);
+        expect![[r#"
+            [
+                "pweq.plugin_FL_2:0"
+                "pweq.plugin_FR_2:0"
+            ]"#]]
+        .assert_eq(&to_spa_json(&graph.outputs));
+    }
+
+    const EXTERNAL_CONFIG: &str = r#"
+        context.properties = { log.level = 0 }
+        context.modules = [
+            { name = libpipewire-module-rt args = { nice.level = -11 } flags = [ ifexists nofail ] }
+            {   name = libpipewire-module-filter-chain
+                flags = [ nofail ]
+                args = {
+                    node.description = "Equalizer Sink"
+                    media.name       = "Equalizer Sink"
+                    filter.graph = {
+                        nodes = [
+                            {
+                                type  = builtin
+                                name  = eq_band_1
+                                label = bq_lowshelf
+                                control = { "Freq" = 100.0 "Q" = 1.0 "Gain" = 2.0 }
+                            }
+                            {
+                                type  = builtin
+                                name  = eq_band_3
+                                label = param_eq
+                                config = {
+                                    filters = [
+                                        { type = bq_highshelf freq = 0 q = 1.0 gain = -3.0 }
+                                        { type = bq_peaking freq = 3000 q = 2.0 gain = 1.5 }
+                                    ]
+                                }
+                            }
+                            {
+                                type  = builtin
+                                name  = eq_band_2
+                                label = bq_notch
+                                control = { "Freq" = 50.0 "Q" = 10.0 "Gain" = 0.0 }
+                            }
+                        ]
+                        links = [
+                            { output = "eq_band_1:Out" input = "eq_band_2:In" }
+                            { output = "eq_band_2:Out" input = "eq_band_3:In" }
+                        ]
+                    }
+                    audio.channels = 2
+                    audio.position = [ FL FR ]
+                    capture.props = {
+                        node.name   = "effect_input.eq6"
+                        media.class = Audio/Sink
+                    }
+                    playback.props = {
+                        node.name   = "effect_output.eq6"
+                        node.passive = true
+                        stream.dont-remix = true
+                    }
+                }
+            }
+        ]
+    "#;
+
+    #[test]
+    fn test_parse_external_config() {
+        let config: Config = spa_json::from_str(EXTERNAL_CONFIG).unwrap();
+        assert_eq!(config.context_modules.len(), 2);
+
+        // Unmodelled sections and modules round-trip
+        expect![[r#"
+            {
+                context.modules = [
+                    {
+                        name = "libpipewire-module-rt"
+                        args = {
+                            nice.level = -11
+                        }
+                        flags = [
+                            "ifexists"
+                            "nofail"
+                        ]
+                    }
+                    {
+                        name = "libpipewire-module-filter-chain"
+                        args = {
+                            node.description = "Equalizer Sink"
+                            media.name = "Equalizer Sink"
+                            filter.graph = {
+                                nodes = [
+                                    {
+                                        type = "builtin"
+                                        name = "eq_band_1"
+                                        label = "bq_lowshelf"
+                                        control = {
+                                            Freq = 100.0
+                                            Q = 1.0
+                                            Gain = 2.0
+                                        }
+                                    }
+                                    {
+                                        type = "builtin"
+                                        name = "eq_band_3"
+                                        label = "param_eq"
+                                        config = {
+                                            filters = [
+                                                {
+                                                    type = "bq_highshelf"
+                                                    freq = 0.0
+                                                    q = 1.0
+                                                    gain = -3.0
+                                                }
+                                                {
+                                                    type = "bq_peaking"
+                                                    freq = 3000.0
+                                                    q = 2.0
+                                                    gain = 1.5
+                                                }
+                                            ]
+                                        }
+                                    }
+                                    {
+                                        type = "builtin"
+                                        name = "eq_band_2"
+                                        label = "bq_notch"
+                                        control = {
+                                            Freq = 50.0
+                                            Q = 10.0
+                                            Gain = 0.0
+                                        }
+                                    }
+                                ]
+                                links = [
+                                    {
+                                        output = "eq_band_1:Out"
+                                        input = "eq_band_2:In"
+                                    }
+                                    {
+                                        output = "eq_band_2:Out"
+                                        input = "eq_band_3:In"
+                                    }
+                                ]
+                            }
+                            audio.channels = 2
+                            audio.position = [
+                                "FL"
+                                "FR"
+                            ]
+                            playback.props = {
+                                node.name = "effect_output.eq6"
+                                node.passive = true
+                                stream.dont-remix = true
+                            }
+                            capture.props = {
+                                node.name = "effect_input.eq6"
+                                media.class = "Audio/Sink"
+                            }
+                        }
+                        flags = [
+                            "nofail"
+                        ]
+                    }
+                ]
+                context.properties = {
+                    log.level = 0
+                }
+            }"#]]
+        .assert_eq(&to_spa_json(&config));
+
+        let module = config.filter_chains().next().unwrap();
+        expect![[r#"
+            Preamp: -3.0 dB
+            Filter 1: ON LSC Fc 100.0 Hz Gain 2.0 dB Q 1.000000
+            Filter 2: ON NO Fc 50.0 Hz Gain 0.0 dB Q 10.000000
+            Filter 3: ON PK Fc 3000.0 Hz Gain 1.5 dB Q 2.000000
+        "#]]
+        .assert_eq(&module.to_apo().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_parse_unknown_nodes() {
+        let graph: FilterGraph = spa_json::from_str(
+            r#"{
+                nodes = [
+                    { type = builtin name = mix label = mixer control = { "Gain 1" = 0.5 "Gain 2" = 0.5 } }
+                    { type = builtin name = eq label = bq_peaking control = { Freq = 1000 Q = 1 Gain = 3 } }
+                    { type = builtin name = delay label = delay config = { max-delay = 1.0 } control = { "Delay (s)" = 0.01 } }
+                ]
+                links = [
+                    { output = "mix:Out" input = "eq:In" }
+                    { output = "eq:Out" input = "delay:In" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert!(
+            matches!(&graph.nodes[0].kind, NodeKind::Other(node) if node.label.as_deref() == Some("mixer"))
+        );
+        assert!(matches!(graph.nodes[1].kind, NodeKind::Peaking { .. }));
+        assert!(
+            matches!(&graph.nodes[2].kind, NodeKind::Other(node) if node.label.as_deref() == Some("delay"))
+        );
+
+        expect![[r#"
+            {
+                nodes = [
+                    {
+                        type = "builtin"
+                        name = "mix"
+                        label = "mixer"
+                        control = {
+                            "Gain 1" = 0.5
+                            "Gain 2" = 0.5
+                        }
+                    }
+                    {
+                        type = "builtin"
+                        name = "eq"
+                        label = "bq_peaking"
+                        control = {
+                            Freq = 1000.0
+                            Q = 1.0
+                            Gain = 3.0
+                        }
+                    }
+                    {
+                        type = "builtin"
+                        name = "delay"
+                        label = "delay"
+                        control = {
+                            "Delay (s)" = 0.01
+                        }
+                        config = {
+                            max-delay = 1.0
+                        }
+                    }
+                ]
+                links = [
+                    {
+                        output = "mix:Out"
+                        input = "eq:In"
+                    }
+                    {
+                        output = "eq:Out"
+                        input = "delay:In"
+                    }
+                ]
+            }"#]]
+        .assert_eq(&to_spa_json(&graph));
+
+        let written = to_spa_json(&graph);
+        let reparsed: FilterGraph = spa_json::from_str(&written).unwrap();
+        assert_eq!(to_spa_json(&reparsed), written);
+
+        // Builtins pw-eq models must have the settings it expects
+        let err = spa_json::from_str::<FilterGraph>(
+            "{ nodes = [ { type = builtin name = eq label = bq_peaking control = { Freq = high } } ] }",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("malformed `control` for `bq_peaking` node"),
+            "{err}"
+        );
+        let err = spa_json::from_str::<FilterGraph>(
+            "{ nodes = [ { type = builtin name = eq label = bq_peaking control = { Frequency = 100 } } ] }",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("malformed `control` for `bq_peaking` node"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_parse_partial_controls() {
+        // PipeWire fills in the controls a builtin biquad leaves out
+        let graph: FilterGraph = spa_json::from_str(
+            r#"{
+                nodes = [
+                    { type = builtin label = bq_lowpass name = lp control = { "Freq" = 150.0 } }
+                    { type = builtin label = bq_highpass name = hp }
+                ]
+                links = [ { output = "lp:Out" input = "hp:In" } ]
+            }"#,
+        )
+        .unwrap();
+
+        let NodeKind::LowPass { control } = &graph.nodes[0].kind else {
+            panic!("expected a lowpass, got {:?}", graph.nodes[0].kind);
+        };
+        assert_eq!((control.freq, control.q, control.gain), (150.0, 0.0, 0.0));
+        let NodeKind::HighPass { control } = &graph.nodes[1].kind else {
+            panic!("expected a highpass, got {:?}", graph.nodes[1].kind);
+        };
+        assert_eq!((control.freq, control.q, control.gain), (0.0, 0.0, 0.0));
+
+        // Written back as they were read, with the keys spelled the same
+        expect![[r#"
+            [
+                {
+                    type = "builtin"
+                    name = "lp"
+                    label = "bq_lowpass"
+                    control = {
+                        Freq = 150.0
+                    }
+                }
+                {
+                    type = "builtin"
+                    name = "hp"
+                    label = "bq_highpass"
+                }
+            ]"#]]
+        .assert_eq(&to_spa_json(&graph.nodes));
+
+        // Controls changed from PipeWire's default are written even if they were left out
+        let mut graph = graph;
+        if let NodeKind::HighPass { control } = &mut graph.nodes[1].kind {
+            control.freq = 80.0;
+        }
+        expect![[r#"
+            {
+                type = "builtin"
+                name = "hp"
+                label = "bq_highpass"
+                control = {
+                    freq = 80.0
+                }
+            }"#]]
+        .assert_eq(&to_spa_json(&graph.nodes[1]));
+    }
+
+    #[test]
+    fn test_link_apo_file() {
+        let path = Path::new("/home/user/autoeq/headphones.apo");
+        let mut module = Module::from_apo_file("linked", &ChannelLayout::stereo(), path).unwrap();
+        module.args.validate().unwrap();
+        expect![[r#"
+            {
+                nodes = [
+                    {
+                        type = "builtin"
+                        name = "pweq.filter_1"
+                        label = "param_eq"
+                        config = {
+                            filename = "/home/user/autoeq/headphones.apo"
+                        }
+                    }
+                ]
+                inputs = [
+                    "pweq.filter_1:In 1"
+                    "pweq.filter_1:In 2"
+                ]
+                outputs = [
+                    "pweq.filter_1:Out 1"
+                    "pweq.filter_1:Out 2"
+                ]
+            }"#]]
+        .assert_eq(&to_spa_json(&module.args.filter_graph));
+
+        // Filters are read from the file when converting back
+        let path = std::env::temp_dir().join(format!(
+            "pweq-{}-test_link_apo_file.apo",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "Preamp: -6.0 dB\nFilter 1: ON PK Fc 1000 Hz Gain 3.0 dB Q 1.41\n",
+        )
+        .unwrap();
+        let apo = Module::from_apo_file("linked", &ChannelLayout::stereo(), &path)
+            .unwrap()
+            .to_apo()
+            .unwrap();
+        assert_eq!(apo.preamp, -6.0);
+        assert_eq!(apo.filters.len(), 1);
+
+        // Plugins are appended per channel after the param_eq ports
+        let plugins: Vec<PluginStage> =
+            spa_json::from_str("[ { type = ladspa plugin = limiter label = limit } ]").unwrap();
+        module.append_plugins(&plugins);
+        module.args.validate().unwrap();
+        expect![[r#"
+            [
+                "pweq.plugin_FL_1:0"
+                "pweq.plugin_FR_1:0"
+            ]"#]]
+        .assert_eq(&to_spa_json(&module.args.filter_graph.outputs));
+
+        let err = Module::from_apo_file("linked", &"aux9".parse().unwrap(), &path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "param_eq supports at most 8 channels, got 9"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_raw_coefficients_for_rates() {
+        let config = RawNodeConfig::for_rates(COMMON_SAMPLE_RATES, |rate| BiquadCoefficients {
+            b0: rate,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+        });
+        let rates = config
+            .coefficients
+            .iter()
+            .map(|c| c.rate)
+            .collect::<Vec<_>>();
+        assert_eq!(rates, COMMON_SAMPLE_RATES);
+        // Each set is computed at its own rate
+        assert!(
+            config
+                .coefficients
+                .iter()
+                .all(|c| c.coefficients.b0 == f64::from(c.rate))
+        );
+
+        expect![[r#"
+            {
+                coefficients = [
+                    {
+                        rate = 44100
+                        b0 = 1.0
+                        b1 = 0.0
+                        b2 = 0.0
+                        a1 = 0.0
+                        a2 = 0.0
+                    }
+                    {
+                        rate = 48000
+                        b0 = 1.0
+                        b1 = 0.0
+                        b2 = 0.0
+                        a1 = 0.0
+                        a2 = 0.0
+                    }
+                ]
+            }"#]]
+        .assert_eq(&to_spa_json(&RawNodeConfig::for_rates(
+            [44100, 48000],
+            |_| BiquadCoefficients {
+                b0: 1.0,
+                b1: 0.0,
+                b2: 0.0,
+                a1: 0.0,
+                a2: 0.0,
+            },
+        )));
+    }
 }
@@ -0,0 +1,369 @@
+//! Semantic diff between filter-chain configs, telling live-applicable control changes apart
+//! from changes that need the module to be reloaded
+
+use std::{collections::BTreeMap, fmt};
+
+use spa_json::{Map, Value};
+
+use super::{Config, ContextModule, FilterGraph, Link, ModuleArgs};
+
+/// Changes from one config to another
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A node control changed, which can be applied to a running module
+    Param {
+        node: String,
+        param: String,
+        old: Option<Value>,
+        new: Option<Value>,
+    },
+    NodeAdded {
+        node: String,
+    },
+    NodeRemoved {
+        node: String,
+    },
+    /// The type, label, plugin or config of a node changed
+    NodeChanged {
+        node: String,
+    },
+    LinkAdded(Link),
+    LinkRemoved(Link),
+    /// A module arg, stream prop or graph port changed, keyed by its dotted path
+    Prop {
+        key: String,
+        old: Option<Value>,
+        new: Option<Value>,
+    },
+    ModuleAdded {
+        name: String,
+    },
+    ModuleRemoved {
+        name: String,
+    },
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Whether every change is a control change that `update_filters` can apply without
+    /// reloading the module
+    pub fn is_live(&self) -> bool {
+        self.changes
+            .iter()
+            .all(|change| matches!(change, Change::Param { .. }))
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Param {
+                node,
+                param,
+                old,
+                new,
+            } => write!(f, "~ {node} {param}: {} -> {}", show(old), show(new)),
+            Change::NodeAdded { node } => write!(f, "+ node {node}"),
+            Change::NodeRemoved { node } => write!(f, "- node {node}"),
+            Change::NodeChanged { node } => write!(f, "~ node {node}"),
+            Change::LinkAdded(link) => write!(f, "+ link {} -> {}", link.output, link.input),
+            Change::LinkRemoved(link) => write!(f, "- link {} -> {}", link.output, link.input),
+            Change::Prop { key, old, new } => match (old, new) {
+                (None, Some(new)) => write!(f, "+ {key} = {}", show_value(new)),
+                (Some(old), None) => write!(f, "- {key} = {}", show_value(old)),
+                _ => write!(f, "~ {key}: {} -> {}", show(old), show(new)),
+            },
+            Change::ModuleAdded { name } => write!(f, "+ module {name}"),
+            Change::ModuleRemoved { name } => write!(f, "- module {name}"),
+        }
+    }
+}
+
+fn show(value: &Option<Value>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "unset".to_string(), show_value)
+}
+
+fn show_value(value: &Value) -> String {
+    spa_json::to_string(value).unwrap_or_default()
+}
+
+impl Config {
+    /// Filter-chain modules are matched by `media.name`, anything else is compared as props
+    pub fn diff(&self, other: &Config) -> Diff {
+        let mut diff = Diff::default();
+
+        for module in self.filter_chains() {
+            match other
+                .filter_chains()
+                .find(|other| other.args.media_name == module.args.media_name)
+            {
+                Some(other) => diff.changes.extend(module.args.diff(&other.args).changes),
+                None => diff.changes.push(Change::ModuleRemoved {
+                    name: module.args.media_name.clone(),
+                }),
+            }
+        }
+        for module in other.filter_chains() {
+            if !self
+                .filter_chains()
+                .any(|this| this.args.media_name == module.args.media_name)
+            {
+                diff.changes.push(Change::ModuleAdded {
+                    name: module.args.media_name.clone(),
+                });
+            }
+        }
+
+        // Other modules have no natural key, so they are compared as a whole
+        let others = |config: &Config| {
+            let others = config
+                .context_modules
+                .iter()
+                .filter_map(|module| match module {
+                    ContextModule::Other(value) => Some(value.clone()),
+                    ContextModule::FilterChain(_) => None,
+                })
+                .collect::<Vec<_>>();
+            let mut props = config.extra.clone();
+            if !others.is_empty() {
+                props.insert("context.modules".to_string(), Value::Array(others));
+            }
+            props
+        };
+        diff_props("", &others(self), &others(other), &mut diff.changes);
+
+        diff
+    }
+}
+
+impl ModuleArgs {
+    pub fn diff(&self, other: &ModuleArgs) -> Diff {
+        let mut changes = vec![];
+
+        diff_props("", &args_props(self), &args_props(other), &mut changes);
+        diff_graphs(&self.filter_graph, &other.filter_graph, &mut changes);
+
+        Diff { changes }
+    }
+}
+
+/// The args as a map, without the filter graph
+fn args_props(args: &ModuleArgs) -> Map<String, Value> {
+    match spa_json::to_value(args) {
+        Ok(Value::Object(mut map)) => {
+            map.remove("filter.graph");
+            map
+        }
+        _ => unreachable!("module args serialize to a map"),
+    }
+}
+
+fn diff_graphs(old: &FilterGraph, new: &FilterGraph, changes: &mut Vec<Change>) {
+    let nodes = |graph: &FilterGraph| {
+        graph
+            .nodes
+            .iter()
+            .map(|node| match spa_json::to_value(node) {
+                Ok(Value::Object(map)) => (node.name.clone(), map),
+                _ => unreachable!("nodes serialize to a map"),
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    let (old_nodes, new_nodes) = (nodes(old), nodes(new));
+
+    for (name, old_node) in &old_nodes {
+        let Some(new_node) = new_nodes.get(name) else {
+            changes.push(Change::NodeRemoved { node: name.clone() });
+            continue;
+        };
+
+        let (mut old_node, mut new_node) = (old_node.clone(), new_node.clone());
+        let old_control = take_object(&mut old_node, "control");
+        let new_control = take_object(&mut new_node, "control");
+        if old_node != new_node {
+            changes.push(Change::NodeChanged { node: name.clone() });
+            continue;
+        }
+
+        for key in keys(&old_control, &new_control) {
+            let (old, new) = (old_control.get(key), new_control.get(key));
+            if old != new {
+                changes.push(Change::Param {
+                    node: name.clone(),
+                    param: key.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                });
+            }
+        }
+    }
+    for name in new_nodes.keys() {
+        if !old_nodes.contains_key(name) {
+            changes.push(Change::NodeAdded { node: name.clone() });
+        }
+    }
+
+    for link in &old.links {
+        if !new.links.contains(link) {
+            changes.push(Change::LinkRemoved(link.clone()));
+        }
+    }
+    for link in &new.links {
+        if !old.links.contains(link) {
+            changes.push(Change::LinkAdded(link.clone()));
+        }
+    }
+
+    // Graph ports and any other graph keys
+    let props = |graph: &FilterGraph| match spa_json::to_value(graph) {
+        Ok(Value::Object(mut map)) => {
+            map.remove("nodes");
+            map.remove("links");
+            map
+        }
+        _ => unreachable!("filter graphs serialize to a map"),
+    };
+    diff_props("filter.graph.", &props(old), &props(new), changes);
+}
+
+fn take_object(map: &mut Map<String, Value>, key: &str) -> Map<String, Value> {
+    match map.remove(key) {
+        Some(Value::Object(object)) => object,
+        _ => Map::new(),
+    }
+}
+
+fn keys<'a>(a: &'a Map<String, Value>, b: &'a Map<String, Value>) -> Vec<&'a String> {
+    let mut keys = a.keys().chain(b.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// Compare nested maps as flat dotted keys, e.g. `capture.props.node.latency`
+fn diff_props(
+    prefix: &str,
+    old: &Map<String, Value>,
+    new: &Map<String, Value>,
+    changes: &mut Vec<Change>,
+) {
+    for key in keys(old, new) {
+        match (old.get(key), new.get(key)) {
+            (Some(Value::Object(old)), Some(Value::Object(new))) => {
+                diff_props(&format!("{prefix}{key}."), old, new, changes)
+            }
+            (old, new) if old != new => changes.push(Change::Prop {
+                key: format!("{prefix}{key}"),
+                old: old.cloned(),
+                new: new.cloned(),
+            }),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    use crate::module::{ChannelLayout, Config, Control, Module, NodeKind};
+
+    fn peaking(gain: f64) -> NodeKind {
+        NodeKind::Peaking {
+            control: Control {
+                freq: 1000.0,
+                q: 1.0,
+                gain,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Config::from(Module::from_kinds(
+            "eq",
+            -3.0,
+            &ChannelLayout::stereo(),
+            [peaking(3.0)],
+        ));
+        assert!(old.diff(&old).is_empty());
+
+        let live = Config::from(Module::from_kinds(
+            "eq",
+            -4.0,
+            &ChannelLayout::stereo(),
+            [peaking(4.0)],
+        ));
+        let diff = old.diff(&live);
+        assert!(diff.is_live());
+        expect![[r#"
+            ~ pweq.filter_1 gain: 3.0 -> 4.0
+            ~ pweq.filter_preamp gain: -3.0 -> -4.0
+        "#]]
+        .assert_eq(&diff.to_string());
+
+        let structural: Config = spa_json::from_str(&crate::to_spa_json(&Config::from(
+            Module::from_kinds(
+                "eq",
+                -3.0,
+                &ChannelLayout::mono(),
+                [
+                    NodeKind::LowShelf {
+                        control: Control {
+                            freq: 1000.0,
+                            q: 1.0,
+                            gain: 3.0,
+                            ..Default::default()
+                        },
+                    },
+                    peaking(1.0),
+                ],
+            )
+            .with_target(Some("alsa_output.usb-dac".to_string())),
+        )))
+        .unwrap();
+        let diff = old.diff(&structural);
+        assert!(!diff.is_live());
+        expect![[r#"
+            ~ audio.channels: 2 -> 1
+            ~ audio.position: ["FL" "FR"] -> ["MONO"]
+            + playback.props.target.object = "alsa_output.usb-dac"
+            ~ node pweq.filter_1
+            + node pweq.filter_2
+            + link pweq.filter_1:Out -> pweq.filter_2:In
+        "#]]
+        .assert_eq(&diff.to_string());
+
+        let renamed = Config::from(Module::from_kinds(
+            "other",
+            0.0,
+            &ChannelLayout::stereo(),
+            [],
+        ));
+        expect![[r#"
+            - module eq
+            + module other
+        "#]]
+        .assert_eq(&old.diff(&renamed).to_string());
+    }
+}
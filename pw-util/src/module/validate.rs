@@ -0,0 +1,383 @@
+//! Graph checks for mistakes filter-chain would only report as a failed module load
+
+use std::collections::{HashMap, HashSet};
+
+use super::{FilterGraph, ModuleArgs, Node, NodeKind, NodeType};
+
+impl ModuleArgs {
+    /// Check the filter graph and channel counts, reporting every problem found
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = self.filter_graph.problems();
+
+        match self.audio_channels {
+            Some(channels)
+                if !self.audio_position.is_empty() && self.audio_position.len() != channels =>
+            {
+                problems.push(format!(
+                    "audio.channels is {channels} but audio.position has {} positions",
+                    self.audio_position.len()
+                ))
+            }
+            _ => {}
+        }
+
+        // Either stream may override the channel count of the module
+        let channels = self
+            .audio_channels
+            .or_else(|| (!self.audio_position.is_empty()).then_some(self.audio_position.len()));
+        let capture = stream_channels(&self.capture_props.extra).or(channels);
+        let playback = stream_channels(&self.playback_props.extra).or(channels);
+        problems.extend(self.filter_graph.channel_problems(capture, playback));
+
+        report(problems)
+    }
+}
+
+impl FilterGraph {
+    /// Check node names, links and graph ports
+    pub fn validate(&self) -> anyhow::Result<()> {
+        report(self.problems())
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        let mut nodes = HashMap::new();
+        for node in &self.nodes {
+            if nodes.contains_key(node.name.as_str()) {
+                problems.push(format!("duplicate node name `{}`", node.name));
+            } else {
+                nodes.insert(node.name.as_str(), node);
+            }
+        }
+
+        // Ports without a node name belong to the first node, except graph outputs which
+        // belong to the last
+        let first = self.nodes.first();
+        let last = self.nodes.last();
+
+        let mut linked_inputs = HashSet::new();
+        for link in &self.links {
+            let output = self.check_port(&nodes, &link.output, PortDirection::Output, first);
+            problems.extend(output.err().map(|err| format!("link output {err}")));
+            match self.check_port(&nodes, &link.input, PortDirection::Input, first) {
+                Ok(input) if !linked_inputs.insert(input) => problems.push(format!(
+                    "input port `{}` is linked more than once",
+                    link.input
+                )),
+                Ok(_) => {}
+                Err(err) => problems.push(format!("link input {err}")),
+            }
+        }
+
+        for input in &self.inputs {
+            match self.check_port(&nodes, input, PortDirection::Input, first) {
+                Ok(port) if linked_inputs.contains(&port) => {
+                    problems.push(format!("graph input `{input}` is also the input of a link"))
+                }
+                Ok(_) => {}
+                Err(err) => problems.push(format!("graph input {err}")),
+            }
+        }
+        for output in &self.outputs {
+            let output = self.check_port(&nodes, output, PortDirection::Output, last);
+            problems.extend(output.err().map(|err| format!("graph output {err}")));
+        }
+
+        problems.extend(
+            self.cycle()
+                .map(|cycle| format!("links form a cycle: {}", cycle.join(" -> "))),
+        );
+
+        problems
+    }
+
+    fn channel_problems(&self, capture: Option<usize>, playback: Option<usize>) -> Vec<String> {
+        let mut problems = vec![];
+
+        // filter-chain runs one copy of the graph per group of `inputs.len()` channels
+        let mut copies = |channels: Option<usize>, ports: &[String], stream: &str, kind: &str| {
+            let channels = channels?;
+            if ports.is_empty() {
+                return None;
+            }
+            if channels % ports.len() != 0 {
+                problems.push(format!(
+                    "{stream} has {channels} channels, which is not a multiple of the {} graph {kind}",
+                    ports.len()
+                ));
+                return None;
+            }
+            Some(channels / ports.len())
+        };
+
+        let capture_copies = copies(capture, &self.inputs, "capture", "inputs");
+        let playback_copies = copies(playback, &self.outputs, "playback", "outputs");
+        match (capture_copies, playback_copies) {
+            (Some(capture_copies), Some(playback_copies)) if capture_copies != playback_copies => {
+                problems.push(format!(
+                    "graph inputs need {capture_copies} copies of the graph but outputs need {playback_copies}"
+                ))
+            }
+            _ => {}
+        }
+
+        problems
+    }
+
+    /// Resolve a `node:port` reference, or a bare port of `default`, to its node and port name
+    fn check_port<'a>(
+        &'a self,
+        nodes: &HashMap<&'a str, &'a Node>,
+        port: &'a str,
+        direction: PortDirection,
+        default: Option<&'a Node>,
+    ) -> Result<(&'a str, &'a str), String> {
+        let (node, port_name) = match port.split_once(':') {
+            Some((name, port_name)) => {
+                let node = nodes
+                    .get(name)
+                    .ok_or_else(|| format!("`{port}` refers to unknown node `{name}`"))?;
+                (*node, port_name)
+            }
+            None => (
+                default.ok_or_else(|| format!("`{port}` refers to a node in an empty graph"))?,
+                port,
+            ),
+        };
+
+        let Some(ports) = ports(node, direction) else {
+            // Plugin ports can't be checked without loading the plugin
+            return Ok((node.name.as_str(), port_name));
+        };
+        // Ports may also be referred to by index
+        let known = ports.iter().any(|name| name == port_name)
+            || port_name
+                .parse::<usize>()
+                .is_ok_and(|idx| idx < ports.len());
+        if !known {
+            return Err(format!(
+                "`{port}` refers to unknown {direction} port `{port_name}` of `{}` (expected one of {})",
+                node.name,
+                ports.join(", ")
+            ));
+        }
+
+        Ok((node.name.as_str(), port_name))
+    }
+
+    /// A path of node names around a cycle in the links, if any
+    fn cycle(&self) -> Option<Vec<&str>> {
+        fn port_node(port: &str) -> Option<&str> {
+            port.split_once(':').map(|(node, _)| node)
+        }
+
+        // Bare ports refer to the first node, see `check_port`
+        let first = self.nodes.first().map(|node| node.name.as_str());
+        let mut edges = HashMap::<&str, Vec<&str>>::new();
+        for link in &self.links {
+            if let (Some(from), Some(to)) = (
+                port_node(&link.output).or(first),
+                port_node(&link.input).or(first),
+            ) {
+                edges.entry(from).or_default().push(to);
+            }
+        }
+
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Visiting,
+            Done,
+        }
+
+        fn visit<'a>(
+            node: &'a str,
+            edges: &HashMap<&'a str, Vec<&'a str>>,
+            states: &mut HashMap<&'a str, State>,
+            path: &mut Vec<&'a str>,
+        ) -> bool {
+            match states.get(node) {
+                Some(State::Done) => return false,
+                Some(State::Visiting) => {
+                    let start = path.iter().position(|&n| n == node).unwrap_or(0);
+                    path.drain(..start);
+                    path.push(node);
+                    return true;
+                }
+                None => {}
+            }
+
+            states.insert(node, State::Visiting);
+            path.push(node);
+            for &next in edges.get(node).into_iter().flatten() {
+                if visit(next, edges, states, path) {
+                    return true;
+                }
+            }
+            path.pop();
+            states.insert(node, State::Done);
+            false
+        }
+
+        let mut states = HashMap::new();
+        for node in &self.nodes {
+            let mut path = vec![];
+            if visit(&node.name, &edges, &mut states, &mut path) {
+                return Some(path);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PortDirection {
+    Input,
+    Output,
+}
+
+impl std::fmt::Display for PortDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PortDirection::Input => "input",
+            PortDirection::Output => "output",
+        })
+    }
+}
+
+/// Audio port names of a builtin node, or `None` if they aren't known (plugins, unmodelled labels)
+fn ports(node: &Node, direction: PortDirection) -> Option<Vec<String>> {
+    if node.node_type != NodeType::Builtin {
+        return None;
+    }
+
+    let numbered = |prefix: &str| (1..=8).map(|i| format!("{prefix} {i}")).collect();
+    let label = match &node.kind {
+        NodeKind::ParamEq { .. } => "param_eq",
+        NodeKind::Other(other) => other.label.as_deref()?,
+        // All biquads have a single input and output
+        _ => "bq",
+    };
+
+    Some(match (label, direction) {
+        ("bq" | "copy" | "delay" | "convolver" | "invert", PortDirection::Input) => {
+            vec!["In".into()]
+        }
+        ("bq" | "copy" | "delay" | "convolver" | "invert" | "mixer", PortDirection::Output) => {
+            vec!["Out".into()]
+        }
+        ("param_eq", PortDirection::Input) => numbered("In"),
+        ("param_eq", PortDirection::Output) => numbered("Out"),
+        ("mixer", PortDirection::Input) => numbered("In"),
+        _ => return None,
+    })
+}
+
+fn stream_channels(props: &spa_json::Map<String, spa_json::Value>) -> Option<usize> {
+    match props.get("audio.channels")? {
+        spa_json::Value::Number(channels) => channels.as_u64().map(|channels| channels as usize),
+        _ => None,
+    }
+}
+
+fn report(problems: Vec<String>) -> anyhow::Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    anyhow::bail!("invalid filter graph:\n  {}", problems.join("\n  "))
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    use crate::module::{
+        AudioPosition, ChannelChain, ChannelLayout, Control, FilterGraph, Module, NodeKind,
+        PluginStage,
+    };
+
+    fn peaking() -> NodeKind {
+        NodeKind::Peaking {
+            control: Control {
+                freq: 1000.0,
+                q: 1.0,
+                gain: 3.0,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_generated_graphs_are_valid() {
+        let mut module = Module::from_kinds("eq", -3.0, &ChannelLayout::stereo(), [peaking()]);
+        module.args.validate().unwrap();
+
+        let plugins: Vec<PluginStage> =
+            spa_json::from_str("[ { type = ladspa plugin = limiter label = limit } ]").unwrap();
+        module.append_plugins(&plugins);
+        module.args.validate().unwrap();
+
+        let mut module = Module::from_channel_chains(
+            "eq",
+            &ChannelLayout::stereo(),
+            [AudioPosition::FrontLeft, AudioPosition::FrontRight].map(|position| ChannelChain {
+                position,
+                preamp: 0.0,
+                kinds: vec![peaking(), peaking()],
+            }),
+        )
+        .unwrap();
+        module.args.validate().unwrap();
+        module.append_plugins(&plugins);
+        module.args.validate().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_graph() {
+        let graph: FilterGraph = spa_json::from_str(
+            r#"{
+                nodes = [
+                    { type = builtin name = a label = bq_peaking control = { freq = 1000 q = 1 gain = 0 } }
+                    { type = builtin name = b label = param_eq config = { filters = [] } }
+                    { type = builtin name = a label = copy }
+                    { type = ladspa name = c plugin = limiter label = limit }
+                ]
+                links = [
+                    { output = "a:Out" input = "b:In 1" }
+                    { output = "b:Out 9" input = "c:Input" }
+                    { output = "c:Output" input = "b:In 1" }
+                    { output = "d:Out" input = "a:In" }
+                    { output = "b:Out 1" input = "a:Out" }
+                ]
+                inputs = [ "In" ]
+                outputs = [ "c:Output" "b:Out 2" "b:Out 3" ]
+            }"#,
+        )
+        .unwrap();
+
+        expect![[r#"
+            invalid filter graph:
+              duplicate node name `a`
+              link output `b:Out 9` refers to unknown output port `Out 9` of `b` (expected one of Out 1, Out 2, Out 3, Out 4, Out 5, Out 6, Out 7, Out 8)
+              input port `b:In 1` is linked more than once
+              link output `d:Out` refers to unknown node `d`
+              link input `a:Out` refers to unknown input port `Out` of `a` (expected one of In)
+              graph input `In` is also the input of a link
+              links form a cycle: b -> c -> b"#]]
+        .assert_eq(&graph.validate().unwrap_err().to_string());
+
+        let mut module = Module::from_kinds("eq", 0.0, &ChannelLayout::stereo(), [peaking()]);
+        module.args.filter_graph = graph;
+        expect![[r#"
+            invalid filter graph:
+              duplicate node name `a`
+              link output `b:Out 9` refers to unknown output port `Out 9` of `b` (expected one of Out 1, Out 2, Out 3, Out 4, Out 5, Out 6, Out 7, Out 8)
+              input port `b:In 1` is linked more than once
+              link output `d:Out` refers to unknown node `d`
+              link input `a:Out` refers to unknown input port `Out` of `a` (expected one of In)
+              graph input `In` is also the input of a link
+              links form a cycle: b -> c -> b
+              playback has 2 channels, which is not a multiple of the 3 graph outputs"#]]
+        .assert_eq(&module.args.validate().unwrap_err().to_string());
+    }
+}
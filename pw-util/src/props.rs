@@ -0,0 +1,153 @@
+//! Typed filter-chain controls, decoded from a node's `Props` and `PropInfo` params
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::PwParams;
+
+/// The controls of one filter-graph node, e.g. `pweq.filter_3` with `Freq`, `Q` and `Gain`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NodeControls {
+    pub node: String,
+    pub controls: BTreeMap<String, ControlParam>,
+}
+
+/// A control's current value, with the default and range the plugin declares for it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ControlParam {
+    pub value: Option<f64>,
+    pub default: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl ControlParam {
+    /// Whether `value` is within the declared range
+    pub fn accepts(&self, value: f64) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+impl NodeControls {
+    pub fn value(&self, control: &str) -> Option<f64> {
+        self.controls.get(control)?.value
+    }
+}
+
+/// The entry for a control named `<node>:<control>`, as filter-graph controls are
+fn control_mut<'a>(nodes: &'a mut Vec<NodeControls>, name: &str) -> Option<&'a mut ControlParam> {
+    let (node, control) = name.split_once(':')?;
+    let idx = match nodes.iter().position(|controls| controls.node == node) {
+        Some(idx) => idx,
+        None => {
+            nodes.push(NodeControls {
+                node: node.to_string(),
+                controls: BTreeMap::new(),
+            });
+            nodes.len() - 1
+        }
+    };
+    Some(nodes[idx].controls.entry(control.to_string()).or_default())
+}
+
+/// Decode the controls of every filter-graph node, in the order the node declares them
+pub fn filter_controls(params: &PwParams) -> Vec<NodeControls> {
+    let mut nodes = Vec::new();
+
+    for info in &params.prop_info {
+        let Some(param) = info
+            .name
+            .as_deref()
+            .and_then(|name| control_mut(&mut nodes, name))
+        else {
+            continue;
+        };
+        match &info.type_ {
+            Value::Object(range) => {
+                let get = |key| range.get(key).and_then(Value::as_f64);
+                param.default = get("default");
+                param.min = get("min");
+                param.max = get("max");
+            }
+            value => param.default = value.as_f64(),
+        }
+    }
+
+    for props in &params.props {
+        for (name, value) in &props.params.0 {
+            if let Some(param) = control_mut(&mut nodes, name) {
+                param.value = value.as_f64();
+            }
+        }
+    }
+
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ControlParam;
+    use crate::PwParams;
+
+    #[test]
+    fn test_filter_controls() {
+        // Hand-written in the shape `pw-dump` gives a pw-eq node
+        let params: PwParams = serde_json::from_value(serde_json::json!({
+            "PropInfo": [
+                {
+                    "name": "pweq.filter_preamp:Freq",
+                    "type": { "default": 0.0, "min": 0.0, "max": 1.0 },
+                    "params": true
+                },
+                {
+                    "name": "pweq.filter_preamp:Gain",
+                    "type": { "default": 0.0, "min": -120.0, "max": 20.0 },
+                    "params": true
+                },
+                {
+                    "name": "pweq.filter_1:Freq",
+                    "type": { "default": 1000.0, "min": 0.0, "max": 24000.0 },
+                    "params": true
+                },
+                { "name": "pweq.filter_1:Q", "type": 0.707, "params": true },
+                { "id": "volume", "description": "Volume", "type": { "default": 1.0, "min": 0.0, "max": 10.0 } }
+            ],
+            "Props": [
+                { "volume": 1.0, "mute": false },
+                {
+                    "params": [
+                        "pweq.filter_preamp:Gain", -3.5,
+                        "pweq.filter_1:Freq", 105.0,
+                        "pweq.filter_1:Q", 0.7,
+                        "pweq.filter_1:Gain", 4.0
+                    ]
+                }
+            ]
+        }))
+        .unwrap();
+
+        let controls = super::filter_controls(&params);
+        assert_eq!(controls.len(), 2);
+        assert_eq!(
+            controls[0].controls["Gain"],
+            ControlParam {
+                value: Some(-3.5),
+                default: Some(0.0),
+                min: Some(-120.0),
+                max: Some(20.0),
+            }
+        );
+        // Declared but not set
+        assert_eq!(controls[0].value("Freq"), None);
+
+        let filter = &controls[1];
+        assert_eq!(filter.node, "pweq.filter_1");
+        assert_eq!(filter.value("Freq"), Some(105.0));
+        assert_eq!(filter.controls["Q"].default, Some(0.707));
+        // Set but not declared
+        assert_eq!(filter.value("Gain"), Some(4.0));
+        assert!(filter.controls["Freq"].accepts(20000.0));
+        assert!(!filter.controls["Freq"].accepts(30000.0));
+    }
+}
@@ -0,0 +1,567 @@
+//! Native registry client that collects globals into the same model `pw-dump` produces
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::rc::Rc;
+
+use anyhow::Context as _;
+use pipewire::{
+    context::ContextRc,
+    core::{CoreRc, PW_ID_CORE},
+    device::Device,
+    main_loop::MainLoopRc,
+    node::{Node, NodeInfoRef, NodeState},
+    permissions::PermissionFlags,
+    proxy::ProxyT,
+    registry::RegistryRc,
+    spa::{
+        self,
+        param::ParamType,
+        pod::{ChoiceValue, Pod, Value as PodValue, ValueArray, deserialize::PodDeserializer},
+        sys::spa_type_info,
+        utils::{Choice, ChoiceEnum, dict::DictRef},
+    },
+    types::ObjectType,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::control::PwError;
+use crate::{
+    Format, Latency, ProcessLatency, Profile, Prop, PwDumpObject, PwObjectInfo, PwObjectType,
+    PwParams, PwPropInfo, Route,
+};
+
+/// A connection to the PipeWire daemon driven from the calling thread
+pub struct Connection {
+    mainloop: MainLoopRc,
+    core: CoreRc,
+    registry: RegistryRc,
+    _context: ContextRc,
+}
+
+impl Connection {
+    pub fn new() -> anyhow::Result<Self> {
+        pipewire::init();
+        let mainloop = MainLoopRc::new(None).context("failed to create main loop")?;
+        let context = ContextRc::new(&mainloop, None).context("failed to create context")?;
+        let core = context
+            .connect_rc(None)
+            .context("failed to connect to PipeWire")?;
+        let registry = core.get_registry_rc().context("failed to get registry")?;
+
+        Ok(Self {
+            mainloop,
+            core,
+            registry,
+            _context: context,
+        })
+    }
+
+    pub fn mainloop(&self) -> &MainLoopRc {
+        &self.mainloop
+    }
+
+    pub fn core(&self) -> &CoreRc {
+        &self.core
+    }
+
+    pub fn registry(&self) -> &RegistryRc {
+        &self.registry
+    }
+
+    /// Bind the global with the given id, which must be of type `T`
+    pub fn bind<T: ProxyT + 'static>(&self, id: u32) -> anyhow::Result<T> {
+        let proxy = Rc::new(RefCell::new(None));
+        let _listener = self
+            .registry
+            .add_listener_local()
+            .global({
+                let proxy = proxy.clone();
+                let registry = self.registry.downgrade();
+                move |global| {
+                    if global.id != id {
+                        return;
+                    }
+                    let result = if global.type_ == T::type_() {
+                        registry
+                            .upgrade()
+                            .context("registry was destroyed")
+                            .and_then(|registry| Ok(registry.bind::<T, _>(global)?))
+                    } else {
+                        Err(anyhow::anyhow!(
+                            "object {id} is a {}, not a {}",
+                            global.type_,
+                            T::type_()
+                        ))
+                    };
+                    *proxy.borrow_mut() = Some(result);
+                }
+            })
+            .register();
+        self.roundtrip()?;
+
+        proxy
+            .take()
+            .with_context(|| format!("object {id} not found"))?
+    }
+
+    /// Run the main loop until the daemon has processed every request sent so far
+    pub fn roundtrip(&self) -> anyhow::Result<()> {
+        let done = Rc::new(Cell::new(false));
+        let error = Rc::new(RefCell::new(None));
+        let pending = self.core.sync(0).context("failed to sync with PipeWire")?;
+
+        let _listener = self
+            .core
+            .add_listener_local()
+            .done({
+                let done = done.clone();
+                let mainloop = self.mainloop.clone();
+                move |id, seq| {
+                    if id == PW_ID_CORE && seq == pending {
+                        done.set(true);
+                        mainloop.quit();
+                    }
+                }
+            })
+            .error({
+                let error = error.clone();
+                let mainloop = self.mainloop.clone();
+                move |id, _seq, res, message| {
+                    // Errors on other proxies are reported to whoever owns them
+                    if id == PW_ID_CORE {
+                        *error.borrow_mut() = Some(format!("{message} ({res})"));
+                        mainloop.quit();
+                    }
+                }
+            })
+            .register();
+
+        while !done.get() {
+            self.mainloop.run();
+            if let Some(error) = error.borrow_mut().take() {
+                anyhow::bail!("PipeWire error: {error}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Destroy a global, as `pw-cli destroy` does. The client needs permission to do so.
+pub fn destroy(id: u32) -> anyhow::Result<()> {
+    let conn = Connection::new()?;
+
+    // A refused destroy is reported on the core, against the registry's proxy id. The pipewire
+    // crate only exposes proxy ids on bound objects, not on the registry.
+    // SAFETY: a `pw_registry` is a `pw_proxy`, and `conn` keeps it alive for the call
+    let registry_id = unsafe { pipewire_sys::pw_proxy_get_id(conn.registry().as_raw_ptr().cast()) };
+    let error = Rc::new(RefCell::new(None));
+    let _listener = conn
+        .core()
+        .add_listener_local()
+        .error({
+            let error = error.clone();
+            move |id, _seq, code, message| {
+                if id == registry_id {
+                    *error.borrow_mut() = Some(PwError {
+                        code,
+                        message: message.to_string(),
+                    });
+                }
+            }
+        })
+        .register();
+
+    conn.registry()
+        .destroy_global(id)
+        .into_result()
+        .map_err(|err| anyhow::anyhow!("failed to destroy object {id}: {err}"))?;
+    conn.roundtrip()?;
+
+    match error.take() {
+        Some(error) => {
+            Err(anyhow::Error::new(error).context(format!("failed to destroy object {id}")))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Enumerate every global with its props, and node and device info and params
+pub fn dump() -> anyhow::Result<Vec<PwDumpObject>> {
+    let conn = Connection::new()?;
+    let objects = Rc::new(RefCell::new(BTreeMap::<u32, PwDumpObject>::new()));
+    // Bound nodes and devices and their listeners must outlive the roundtrips
+    let proxies = Rc::new(RefCell::new(Vec::<Box<dyn Any>>::new()));
+
+    let _listener = conn
+        .registry()
+        .add_listener_local()
+        .global({
+            let objects = objects.clone();
+            let proxies = proxies.clone();
+            let registry = conn.registry().downgrade();
+            move |global| {
+                let object_type = PwObjectType::from(global.type_.to_str());
+
+                objects.borrow_mut().insert(
+                    global.id,
+                    PwDumpObject {
+                        id: global.id,
+                        object_type,
+                        version: Some(global.version),
+                        permissions: Some(permissions(global.permissions)),
+                        info: PwObjectInfo::default(),
+                        props: global.props.map(dict_to_map),
+                    },
+                );
+
+                let Some(registry) = registry.upgrade() else {
+                    return;
+                };
+                let id = global.id;
+                match global.type_ {
+                    ObjectType::Node => {
+                        let Ok(node) = registry.bind::<Node, _>(global) else {
+                            return;
+                        };
+                        let listener = node
+                            .add_listener_local()
+                            .info({
+                                let objects = objects.clone();
+                                move |info| {
+                                    if let Some(object) = objects.borrow_mut().get_mut(&id) {
+                                        node_info(&mut object.info, info);
+                                    }
+                                }
+                            })
+                            .param(collect_params(&objects, id))
+                            .register();
+                        for param in Param::NODE_TYPES {
+                            node.enum_params(0, Some(param), 0, u32::MAX);
+                        }
+                        proxies.borrow_mut().push(Box::new((node, listener)));
+                    }
+                    ObjectType::Device => {
+                        let Ok(device) = registry.bind::<Device, _>(global) else {
+                            return;
+                        };
+                        let listener = device
+                            .add_listener_local()
+                            .info({
+                                let objects = objects.clone();
+                                move |info| {
+                                    let mut objects = objects.borrow_mut();
+                                    if let (Some(object), Some(props)) =
+                                        (objects.get_mut(&id), info.props())
+                                    {
+                                        object.info.props = dict_to_map(props);
+                                    }
+                                }
+                            })
+                            .param(collect_params(&objects, id))
+                            .register();
+                        for param in Param::DEVICE_TYPES {
+                            device.enum_params(0, Some(param), 0, u32::MAX);
+                        }
+                        proxies.borrow_mut().push(Box::new((device, listener)));
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .register();
+
+    // The first roundtrip delivers the globals, the second the info and params of bound objects
+    conn.roundtrip()?;
+    conn.roundtrip()?;
+
+    let objects = objects.take();
+    Ok(objects.into_values().collect())
+}
+
+fn permissions(flags: PermissionFlags) -> Vec<String> {
+    [
+        (PermissionFlags::R, "r"),
+        (PermissionFlags::W, "w"),
+        (PermissionFlags::X, "x"),
+        (PermissionFlags::M, "m"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+/// Dict values are all strings; like `pw-dump`, keep numbers, bools and null as such
+pub(crate) fn dict_to_map(dict: &DictRef) -> std::collections::HashMap<String, Value> {
+    dict.iter()
+        .map(|(key, value)| {
+            let value = match serde_json::from_str::<Value>(value) {
+                Ok(value @ (Value::Number(_) | Value::Bool(_) | Value::Null)) => value,
+                _ => Value::String(value.to_string()),
+            };
+            (key.to_string(), value)
+        })
+        .collect()
+}
+
+pub(crate) fn node_info(info: &mut PwObjectInfo, node: &NodeInfoRef) {
+    if let Some(props) = node.props() {
+        info.props = dict_to_map(props);
+    }
+
+    let (state, error) = match node.state() {
+        NodeState::Error(error) => ("error", Some(error.to_string())),
+        NodeState::Creating => ("creating", None),
+        NodeState::Suspended => ("suspended", None),
+        NodeState::Idle => ("idle", None),
+        NodeState::Running => ("running", None),
+    };
+    let fields = [
+        ("max-input-ports", Value::from(node.max_input_ports())),
+        ("max-output-ports", Value::from(node.max_output_ports())),
+        ("n-input-ports", Value::from(node.n_input_ports())),
+        ("n-output-ports", Value::from(node.n_output_ports())),
+        ("state", Value::from(state)),
+        ("error", Value::from(error)),
+    ];
+    info.fields
+        .extend(fields.map(|(key, value)| (key.to_string(), value)));
+}
+
+/// A node or device param of one of the kinds `PwParams` models
+#[derive(Debug, Clone)]
+pub enum Param {
+    EnumFormat(Value),
+    Format(Format),
+    PropInfo(PwPropInfo),
+    Props(Prop),
+    EnumRoute(Route),
+    Route(Route),
+    EnumProfile(Profile),
+    Profile(Profile),
+    Latency(Latency),
+    ProcessLatency(ProcessLatency),
+}
+
+impl Param {
+    /// The node param kinds clients enumerate or subscribe to
+    pub(crate) const NODE_TYPES: [ParamType; 6] = [
+        ParamType::EnumFormat,
+        ParamType::Format,
+        ParamType::PropInfo,
+        ParamType::Props,
+        ParamType::Latency,
+        ParamType::ProcessLatency,
+    ];
+
+    /// The device param kinds clients enumerate or subscribe to
+    pub(crate) const DEVICE_TYPES: [ParamType; 4] = [
+        ParamType::EnumRoute,
+        ParamType::Route,
+        ParamType::EnumProfile,
+        ParamType::Profile,
+    ];
+
+    pub(crate) fn from_pod(param: ParamType, pod: &Pod) -> Option<Self> {
+        fn typed<T: DeserializeOwned>(value: Value, param: fn(T) -> Param) -> Option<Param> {
+            serde_json::from_value(value).ok().map(param)
+        }
+
+        let (_, value) = PodDeserializer::deserialize_any_from(pod.as_bytes()).ok()?;
+        let value = pod_to_json(&value, None);
+
+        match param {
+            ParamType::EnumFormat => Some(Param::EnumFormat(value)),
+            ParamType::Format => typed(value, Param::Format),
+            ParamType::PropInfo => typed(value, Param::PropInfo),
+            ParamType::Props => typed(value, Param::Props),
+            ParamType::EnumRoute => typed(value, Param::EnumRoute),
+            ParamType::Route => typed(value, Param::Route),
+            ParamType::EnumProfile => typed(value, Param::EnumProfile),
+            ParamType::Profile => typed(value, Param::Profile),
+            ParamType::Latency => typed(value, Param::Latency),
+            ParamType::ProcessLatency => typed(value, Param::ProcessLatency),
+            _ => None,
+        }
+    }
+}
+
+impl PwParams {
+    pub(crate) fn push(&mut self, param: Param) {
+        match param {
+            Param::EnumFormat(value) => self.enum_format.push(value),
+            Param::Format(format) => self.format.push(format),
+            Param::PropInfo(prop_info) => self.prop_info.push(prop_info),
+            Param::Props(props) => self.props.push(props),
+            Param::EnumRoute(route) => self.enum_route.push(route),
+            Param::Route(route) => self.route.push(route),
+            Param::EnumProfile(profile) => self.enum_profile.push(profile),
+            Param::Profile(profile) => self.profile.push(profile),
+            Param::Latency(latency) => self.latency.push(latency),
+            Param::ProcessLatency(latency) => self.process_latency.push(latency),
+        }
+    }
+}
+
+/// Collect the params a bound node or device reports into its object
+fn collect_params(
+    objects: &Rc<RefCell<BTreeMap<u32, PwDumpObject>>>,
+    id: u32,
+) -> impl Fn(i32, ParamType, u32, u32, Option<&Pod>) + 'static {
+    let objects = objects.clone();
+    move |_seq, param, _index, _next, pod| {
+        let Some(param) = pod.and_then(|pod| Param::from_pod(param, pod)) else {
+            return;
+        };
+        if let Some(object) = objects.borrow_mut().get_mut(&id) {
+            object.info.params.push(param);
+        }
+    }
+}
+
+/// Convert a pod to the JSON `pw-dump` prints for it. `info` is the type info of the object key
+/// holding the value, which names ids (e.g. `mediaType` or channel positions).
+fn pod_to_json(value: &PodValue, info: Option<&spa_type_info>) -> Value {
+    match value {
+        PodValue::None | PodValue::Bytes(_) | PodValue::Pointer(..) => Value::Null,
+        PodValue::Bool(b) => Value::Bool(*b),
+        PodValue::Id(id) => id_to_json(id.0, info),
+        PodValue::Int(n) => Value::from(*n),
+        PodValue::Long(n) => Value::from(*n),
+        PodValue::Float(n) => Value::from(f64::from(*n)),
+        PodValue::Double(n) => Value::from(*n),
+        PodValue::String(s) => Value::String(s.clone()),
+        PodValue::Rectangle(rect) => rectangle_to_json(rect),
+        PodValue::Fraction(fraction) => fraction_to_json(fraction),
+        PodValue::Fd(fd) => Value::from(fd.0),
+        PodValue::ValueArray(array) => Value::Array(match array {
+            ValueArray::None(values) => values.iter().map(|_| Value::Null).collect(),
+            ValueArray::Bool(values) => values.iter().map(|&b| Value::Bool(b)).collect(),
+            ValueArray::Id(values) => values.iter().map(|id| id_to_json(id.0, info)).collect(),
+            ValueArray::Int(values) => values.iter().map(|&n| Value::from(n)).collect(),
+            ValueArray::Long(values) => values.iter().map(|&n| Value::from(n)).collect(),
+            ValueArray::Float(values) => {
+                values.iter().map(|&n| Value::from(f64::from(n))).collect()
+            }
+            ValueArray::Double(values) => values.iter().map(|&n| Value::from(n)).collect(),
+            ValueArray::Rectangle(values) => values.iter().map(rectangle_to_json).collect(),
+            ValueArray::Fraction(values) => values.iter().map(fraction_to_json).collect(),
+            ValueArray::Fd(values) => values.iter().map(|fd| Value::from(fd.0)).collect(),
+        }),
+        PodValue::Struct(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| pod_to_json(value, None))
+                .collect(),
+        ),
+        PodValue::Object(object) => {
+            // SAFETY: the root type table is a static
+            let keys =
+                find_type(unsafe { spa::sys::spa_types }, object.type_).map(|info| info.values);
+            let map = object
+                .properties
+                .iter()
+                .map(|prop| {
+                    let info = keys.and_then(|keys| find_type(keys, prop.key));
+                    let key = info.map_or_else(|| prop.key.to_string(), short_name);
+                    (key, pod_to_json(&prop.value, info))
+                })
+                .collect::<Map<_, _>>();
+            Value::Object(map)
+        }
+        PodValue::Choice(choice) => match choice {
+            ChoiceValue::Bool(choice) => choice_to_json(choice, |&b| Value::Bool(b)),
+            ChoiceValue::Int(choice) => choice_to_json(choice, |&n| Value::from(n)),
+            ChoiceValue::Long(choice) => choice_to_json(choice, |&n| Value::from(n)),
+            ChoiceValue::Float(choice) => choice_to_json(choice, |&n| Value::from(f64::from(n))),
+            ChoiceValue::Double(choice) => choice_to_json(choice, |&n| Value::from(n)),
+            ChoiceValue::Id(choice) => choice_to_json(choice, |id| id_to_json(id.0, info)),
+            ChoiceValue::Rectangle(choice) => choice_to_json(choice, rectangle_to_json),
+            ChoiceValue::Fraction(choice) => choice_to_json(choice, fraction_to_json),
+            ChoiceValue::Fd(choice) => choice_to_json(choice, |fd| Value::from(fd.0)),
+        },
+    }
+}
+
+/// Choices use the same labels as `pw-dump`, a choice of none is just its value
+fn choice_to_json<T: spa::pod::CanonicalFixedSizedPod>(
+    choice: &Choice<T>,
+    to_json: impl Fn(&T) -> Value,
+) -> Value {
+    let Choice(_, choice) = choice;
+    let entries = match choice {
+        ChoiceEnum::None(value) => return to_json(value),
+        ChoiceEnum::Range { default, min, max } => vec![
+            ("default".to_string(), to_json(default)),
+            ("min".to_string(), to_json(min)),
+            ("max".to_string(), to_json(max)),
+        ],
+        ChoiceEnum::Step {
+            default,
+            min,
+            max,
+            step,
+        } => vec![
+            ("default".to_string(), to_json(default)),
+            ("min".to_string(), to_json(min)),
+            ("max".to_string(), to_json(max)),
+            ("step".to_string(), to_json(step)),
+        ],
+        ChoiceEnum::Enum {
+            default,
+            alternatives,
+        } => std::iter::once(("default".to_string(), to_json(default)))
+            .chain(
+                alternatives
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| (format!("alt{}", i + 1), to_json(value))),
+            )
+            .collect(),
+        ChoiceEnum::Flags { default, flags } => {
+            std::iter::once(("default".to_string(), to_json(default)))
+                .chain(
+                    flags
+                        .iter()
+                        .enumerate()
+                        .map(|(i, value)| (format!("flag{}", i + 1), to_json(value))),
+                )
+                .collect()
+        }
+    };
+    Value::Object(entries.into_iter().collect())
+}
+
+fn id_to_json(id: u32, info: Option<&spa_type_info>) -> Value {
+    info.and_then(|info| find_type(info.values, id))
+        .map_or_else(|| Value::from(id), |info| Value::String(short_name(info)))
+}
+
+fn rectangle_to_json(rect: &spa::utils::Rectangle) -> Value {
+    serde_json::json!({ "width": rect.width, "height": rect.height })
+}
+
+fn fraction_to_json(fraction: &spa::utils::Fraction) -> Value {
+    serde_json::json!({ "num": fraction.num, "denom": fraction.denom })
+}
+
+/// Look up `type_` in a type info table, including the tables nested in it
+fn find_type(table: *const spa_type_info, type_: u32) -> Option<&'static spa_type_info> {
+    if table.is_null() {
+        return None;
+    }
+    // SAFETY: the tables are static and terminated by an entry without a name
+    unsafe { spa::sys::spa_debug_type_find(table, type_).as_ref() }
+}
+
+/// The last component of a type name, e.g. `volume` for `Spa:Pod:Object:Param:Props:volume`
+fn short_name(info: &spa_type_info) -> String {
+    // SAFETY: type info names are static nul-terminated strings
+    let name = unsafe { CStr::from_ptr(info.name) }.to_string_lossy();
+    name.rsplit(':').next().unwrap_or_default().to_string()
+}
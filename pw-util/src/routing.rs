@@ -0,0 +1,224 @@
+//! Application streams and the nodes they play into or record from. Setting an EQ as the
+//! default only moves streams that follow the default, so streams pinned to a device are moved
+//! onto it with the `target.object` metadata the session manager follows. Where a stream was
+//! before is kept next to its pin, so it can be put back from another process.
+
+use anyhow::Context as _;
+
+use crate::backend::PwBackend;
+use crate::metadata::RouteOrigin;
+use crate::module::Direction;
+use crate::{PwDumpObject, PwObjectType};
+
+/// A playback or capture stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stream {
+    pub id: u32,
+    /// `application.name`, falling back to the node name
+    pub app: String,
+    /// `application.process.binary`, e.g. `firefox`
+    pub binary: Option<String>,
+    /// What is playing, e.g. the tab title
+    pub media_name: Option<String>,
+    /// `Sink` for playback streams, `Source` for capture streams
+    pub direction: Direction,
+    /// The nodes the stream is linked to, usually just one
+    pub nodes: Vec<u32>,
+    /// The node the stream is pinned to, if it doesn't follow the default
+    pub target: Option<u32>,
+    /// Where the stream was before it was routed onto an EQ, if it was
+    pub origin: Option<RouteOrigin>,
+}
+
+impl Stream {
+    /// Whether `app` names this stream's application or binary, ignoring case
+    pub fn matches_app(&self, app: &str) -> bool {
+        self.app.eq_ignore_ascii_case(app)
+            || self
+                .binary
+                .as_deref()
+                .is_some_and(|binary| binary.eq_ignore_ascii_case(app))
+    }
+
+    fn from_object(obj: &PwDumpObject, objects: &[PwDumpObject]) -> Option<Self> {
+        // The streams of a filter-chain or loopback carry its own audio, not an application's
+        if obj.object_type != PwObjectType::Node || obj.prop("node.link-group").is_some() {
+            return None;
+        }
+        let prop = |key| obj.prop(key)?.as_str().map(str::to_string);
+        let direction = match prop("media.class")?.as_str() {
+            "Stream/Output/Audio" => Direction::Sink,
+            "Stream/Input/Audio" => Direction::Source,
+            _ => return None,
+        };
+
+        let mut nodes = objects
+            .iter()
+            .filter_map(PwDumpObject::link_nodes)
+            .filter_map(|(output, input)| match direction {
+                Direction::Sink if output == obj.id => Some(input),
+                Direction::Source if input == obj.id => Some(output),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // One link per channel
+        nodes.sort_unstable();
+        nodes.dedup();
+
+        Some(Self {
+            id: obj.id,
+            app: prop("application.name")
+                .or_else(|| prop("node.name"))
+                .unwrap_or_else(|| format!("stream {}", obj.id)),
+            binary: prop("application.process.binary"),
+            media_name: prop("media.name"),
+            direction,
+            nodes,
+            target: None,
+            origin: None,
+        })
+    }
+}
+
+/// Every audio stream, with the nodes it is linked to, the node it is pinned to and where it was
+/// before it was routed
+pub async fn list_streams(backend: &dyn PwBackend) -> anyhow::Result<Vec<Stream>> {
+    let objects = backend.dump().await?;
+    let targets = backend.stream_targets().await?;
+    let origins = backend.route_origins().await?;
+    let streams = objects
+        .iter()
+        .filter_map(|obj| Stream::from_object(obj, &objects))
+        .map(|stream| Stream {
+            target: targets.get(&stream.id).copied(),
+            origin: origins.get(&stream.id).copied(),
+            ..stream
+        })
+        .collect();
+    Ok(streams)
+}
+
+/// Pin a stream to a node, recording where it was unless it was already routed. Keep the stream
+/// as listed to hand to `restore_stream`.
+pub async fn route_stream(
+    backend: &dyn PwBackend,
+    stream: &Stream,
+    node_id: u32,
+) -> anyhow::Result<()> {
+    if stream.origin.is_none() {
+        let origin = stream
+            .target
+            .map_or(RouteOrigin::Default, RouteOrigin::Pinned);
+        backend
+            .set_route_origin(stream.id, Some(origin))
+            .await
+            .with_context(|| format!("failed to record where {} plays", stream.app))?;
+    }
+
+    let pinned = backend
+        .set_stream_target(stream.id, Some(node_id))
+        .await
+        .with_context(|| format!("failed to move {} onto node {node_id}", stream.app));
+    if pinned.is_err() && stream.origin.is_none() {
+        let _ = backend.set_route_origin(stream.id, None).await;
+    }
+    pinned
+}
+
+/// Put a stream back where it was before it was routed: on the node it was pinned to, or
+/// following the default again if it wasn't pinned
+pub async fn restore_stream(backend: &dyn PwBackend, before: &Stream) -> anyhow::Result<()> {
+    let target = match before.origin {
+        Some(RouteOrigin::Pinned(node_id)) => Some(node_id),
+        Some(RouteOrigin::Default) => None,
+        None => before.target,
+    };
+    backend.set_stream_target(before.id, target).await?;
+    backend.set_route_origin(before.id, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Stream, list_streams, restore_stream, route_stream};
+    use crate::backend::{Fake, PwBackend as _};
+    use crate::metadata::RouteOrigin;
+    use crate::module::Direction;
+
+    #[tokio::test]
+    async fn test_route_and_restore() {
+        let fake = Fake::new();
+        let speakers = fake.add_node(json!({
+            "node.name": "speakers",
+            "media.class": "Audio/Sink",
+        }));
+        let headphones = fake.add_node(json!({
+            "node.name": "headphones",
+            "media.class": "Audio/Sink",
+        }));
+        let eq = fake.add_node(json!({
+            "node.name": "effect_input.pweq.music",
+            "media.class": "Audio/Sink",
+        }));
+        fake.set_default(speakers).await.unwrap();
+
+        let firefox = fake.add_node(json!({
+            "node.name": "Firefox",
+            "application.name": "Firefox",
+            "application.process.binary": "firefox",
+            "media.name": "Video",
+            "media.class": "Stream/Output/Audio",
+        }));
+        fake.add_link(firefox, headphones);
+        fake.add_link(firefox, headphones);
+        fake.set_stream_target(firefox, Some(headphones))
+            .await
+            .unwrap();
+        let mic = fake.add_node(json!({
+            "node.name": "recorder",
+            "media.class": "Stream/Input/Audio",
+        }));
+
+        let streams = list_streams(&fake).await.unwrap();
+        assert_eq!(streams.len(), 2);
+        let before = &streams[0];
+        assert!(before.matches_app("FIREFOX"));
+        assert_eq!(before.media_name.as_deref(), Some("Video"));
+        assert_eq!(before.direction, Direction::Sink);
+        assert_eq!(before.nodes, vec![headphones]);
+        assert_eq!(before.target, Some(headphones));
+        assert_eq!(streams[1].id, mic);
+        assert_eq!(streams[1].app, "recorder");
+        assert_eq!(streams[1].direction, Direction::Source);
+
+        route_stream(&fake, before, eq).await.unwrap();
+        assert_eq!(fake.links(firefox), vec![eq; 2]);
+
+        // Back on the device it was pinned to, not the default
+        restore_stream(&fake, before).await.unwrap();
+        assert_eq!(fake.links(firefox), vec![headphones; 2]);
+        assert!(fake.route_origins().await.unwrap().is_empty());
+
+        // The origin is kept in the metadata, so a stream listed after it was routed (by
+        // another process) goes back to the same place
+        route_stream(&fake, before, eq).await.unwrap();
+        let routed = list_streams(&fake).await.unwrap().remove(0);
+        assert_eq!(routed.target, Some(eq));
+        assert_eq!(routed.origin, Some(RouteOrigin::Pinned(headphones)));
+        // Routing it again keeps where it came from
+        route_stream(&fake, &routed, speakers).await.unwrap();
+        restore_stream(&fake, &routed).await.unwrap();
+        assert_eq!(fake.links(firefox), vec![headphones; 2]);
+
+        // A stream that wasn't pinned follows the default again
+        let unpinned = Stream {
+            target: None,
+            ..before.clone()
+        };
+        route_stream(&fake, &unpinned, eq).await.unwrap();
+        restore_stream(&fake, &unpinned).await.unwrap();
+        assert_eq!(fake.links(firefox), vec![speakers; 2]);
+        assert!(fake.stream_targets().await.unwrap().is_empty());
+    }
+}
@@ -407,3 +407,43 @@ where
         })
     }
 }
+
+/// Deserialize a field, falling back to its default if it is `null` or has an unexpected
+/// shape, so one odd field doesn't fail the whole object
+pub fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: de::Deserializer<'de>,
+    T: de::DeserializeOwned + Default,
+{
+    let value = <serde_json::Value as de::Deserialize>::deserialize(deserializer)?;
+    if value.is_null() {
+        return Ok(T::default());
+    }
+    Ok(serde_json::from_value(value).unwrap_or_else(|err| {
+        tracing::warn!(
+            %err,
+            "ignoring malformed {}",
+            std::any::type_name::<T>()
+        );
+        T::default()
+    }))
+}
+
+/// Deserialize a list, skipping the elements that have an unexpected shape
+pub fn lenient_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: de::Deserializer<'de>,
+    T: de::DeserializeOwned,
+{
+    let values: Vec<serde_json::Value> = lenient(deserializer)?;
+    Ok(values
+        .into_iter()
+        .filter_map(|value| {
+            serde_json::from_value(value)
+                .inspect_err(|err| {
+                    tracing::warn!(%err, "skipping malformed {}", std::any::type_name::<T>());
+                })
+                .ok()
+        })
+        .collect())
+}
@@ -16,6 +16,8 @@ use std::vec::Vec;
 
 pub use crate::read::Read;
 
+const ROOT_DEPTH: u8 = 128;
+
 /// A structure that deserializes JSON into Rust values.
 pub struct Deserializer<R> {
     read: R,
@@ -45,7 +47,7 @@ where
         Deserializer {
             read,
             scratch: Vec::new(),
-            remaining_depth: 128,
+            remaining_depth: ROOT_DEPTH,
         }
     }
 }
@@ -183,6 +185,13 @@ impl<'de, R: Read<'de>> Deserializer<R> {
                 Some(b' ' | b'\n' | b'\t' | b'\r') => {
                     self.eat_char();
                 }
+                // patch(spa): `#` starts a comment running to the end of the line
+                Some(b'#') => loop {
+                    match tri!(self.next_char()) {
+                        Some(b'\n') | None => break,
+                        Some(_) => {}
+                    }
+                },
                 other => {
                     return Ok(other);
                 }
@@ -373,6 +382,29 @@ impl<'de, R: Read<'de>> Deserializer<R> {
         Ok(())
     }
 
+    /// patch(spa): reads an unquoted string into `scratch`, stopping at whitespace or a
+    /// structural character.
+    fn parse_bare(&mut self) -> Result<&str> {
+        self.scratch.clear();
+        while let Some(b) = tri!(self.peek()) {
+            if is_bare_end(b) {
+                break;
+            }
+            self.scratch.push(b);
+            self.eat_char();
+        }
+        std::str::from_utf8(&self.scratch)
+            .map_err(|_| self.error(ErrorCode::InvalidUnicodeCodePoint))
+    }
+
+    /// patch(spa): a top-level object may omit its enclosing braces.
+    fn visit_implicit_map<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_map(MapAccess::implicit(self))
+    }
+
     fn parse_integer(&mut self, positive: bool) -> Result<ParserNumber> {
         let next = match tri!(self.next_char()) {
             Some(b) => b,
@@ -814,26 +846,14 @@ impl<'de, R: Read<'de>> de::Deserializer<'de> for &mut Deserializer<R> {
         };
 
         let value = match peek {
-            b'n' => {
-                self.eat_char();
-                tri!(self.parse_ident(b"ull"));
-                visitor.visit_unit()
-            }
-            b't' => {
-                self.eat_char();
-                tri!(self.parse_ident(b"rue"));
-                visitor.visit_bool(true)
-            }
-            b'f' => {
-                self.eat_char();
-                tri!(self.parse_ident(b"alse"));
-                visitor.visit_bool(false)
-            }
-            b'-' => {
-                self.eat_char();
-                tri!(self.parse_any_number(false)).visit(visitor)
+            // patch(spa): a word is only a number if all of it is, e.g. `256/48000` is a string
+            b'-' | b'0'..=b'9' => {
+                let word = tri!(self.parse_bare());
+                match parse_number_word(word) {
+                    Some(number) => number.visit(visitor),
+                    None => visitor.visit_str(word),
+                }
             }
-            b'0'..=b'9' => tri!(self.parse_any_number(true)).visit(visitor),
             b'"' => {
                 self.eat_char();
                 self.scratch.clear();
@@ -864,6 +884,13 @@ impl<'de, R: Read<'de>> de::Deserializer<'de> for &mut Deserializer<R> {
                     (Err(err), _) | (_, Err(err)) => Err(err),
                 }
             }
+            // patch(spa): unquoted strings, of which `null`, `true` and `false` are keywords
+            b if !is_bare_end(b) => match tri!(self.parse_bare()) {
+                "null" => visitor.visit_unit(),
+                "true" => visitor.visit_bool(true),
+                "false" => visitor.visit_bool(false),
+                s => visitor.visit_str(s),
+            },
             _ => Err(self.peek_error(ErrorCode::ExpectedSomeValue)),
         };
 
@@ -950,6 +977,10 @@ impl<'de, R: Read<'de>> de::Deserializer<'de> for &mut Deserializer<R> {
                     Reference::Copied(s) => visitor.visit_str(s),
                 }
             }
+            // patch(spa): unquoted strings
+            b if !is_bare_end(b) && !matches!(b, b'-' | b'0'..=b'9') => {
+                visitor.visit_str(tri!(self.parse_bare()))
+            }
             _ => Err(self.peek_invalid_type(&visitor)),
         };
 
@@ -1084,11 +1115,11 @@ impl<'de, R: Read<'de>> de::Deserializer<'de> for &mut Deserializer<R> {
         V: de::Visitor<'de>,
     {
         match tri!(self.parse_whitespace()) {
-            Some(b'n') => {
-                self.eat_char();
-                tri!(self.parse_ident(b"ull"));
-                visitor.visit_none()
-            }
+            // patch(spa): an unquoted string starting with `n` is not necessarily `null`
+            Some(b'n') => match tri!(self.parse_bare()) {
+                "null" => visitor.visit_none(),
+                s => visitor.visit_some(de::value::StrDeserializer::<Error>::new(s)),
+            },
             _ => visitor.visit_some(self),
         }
     }
@@ -1210,6 +1241,7 @@ impl<'de, R: Read<'de>> de::Deserializer<'de> for &mut Deserializer<R> {
                     (Err(err), _) | (_, Err(err)) => Err(err),
                 }
             }
+            _ if self.remaining_depth == ROOT_DEPTH => self.visit_implicit_map(visitor),
             _ => Err(self.peek_invalid_type(&visitor)),
         };
 
@@ -1258,6 +1290,7 @@ impl<'de, R: Read<'de>> de::Deserializer<'de> for &mut Deserializer<R> {
                     (Err(err), _) | (_, Err(err)) => Err(err),
                 }
             }
+            _ if self.remaining_depth == ROOT_DEPTH => self.visit_implicit_map(visitor),
             _ => Err(self.peek_invalid_type(&visitor)),
         };
 
@@ -1319,6 +1352,26 @@ impl<'de, R: Read<'de>> de::Deserializer<'de> for &mut Deserializer<R> {
     }
 }
 
+// patch(spa): parse an unquoted word as a number, if all of it is one
+fn parse_number_word(word: &str) -> Option<ParserNumber> {
+    let mut de = Deserializer::from_str(word);
+    let number = if word.starts_with('-') {
+        de.eat_char();
+        de.parse_any_number(false)
+    } else {
+        de.parse_any_number(true)
+    };
+    number.ok().filter(|_| de.read.byte_offset() == word.len())
+}
+
+// patch(spa): bytes that terminate an unquoted string
+fn is_bare_end(b: u8) -> bool {
+    matches!(
+        b,
+        b' ' | b'\n' | b'\t' | b'\r' | b'"' | b'#' | b':' | b',' | b'=' | b'[' | b']' | b'{' | b'}'
+    )
+}
+
 struct SeqAccess<'a, R: 'a> {
     de: &'a mut Deserializer<R>,
     first: bool,
@@ -1378,11 +1431,20 @@ impl<'de, 'a, R: Read<'de> + 'a> de::SeqAccess<'de> for SeqAccess<'a, R> {
 
 struct MapAccess<'a, R: 'a> {
     de: &'a mut Deserializer<R>,
+    // patch(spa): the object has no braces and ends at EOF
+    implicit: bool,
 }
 
 impl<'a, R: 'a> MapAccess<'a, R> {
     fn new(de: &'a mut Deserializer<R>) -> Self {
-        MapAccess { de }
+        MapAccess {
+            de,
+            implicit: false,
+        }
+    }
+
+    fn implicit(de: &'a mut Deserializer<R>) -> Self {
+        MapAccess { de, implicit: true }
     }
 }
 
@@ -1396,12 +1458,13 @@ impl<'de, 'a, R: Read<'de> + 'a> de::MapAccess<'de> for MapAccess<'a, R> {
         fn has_next_key<'de, 'a, R: Read<'de> + 'a>(map: &mut MapAccess<'a, R>) -> Result<bool> {
             let peek = match tri!(map.de.parse_whitespace()) {
                 Some(b) => b,
+                None if map.implicit => return Ok(false),
                 None => {
                     return Err(map.de.peek_error(ErrorCode::EofWhileParsingObject));
                 }
             };
 
-            if peek == b'}' {
+            if peek == b'}' && !map.implicit {
                 Ok(false)
             } else if peek == b',' {
                 map.de.eat_char();
@@ -103,6 +103,52 @@ fn scratch() {
     assert_eq!(v, v2);
 }
 
+#[test]
+fn test_de_pipewire_syntax() {
+    let v: Map<String, Value> = spa_json::from_str(
+        r#"
+        # comments run to the end of the line
+        context.properties = { log.level = 0 node.latency = 256/48000 }
+        context.modules = [
+            { name = libpipewire-module-rt args = { nice.level = -11 } flags = [ ifexists nofail ] }
+            { name = "quoted" enabled = true none = null }
+        ]
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        Value::Object(v),
+        json!({
+            "context.properties": { "log.level": 0, "node.latency": "256/48000" },
+            "context.modules": [
+                {
+                    "name": "libpipewire-module-rt",
+                    "args": { "nice.level": -11 },
+                    "flags": ["ifexists", "nofail"],
+                },
+                { "name": "quoted", "enabled": true, "none": null },
+            ],
+        })
+    );
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Props {
+        #[serde(rename = "node.name")]
+        node_name: Option<String>,
+        #[serde(rename = "media.class")]
+        media_class: Option<String>,
+    }
+
+    assert_eq!(
+        spa_json::from_str::<Props>("node.name = null media.class = nullsink").unwrap(),
+        Props {
+            node_name: None,
+            media_class: Some("nullsink".to_string()),
+        }
+    );
+}
+
 proptest! {
     #[test]
     fn test_roundtrip_pretty(value in arb_v()) {